@@ -2,7 +2,7 @@ extern crate safe_bindgen;
 #[macro_use]
 extern crate clap;
 
-use safe_bindgen::{Bindgen, LangCSharp, LangJava};
+use safe_bindgen::{Bindgen, LangCSharp, LangJava, LangKotlin};
 use std::collections::HashMap;
 
 fn main() {
@@ -27,7 +27,7 @@ fn main() {
                 .takes_value(true)
                 .required(true)
                 .help("target language")
-                .possible_values(&["csharp", "java"]),
+                .possible_values(&["csharp", "java", "kotlin"]),
         )
         .arg(
             clap::Arg::with_name("LIB")
@@ -62,36 +62,47 @@ fn main() {
             bindgen.run_build(&mut lang, &output_dir)
         }
         "java" => {
-            let mut type_map = HashMap::new();
-            type_map.insert("XorNameArray", "byte[]");
-            type_map.insert("SignSecretKey", "byte[]");
-            type_map.insert("SignPublicKey", "byte[]");
-            type_map.insert("SymSecretKey", "byte[]");
-            type_map.insert("SymNonce", "byte[]");
-            type_map.insert("AsymPublicKey", "byte[]");
-            type_map.insert("AsymSecretKey", "byte[]");
-            type_map.insert("AsymNonce", "byte[]");
-            type_map.insert("CipherOptHandle", "long");
-            type_map.insert("EncryptPubKeyHandle", "long");
-            type_map.insert("EncryptSecKeyHandle", "long");
-            type_map.insert("MDataEntriesHandle", "long");
-            type_map.insert("MDataEntryActionsHandle", "long");
-            type_map.insert("MDataPermissionsHandle", "long");
-            type_map.insert("SelfEncryptorReaderHandle", "long");
-            type_map.insert("SelfEncryptorWriterHandle", "long");
-            type_map.insert("SEReaderHandle", "long");
-            type_map.insert("SEWriterHandle", "long");
-            type_map.insert("SignPubKeyHandle", "long");
-            type_map.insert("SignSecKeyHandle", "long");
-            type_map.insert("FileContextHandle", "long");
-            type_map.insert("App", "long");
-            type_map.insert("Authenticator", "long");
-
-            let mut java = LangJava::new(type_map);
+            let mut java = LangJava::new(java_type_map());
             java.set_namespace(format!("net.maidsafe.{}", "safe_app")); // lib
             java.set_lib_name(lib);
             bindgen.run_build(&mut java, &output_dir);
         }
+        "kotlin" => {
+            let mut kotlin = LangKotlin::new(java_type_map());
+            kotlin.set_namespace(format!("net.maidsafe.{}", "safe_app")); // lib
+            kotlin.set_lib_name(lib);
+            bindgen.run_build(&mut kotlin, &output_dir);
+        }
         _ => unreachable!(),
     }
 }
+
+/// Native types mapped onto JVM types, shared by the Java and Kotlin backends.
+fn java_type_map() -> HashMap<&'static str, &'static str> {
+    let mut type_map = HashMap::new();
+    type_map.insert("XorNameArray", "byte[]");
+    type_map.insert("SignSecretKey", "byte[]");
+    type_map.insert("SignPublicKey", "byte[]");
+    type_map.insert("SymSecretKey", "byte[]");
+    type_map.insert("SymNonce", "byte[]");
+    type_map.insert("AsymPublicKey", "byte[]");
+    type_map.insert("AsymSecretKey", "byte[]");
+    type_map.insert("AsymNonce", "byte[]");
+    type_map.insert("CipherOptHandle", "long");
+    type_map.insert("EncryptPubKeyHandle", "long");
+    type_map.insert("EncryptSecKeyHandle", "long");
+    type_map.insert("MDataEntriesHandle", "long");
+    type_map.insert("MDataEntryActionsHandle", "long");
+    type_map.insert("MDataPermissionsHandle", "long");
+    type_map.insert("SelfEncryptorReaderHandle", "long");
+    type_map.insert("SelfEncryptorWriterHandle", "long");
+    type_map.insert("SEReaderHandle", "long");
+    type_map.insert("SEWriterHandle", "long");
+    type_map.insert("SignPubKeyHandle", "long");
+    type_map.insert("SignSecKeyHandle", "long");
+    type_map.insert("FileContextHandle", "long");
+    type_map.insert("App", "long");
+    type_map.insert("Authenticator", "long");
+
+    type_map
+}
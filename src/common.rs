@@ -13,11 +13,119 @@ pub enum FilterMode {
     Whitelist,
 }
 
+/// Conventions of the callbacks through which asynchronous functions report their
+/// results. The default follows `ffi_utils`: callbacks take `user_data: *mut c_void`
+/// followed by `result: *const FfiResult`, where `FfiResult` is
+/// `{ error_code: i32, description: *const c_char }`.
+#[derive(Clone, Debug)]
+pub struct CallbackConvention {
+    /// Name of the `*mut c_void` argument passing the user data to functions and back
+    /// to their callbacks.
+    pub user_data_name: String,
+    /// Position of the user data among the callback arguments.
+    pub user_data_index: usize,
+    /// Name of the callback argument pointing to the result.
+    pub result_name: String,
+    /// Position of the result among the callback arguments.
+    pub result_index: usize,
+    /// Name of the struct describing the result.
+    pub result_type: String,
+    /// Name of the `i32` field of the result holding the error code (zero on success).
+    pub error_code_field: String,
+    /// Name of the `*const c_char` field of the result holding the error description.
+    pub description_field: String,
+    /// Whether the description field precedes the error code in the result struct.
+    pub description_first: bool,
+}
+
+impl Default for CallbackConvention {
+    fn default() -> Self {
+        CallbackConvention {
+            user_data_name: "user_data".to_string(),
+            user_data_index: 0,
+            result_name: "result".to_string(),
+            result_index: 1,
+            result_type: "FfiResult".to_string(),
+            error_code_field: "error_code".to_string(),
+            description_field: "description".to_string(),
+            description_first: false,
+        }
+    }
+}
+
+impl CallbackConvention {
+    /// Check the function argument is the user data (e.g. `user_data: *mut c_void`).
+    pub fn is_user_data_arg(&self, arg: &ast::Arg) -> bool {
+        pprust::pat_to_string(&*arg.pat) == self.user_data_name &&
+            pprust::ty_to_string(&*arg.ty) == "*mut c_void"
+    }
+
+    /// Check the function argument is the result (e.g. `result: *const FfiResult`).
+    pub fn is_result_arg(&self, arg: &ast::Arg) -> bool {
+        pprust::pat_to_string(&*arg.pat) == self.result_name &&
+            pprust::ty_to_string(&*arg.ty) == format!("*const {}", self.result_type)
+    }
+}
+
+/// Conventions of asynchronous functions which can be cancelled. Such functions take
+/// a pointer to the token type, which is created, cancelled and released through
+/// the given functions:
+///
+/// ```ignore
+/// extern "C" fn cancel_token_new() -> *mut CancelToken;
+/// extern "C" fn cancel_token_cancel(token: *const CancelToken);
+/// extern "C" fn cancel_token_free(token: *mut CancelToken);
+/// ```
+///
+/// The token is released after the result callback is called, so the native code
+/// can keep using it until then.
+#[derive(Clone, Debug)]
+pub struct CancelConvention {
+    /// Name of the token type.
+    pub token_type: String,
+    /// Function creating a new token.
+    pub new_function: String,
+    /// Function requesting cancellation of the operations using the token.
+    pub cancel_function: String,
+    /// Function releasing the token.
+    pub free_function: String,
+}
+
+impl Default for CancelConvention {
+    fn default() -> Self {
+        CancelConvention {
+            token_type: "CancelToken".to_string(),
+            new_function: "cancel_token_new".to_string(),
+            cancel_function: "cancel_token_cancel".to_string(),
+            free_function: "cancel_token_free".to_string(),
+        }
+    }
+}
+
+impl CancelConvention {
+    /// Check the type is a pointer to the token (e.g. `*const CancelToken`).
+    pub fn is_token_ty(&self, ty: &ast::Ty) -> bool {
+        match ty.node {
+            ast::TyKind::Ptr(ref ptr) => pprust::ty_to_string(&ptr.ty) == self.token_type,
+            _ => false,
+        }
+    }
+
+    /// Check the function is one of the functions managing the tokens.
+    pub fn is_token_function(&self, name: &str) -> bool {
+        name == self.new_function || name == self.cancel_function || name == self.free_function
+    }
+}
+
 /// Outputs several files as a result of an AST transformation.
 pub type Outputs = HashMap<PathBuf, String>;
 
 /// Target language support
 pub trait Lang {
+    /// Set path of the module whose items are going to be parsed next (e.g. `["ffi", "mdata"]`).
+    /// The path is empty for the top-level module.
+    fn set_current_module(&mut self, _path: &[String]) {}
+
     /// Convert a Rust constant (`pub const NAME: Type = value;`) into a target
     /// language constant.
     fn parse_const(&mut self, _item: &ast::Item, _outputs: &mut Outputs) -> Result<(), Error> {
@@ -68,18 +176,6 @@ pub fn check_no_mangle(attr: &ast::Attribute) -> bool {
     }
 }
 
-/// Check the function argument is `user_data: *mut c_void`
-pub fn is_user_data_arg(arg: &ast::Arg) -> bool {
-    pprust::pat_to_string(&*arg.pat) == "user_data" &&
-        pprust::ty_to_string(&*arg.ty) == "*mut c_void"
-}
-
-/// Check the function argument is `result: *const FfiResult`
-pub fn is_result_arg(arg: &ast::Arg) -> bool {
-    pprust::pat_to_string(&*arg.pat) == "result" &&
-        pprust::ty_to_string(&*arg.ty) == "*const FfiResult"
-}
-
 /// Check the function argument is a length argument for a *const u8 pointer
 pub fn is_ptr_len_arg(ty: &ast::Ty, arg_name: &str) -> bool {
     pprust::ty_to_string(ty) == "usize" &&
@@ -89,9 +185,13 @@ pub fn is_ptr_len_arg(ty: &ast::Ty, arg_name: &str) -> bool {
 
 /// Detect array ptrs and skip the length args - e.g. for a case of
 /// `ptr: *const u8, ptr_len: usize` we're going to skip the `len` part.
-pub fn is_array_arg(arg: &ast::Arg, next_arg: Option<&ast::Arg>) -> bool {
+pub fn is_array_arg(
+    arg: &ast::Arg,
+    next_arg: Option<&ast::Arg>,
+    convention: &CallbackConvention,
+) -> bool {
     if let ast::TyKind::Ptr(..) = arg.ty.node {
-        !is_result_arg(arg) &&
+        !convention.is_result_arg(arg) &&
             next_arg
                 .map(|arg| {
                     is_ptr_len_arg(&*arg.ty, &pprust::pat_to_string(&*arg.pat))
@@ -147,6 +247,136 @@ pub fn check_repr_c(attr: &ast::Attribute) -> bool {
     }
 }
 
+/// Check the attribute is `#[bindgen(nullable)]`, marking a pointer struct field
+/// which can be null.
+pub fn check_nullable(attr: &ast::Attribute) -> bool {
+    bindgen_items(attr, "nullable").any(|item| match item.node {
+        ast::MetaItemKind::Word => true,
+        _ => false,
+    })
+}
+
+/// Names of the pointer parameters which can be null, listed in the
+/// `#[bindgen(nullable(name, ...))]` attribute of the function.
+pub fn nullable_params(attrs: &[ast::Attribute]) -> Vec<String> {
+    bindgen_params(attrs, "nullable")
+}
+
+/// Names of the out-parameters of the function: `*mut *mut T` parameters, `*mut T`
+/// parameters prefixed with `o_` and those listed in the `#[bindgen(out(name, ...))]`
+/// attribute of the function.
+pub fn out_params(decl: &ast::FnDecl, attrs: &[ast::Attribute]) -> Vec<String> {
+    let mut names = bindgen_params(attrs, "out");
+
+    for arg in &decl.inputs {
+        let name = pprust::pat_to_string(&*arg.pat);
+        if is_out_arg(arg) && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+/// Check the function argument is an out-parameter by convention, i.e. it is
+/// either `*mut *mut T` or `*mut T` prefixed with `o_` (e.g. `o_len: *mut usize`).
+pub fn is_out_arg(arg: &ast::Arg) -> bool {
+    match arg.ty.node {
+        ast::TyKind::Ptr(ast::MutTy { ref ty, mutbl: ast::Mutability::Mutable }) => {
+            match ty.node {
+                ast::TyKind::Ptr(ast::MutTy { mutbl: ast::Mutability::Mutable, .. }) => true,
+                _ => {
+                    pprust::pat_to_string(&*arg.pat).starts_with("o_") &&
+                        pprust::ty_to_string(ty) != "c_void"
+                }
+            }
+        }
+        _ => false,
+    }
+}
+
+// Names listed in the `#[bindgen(<name>(a, b, ...))]` attributes.
+fn bindgen_params(attrs: &[ast::Attribute], name: &str) -> Vec<String> {
+    let mut names = Vec::new();
+
+    for attr in attrs {
+        for item in bindgen_items(attr, name) {
+            if let ast::MetaItemKind::List(ref params) = item.node {
+                for param in params {
+                    if let ast::NestedMetaItemKind::MetaItem(ref param) = param.node {
+                        names.push(param.name.as_str().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    names
+}
+
+fn bindgen_items<'a>(
+    attr: &'a ast::Attribute,
+    name: &'a str,
+) -> Box<Iterator<Item = &'a ast::MetaItem> + 'a> {
+    match attr.value.node {
+        ast::MetaItemKind::List(ref items) if attr.name() == "bindgen" => {
+            Box::new(items.iter().filter_map(move |item| match item.node {
+                ast::NestedMetaItemKind::MetaItem(ref item) if item.name == name => Some(item),
+                _ => None,
+            }))
+        }
+        _ => Box::new(None.into_iter()),
+    }
+}
+
+/// If the type is the generic type of the given name with single type parameter
+/// (e.g. `Option<T>`), returns the parameter.
+pub fn generic_param<'a>(ty: &'a ast::Ty, name: &str) -> Option<&'a ast::Ty> {
+    let segment = match ty.node {
+        ast::TyKind::Path(None, ref path) => path.segments.last(),
+        _ => None,
+    };
+
+    match segment {
+        Some(segment) if segment.identifier.name == name => {
+            match segment.parameters {
+                Some(ref parameters) => {
+                    match **parameters {
+                        ast::PathParameters::AngleBracketed(ref data) if data.types.len() == 1 => {
+                            Some(&data.types[0])
+                        }
+                        _ => None,
+                    }
+                }
+                None => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// If the type is a pointer which can be null (`Option<&T>` or `Option<NonNull<T>>`),
+/// returns the type it points to.
+pub fn nullable_pointee(ty: &ast::Ty) -> Option<&ast::Ty> {
+    let ty = match generic_param(ty, "Option") {
+        Some(ty) => ty,
+        None => return None,
+    };
+
+    match ty.node {
+        ast::TyKind::Rptr(_, ast::MutTy { ref ty, .. }) => Some(ty),
+        _ => generic_param(ty, "NonNull"),
+    }
+}
+
+/// If the type is `Option<extern "C" fn(..)>`, returns the function type.
+pub fn nullable_fn(ty: &ast::Ty) -> Option<&ast::BareFnTy> {
+    match generic_param(ty, "Option").map(|ty| &ty.node) {
+        Some(&ast::TyKind::BareFn(ref bare_fn)) => Some(&**bare_fn),
+        _ => None,
+    }
+}
+
 /// If the attribute is  a docstring, indent it the required amount and return it.
 pub fn retrieve_docstring(attr: &ast::Attribute, prepend: &str) -> Option<String> {
     match attr.value.node {
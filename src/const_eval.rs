@@ -0,0 +1,312 @@
+//! Evaluation of constant integer expressions, shared by all target languages.
+
+use std::collections::HashMap;
+use syntax::ast;
+use syntax::print::pprust;
+
+/// Evaluates constant integer expressions like `32 + NONCE_LEN`, `1 << 3` or
+/// `size_of::<u64>()`, resolving references to the constants defined so far.
+///
+/// Expressions of a known integer type (e.g. `!0` in a `u32` constant, or
+/// `x as u8`) are truncated to that type. Values of `u64` expressions above
+/// `i64::MAX` are returned as their two's complement bit pattern.
+#[derive(Default)]
+pub struct ConstEvaluator {
+    consts: HashMap<String, (ast::Ty, ast::Expr)>,
+}
+
+impl ConstEvaluator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Remember the type and value of the constant, so it can be referenced by
+    /// other expressions.
+    pub fn define<T: Into<String>>(&mut self, name: T, ty: &ast::Ty, expr: &ast::Expr) {
+        let _ = self.consts.insert(name.into(), (ty.clone(), expr.clone()));
+    }
+
+    /// Evaluate the value of the constant with the given name.
+    pub fn eval_const(&self, name: &str) -> Option<i64> {
+        self.eval_const_at_depth(name, 0)
+    }
+
+    /// Evaluate the expression, returning `None` if it's not a constant integer expression
+    /// or if it references unknown constants.
+    pub fn eval(&self, expr: &ast::Expr) -> Option<i64> {
+        self.eval_at_depth(expr, IntType::Any, 0)
+    }
+
+    /// Evaluate the expression as a value of the given type, e.g. the declared
+    /// type of a constant.
+    pub fn eval_as(&self, expr: &ast::Expr, ty: &ast::Ty) -> Option<i64> {
+        self.eval_at_depth(expr, IntType::of(ty), 0)
+    }
+
+    fn eval_const_at_depth(&self, name: &str, depth: usize) -> Option<i64> {
+        // Guard against cyclic definitions.
+        if depth > self.consts.len() {
+            return None;
+        }
+
+        self.consts.get(name).and_then(|&(ref ty, ref expr)| {
+            self.eval_at_depth(expr, IntType::of(ty), depth + 1)
+        })
+    }
+
+    fn eval_at_depth(&self, expr: &ast::Expr, ty: IntType, depth: usize) -> Option<i64> {
+        let value = match expr.node {
+            ast::ExprKind::Lit(ref lit) => {
+                match lit.node {
+                    ast::LitKind::Int(value, _) => Some(value as i64),
+                    ast::LitKind::Byte(value) => Some(value as i64),
+                    ast::LitKind::Bool(value) => Some(value as i64),
+                    _ => None,
+                }
+            }
+            ast::ExprKind::Path(None, ref path) => {
+                // Currently supports only unqualified constants.
+                if path.segments.len() > 1 || path.segments[0].parameters.is_some() {
+                    None
+                } else {
+                    let name = path.segments[0].identifier.name.as_str();
+                    self.eval_const_at_depth(&name, depth)
+                }
+            }
+            ast::ExprKind::Paren(ref expr) => self.eval_at_depth(expr, ty, depth),
+            ast::ExprKind::Cast(ref expr, ref cast_ty) => {
+                // Casts to other types (e.g. `0 as *const c_char`) keep the value.
+                self.eval_at_depth(expr, IntType::Any, depth).map(|value| {
+                    IntType::of(cast_ty).truncate(value)
+                })
+            }
+            ast::ExprKind::Unary(op, ref expr) => {
+                let value = match self.eval_at_depth(expr, ty, depth) {
+                    Some(value) => value,
+                    None => return None,
+                };
+
+                match op {
+                    // The result of `!` depends on the width of the type.
+                    ast::UnOp::Not if ty == IntType::PointerSized => None,
+                    ast::UnOp::Not => Some(!value),
+                    ast::UnOp::Neg => value.checked_neg(),
+                    ast::UnOp::Deref => None,
+                }
+            }
+            ast::ExprKind::Binary(op, ref left, ref right) => {
+                let left = match self.eval_at_depth(left, ty, depth) {
+                    Some(value) => value,
+                    None => return None,
+                };
+                let right = match self.eval_at_depth(right, ty, depth) {
+                    Some(value) => value,
+                    None => return None,
+                };
+
+                eval_binary(op.node, left, right, ty)
+            }
+            ast::ExprKind::Call(ref fun, ref args) if args.is_empty() => eval_size_of(fun),
+            _ => None,
+        };
+
+        value.map(|value| ty.truncate(value))
+    }
+}
+
+/// Integer type an expression is evaluated as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IntType {
+    /// Type of a fixed width, e.g. `u32`.
+    Fixed { bits: u32, signed: bool },
+    /// `usize` or `isize`, whose width depends on the target.
+    PointerSized,
+    /// Unknown type (e.g. of an array length), evaluated as `i64`.
+    Any,
+}
+
+impl IntType {
+    fn of(ty: &ast::Ty) -> Self {
+        let (bits, signed) = match &*pprust::ty_to_string(ty) {
+            "u8" | "c_uchar" => (8, false),
+            "i8" | "c_schar" => (8, true),
+            "u16" | "c_ushort" => (16, false),
+            "i16" | "c_short" => (16, true),
+            "u32" | "c_uint" => (32, false),
+            "i32" | "c_int" => (32, true),
+            "u64" | "c_ulonglong" => (64, false),
+            "i64" | "c_longlong" => (64, true),
+            "usize" | "isize" => return IntType::PointerSized,
+            _ => return IntType::Any,
+        };
+
+        IntType::Fixed { bits, signed }
+    }
+
+    fn is_unsigned(self) -> bool {
+        match self {
+            IntType::Fixed { signed, .. } => !signed,
+            _ => false,
+        }
+    }
+
+    /// Truncate the value to the width of the type, the same way `as` does.
+    fn truncate(self, value: i64) -> i64 {
+        match self {
+            IntType::Fixed { bits, signed } if bits < 64 => {
+                let value = value & ((1 << bits) - 1);
+                if signed && value >= 1 << (bits - 1) {
+                    value - (1 << bits)
+                } else {
+                    value
+                }
+            }
+            _ => value,
+        }
+    }
+}
+
+fn eval_binary(op: ast::BinOpKind, left: i64, right: i64, ty: IntType) -> Option<i64> {
+    if ty.is_unsigned() {
+        let (left, right) = (left as u64, right as u64);
+        let value = match op {
+            ast::BinOpKind::Add => left.checked_add(right),
+            ast::BinOpKind::Sub => left.checked_sub(right),
+            ast::BinOpKind::Mul => left.checked_mul(right),
+            ast::BinOpKind::Div => left.checked_div(right),
+            ast::BinOpKind::Rem => left.checked_rem(right),
+            ast::BinOpKind::BitAnd => Some(left & right),
+            ast::BinOpKind::BitOr => Some(left | right),
+            ast::BinOpKind::BitXor => Some(left ^ right),
+            ast::BinOpKind::Shl => left.checked_shl(right as u32),
+            ast::BinOpKind::Shr => left.checked_shr(right as u32),
+            _ => None,
+        };
+
+        return value.map(|value| value as i64);
+    }
+
+    match op {
+        ast::BinOpKind::Add => left.checked_add(right),
+        ast::BinOpKind::Sub => left.checked_sub(right),
+        ast::BinOpKind::Mul => left.checked_mul(right),
+        ast::BinOpKind::Div => left.checked_div(right),
+        ast::BinOpKind::Rem => left.checked_rem(right),
+        ast::BinOpKind::BitAnd => Some(left & right),
+        ast::BinOpKind::BitOr => Some(left | right),
+        ast::BinOpKind::BitXor => Some(left ^ right),
+        ast::BinOpKind::Shl => left.checked_shl(right as u32),
+        ast::BinOpKind::Shr => left.checked_shr(right as u32),
+        _ => None,
+    }
+}
+
+/// Evaluate `size_of::<T>()` (optionally qualified, e.g. `mem::size_of::<T>()`)
+/// for primitive types. Types whose size depends on the target are not
+/// supported: pointers, `usize` and `isize` (4 bytes on 32-bit targets), and
+/// `c_long` and `c_ulong` (also 4 bytes on 64-bit Windows).
+fn eval_size_of(fun: &ast::Expr) -> Option<i64> {
+    let path = match fun.node {
+        ast::ExprKind::Path(None, ref path) => path,
+        _ => return None,
+    };
+
+    let segment = match path.segments.last() {
+        Some(segment) => segment,
+        None => return None,
+    };
+
+    if &*segment.identifier.name.as_str() != "size_of" {
+        return None;
+    }
+
+    let ty = match segment.parameters {
+        Some(ref parameters) => {
+            match **parameters {
+                ast::PathParameters::AngleBracketed(ref data) if data.types.len() == 1 => {
+                    &data.types[0]
+                }
+                _ => return None,
+            }
+        }
+        None => return None,
+    };
+
+    if let ast::TyKind::Ptr(..) = ty.node {
+        return None;
+    }
+
+    let size = match &*pprust::ty_to_string(ty) {
+        "u8" | "i8" | "bool" | "c_char" | "c_uchar" | "c_schar" => 1,
+        "u16" | "i16" | "c_short" | "c_ushort" => 2,
+        "u32" | "i32" | "f32" | "char" | "c_int" | "c_uint" | "c_float" => 4,
+        "u64" | "i64" | "f64" | "c_double" | "c_longlong" | "c_ulonglong" => 8,
+        _ => return None,
+    };
+
+    Some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntax::parse::{self, ParseSess};
+
+    fn expr(source: &str) -> ast::Expr {
+        let parse_sess = ParseSess::new();
+        let expr = unwrap!(parse::parse_expr_from_source_str(
+            "dummy.rs".to_owned(),
+            source.to_owned(),
+            &parse_sess,
+        ));
+
+        (*expr).clone()
+    }
+
+    fn ty(source: &str) -> ast::Ty {
+        match expr(&format!("0 as {}", source)).node {
+            ast::ExprKind::Cast(_, ref ty) => (**ty).clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn eval() {
+        let mut evaluator = ConstEvaluator::new();
+        evaluator.define("HASH_LEN", &ty("usize"), &expr("32"));
+        evaluator.define("NONCE_LEN", &ty("usize"), &expr("size_of::<u64>() * 3"));
+        evaluator.define("KEY_LEN", &ty("usize"), &expr("HASH_LEN + NONCE_LEN"));
+        evaluator.define("CYCLE", &ty("usize"), &expr("CYCLE + 1"));
+
+        assert_eq!(evaluator.eval(&expr("(1 << 4) | 1")), Some(17));
+        assert_eq!(evaluator.eval(&expr("-(1 << 2) as i32")), Some(-4));
+        assert_eq!(evaluator.eval(&expr("2 * HASH_LEN")), Some(64));
+        assert_eq!(evaluator.eval(&expr("mem::size_of::<u16>()")), Some(2));
+        assert_eq!(evaluator.eval_const("KEY_LEN"), Some(56));
+
+        assert_eq!(evaluator.eval(&expr("UNKNOWN + 1")), None);
+        assert_eq!(evaluator.eval(&expr("size_of::<MyStruct>()")), None);
+        assert_eq!(evaluator.eval(&expr("size_of::<c_ulong>()")), None);
+        assert_eq!(evaluator.eval(&expr("size_of::<usize>()")), None);
+        assert_eq!(evaluator.eval(&expr("mem::size_of::<*const u8>()")), None);
+        assert_eq!(evaluator.eval(&expr("\"string\"")), None);
+        assert_eq!(evaluator.eval_const("CYCLE"), None);
+    }
+    #[test]
+    fn eval_as_type() {
+        let mut evaluator = ConstEvaluator::new();
+        evaluator.define("ALL", &ty("u32"), &expr("!0"));
+        evaluator.define("LOW", &ty("u8"), &expr("ALL as u8"));
+
+        assert_eq!(evaluator.eval(&expr("!0")), Some(-1));
+        assert_eq!(evaluator.eval_const("ALL"), Some(0xffff_ffff));
+        assert_eq!(evaluator.eval_const("LOW"), Some(0xff));
+        assert_eq!(evaluator.eval_as(&expr("!0 >> 4"), &ty("u32")), Some(0x0fff_ffff));
+        assert_eq!(evaluator.eval_as(&expr("!0"), &ty("i8")), Some(-1));
+        assert_eq!(evaluator.eval_as(&expr("!1"), &ty("u16")), Some(0xfffe));
+        assert_eq!(evaluator.eval_as(&expr("!0 >> 60"), &ty("u64")), Some(15));
+        assert_eq!(evaluator.eval_as(&expr("-1 as u8"), &ty("i32")), Some(255));
+        assert_eq!(evaluator.eval_as(&expr("300 as u8 as i8"), &ty("i32")), Some(44));
+        assert_eq!(evaluator.eval_as(&expr("!0"), &ty("usize")), None);
+    }
+}
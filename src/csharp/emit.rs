@@ -1,10 +1,11 @@
 //! Utilities for emiting fragments of the target language code.
 
-use super::Context;
+use super::{Alignment, Context, StringEncoding, struct_name};
 use super::intermediate::*;
 use inflector::Inflector;
 use output::IndentedWriter;
 use std::fmt::Write;
+use syntax::ast;
 
 macro_rules! emit {
     ($writer:expr, $($arg:tt)*) => {
@@ -12,6 +13,53 @@ macro_rules! emit {
     }
 }
 
+/// Signature of a wrapper function, as declared by the interface.
+pub struct Signature {
+    pub return_type: String,
+    pub name: String,
+    /// Types and names of the parameters.
+    pub params: Vec<(String, String)>,
+    /// Whether the last parameter is the optional `CancellationToken`.
+    pub cancellable: bool,
+}
+
+pub fn wrapper_function_signature(context: &Context, name: &str, fun: &Function) -> Signature {
+    let mut writer = IndentedWriter::new(0);
+    let mut name = name.to_pascal_case();
+
+    if let Some(callback) = extract_task_callback(&fun.inputs) {
+        emit_task(&mut writer, context, &callback.inputs);
+        name.push_str("Async");
+    } else if returns_out_params(fun) {
+        emit_out_return_type(&mut writer, context, fun);
+    } else if returns_error_code(context, fun) {
+        emit!(writer, "void");
+    } else {
+        emit_type(&mut writer, context, &fun.output, Mode::WrapperFunc);
+    }
+
+    let progress = has_progress_callbacks(&fun.inputs);
+    let skip_out = returns_out_params(fun);
+    let cancellable = is_cancellable(context, fun);
+    let mut params =
+        wrapper_function_params(context, &fun.inputs, skip_out, progress, cancellable);
+
+    // The native token is replaced by the standard one, passed last as it's optional.
+    if cancellable {
+        params.push((
+            "CancellationToken".to_string(),
+            "cancellationToken".to_string(),
+        ));
+    }
+
+    Signature {
+        return_type: writer.into_inner(),
+        name,
+        params,
+        cancellable,
+    }
+}
+
 pub fn emit_wrapper_function_decl(
     writer: &mut IndentedWriter,
     context: &Context,
@@ -19,19 +67,29 @@ pub fn emit_wrapper_function_decl(
     name: &str,
     fun: &Function,
 ) {
+    let signature = wrapper_function_signature(context, name, fun);
+    emit_signature(writer, modifiers, &signature);
+}
+
+fn emit_signature(writer: &mut IndentedWriter, modifiers: &str, signature: &Signature) {
     if !modifiers.is_empty() {
         emit!(writer, "{} ", modifiers);
     }
 
-    if let Some(callback) = extract_first_callback(&fun.inputs) {
-        emit_task(writer, context, &callback.inputs);
-        emit!(writer, " {}Async(", name.to_pascal_case());
-    } else {
-        emit_type(writer, context, &fun.output, Mode::WrapperFunc);
-        emit!(writer, " {}(", name.to_pascal_case());
+    emit!(writer, "{} {}(", signature.return_type, signature.name);
+
+    for (index, &(ref ty, ref name)) in signature.params.iter().enumerate() {
+        if index > 0 {
+            emit!(writer, ", ");
+        }
+
+        emit!(writer, "{} {}", ty, name);
+    }
+
+    if signature.cancellable {
+        emit!(writer, " = default(CancellationToken)");
     }
 
-    emit_wrapper_function_params(writer, context, &fun.inputs, true);
     emit!(writer, ")");
 }
 
@@ -41,32 +99,104 @@ pub fn emit_wrapper_function(
     name: &str,
     fun: &Function,
 ) {
-    let callback = extract_first_callback(&fun.inputs);
+    let callback = extract_task_callback(&fun.inputs);
+    let progress = has_progress_callbacks(&fun.inputs);
+    let error_code = returns_error_code(context, fun);
     let mut has_return = false;
-    // TODO: make sure this doesn't conflict with any arguments.
+    // TODO: make sure these don't conflict with any arguments.
     let return_name = "ret";
+    let code_name = "code";
+    let token_name = "cancelToken";
+    let cancellable = is_cancellable(context, fun);
 
     emit_wrapper_function_decl(writer, context, "public", name, fun);
     emit!(writer, " {{\n");
     writer.indent();
 
+    // Reject nulls before they reach the native code.
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
+        if is_null_checked(context, ty) {
+            emit!(
+                writer,
+                "if ({0} == null) throw new ArgumentNullException(nameof({0}));\n",
+                param_name(name, index)
+            );
+        }
+    }
+
     // Convert wrapper structs to native structs.
-    for &(ref name, ref ty) in &fun.inputs {
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
         if context.is_native_type(ty) {
             emit!(writer, "var {0}Native = {0}.ToNative();\n", name);
+        } else if let Some(type_name) = nullable_struct(context, ty) {
+            let name = param_name(name, index);
+            emit!(writer, "var {}Ptr = ", name);
+            emit_struct_to_pointer(writer, context, type_name, &name);
+            emit!(writer, ";\n");
         }
     }
 
+    let pins = blittable_pins(context, &fun.inputs, false);
+    emit_fixed_statements(writer, &pins);
+
+    if cancellable {
+        let convention = context.cancel_convention().expect("cancellation is enabled");
+        emit!(
+            writer,
+            "var {} = {}();\n",
+            token_name,
+            extern_function_name(&convention.new_function)
+        );
+    }
+
     if let Some(callback) = callback {
         emit!(
             writer,
-            "var ({}, userData) = {}.PrepareTask",
+            "var ({}, userData) = {}.{}",
             return_name,
-            &context.utils_section.class
+            &context.utils_section.class,
+            if progress {
+                "PrepareProgressTask"
+            } else {
+                "PrepareTask"
+            }
         );
         emit_task_generic_args(writer, context, &callback.inputs);
-        emit!(writer, "();\n");
+        emit!(writer, "(");
+
+        if progress {
+            let mut index = 0;
+            for &(ref name, ref ty) in &fun.inputs {
+                if let Some(callback) = extract_callback(ty) {
+                    if !is_result_callback(callback) {
+                        if index > 0 {
+                            emit!(writer, ", ");
+                        }
+
+                        emit!(writer, "{}", output_param_name(name, index));
+                        index += 1;
+                    }
+                }
+            }
+        }
+
+        if cancellable {
+            let convention = context.cancel_convention().expect("cancellation is enabled");
+            emit!(
+                writer,
+                "cancellationToken, () => {1}({0}), () => {2}({0})",
+                token_name,
+                extern_function_name(&convention.cancel_function),
+                extern_function_name(&convention.free_function)
+            );
+        }
+
+        emit!(writer, ");\n");
         has_return = true;
+
+        if error_code {
+            emit!(writer, "var {} = ", code_name);
+        }
     } else {
         match fun.output {
             Type::Unit => (),
@@ -79,17 +209,40 @@ pub fn emit_wrapper_function(
 
     emit!(writer, "{}(", extern_function_name(name));
 
+    let fun_name = name;
+    let mut callback_index = 0;
+
     for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
         if index > 0 {
             emit!(writer, ", ");
         }
 
         if let Some(callback) = extract_callback(ty) {
-            emit!(writer, "On");
-            emit_callback_wrapper_name(writer, &callback);
+            if context.blittable {
+                emit!(writer, "&");
+            }
+
+            if progress {
+                emit!(
+                    writer,
+                    "{}",
+                    progress_callback_wrapper_name(fun_name, callback_index)
+                );
+            } else {
+                emit!(writer, "On");
+                emit_callback_wrapper_name(writer, &callback);
+            }
+
+            callback_index += 1;
+        } else if cancellable && context.is_cancel_token(ty) {
+            emit!(writer, "{}", token_name);
         } else {
             let name = param_name(name, index);
 
+            if emit_blittable_arg(writer, context, ty, &name) {
+                continue;
+            }
+
             match *ty {
                 Type::Array(_, ArraySize::Dynamic) => {
                     emit!(writer, "{0}.ToArray(), (ulong) {0}.Count", name)
@@ -97,6 +250,29 @@ pub fn emit_wrapper_function(
                 Type::Pointer(ref ty) => {
                     emit_pointer_use(writer, context, ty, &name.to_camel_case(), Mode::ExternFunc)
                 }
+                Type::Nullable(_) if nullable_struct(context, ty).is_some() => {
+                    emit!(writer, "{}Ptr", name)
+                }
+                Type::Out(ref ty) => {
+                    let name = out_param_name(fun, &fun.inputs[index].0, index);
+
+                    if context.is_native_type(ty) || is_blittable_bool(context, ty) ||
+                        is_string(ty)
+                    {
+                        emit!(writer, "out var {}Native", name)
+                    } else if returns_out_params(fun) {
+                        emit!(writer, "out var {}", name)
+                    } else {
+                        emit!(writer, "out {}", name)
+                    }
+                }
+                Type::Nullable(ref ty) => {
+                    if let Type::Pointer(ref ty) = **ty {
+                        emit_pointer_use(writer, context, ty, &name, Mode::ExternFunc)
+                    } else {
+                        emit!(writer, "{}", name)
+                    }
+                }
                 _ => emit!(writer, "{}", name),
             }
 
@@ -108,17 +284,104 @@ pub fn emit_wrapper_function(
 
     emit!(writer, ");\n");
 
-    // Free the native structs.
-    for &(ref name, ref ty) in &fun.inputs {
+    // Free the native structs, except the vectors taken over by a consuming function.
+    let transfer = context.is_consuming_function(fun_name);
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
         if context.is_native_type(ty) {
-            emit!(writer, "{0}Native.Free();\n", name);
+            let method = struct_name(ty).map_or("Free", |type_name| {
+                free_method(context, type_name, transfer)
+            });
+            emit!(writer, "{0}Native.{1}();\n", name, method);
+        } else if let Some(type_name) = nullable_struct(context, ty) {
+            let name = format!("{}Ptr", param_name(name, index));
+            emit_free_object(writer, context, type_name, &name, transfer);
         }
     }
 
-    if has_return {
+    if error_code {
+        let last_error = extern_function_name(context.last_error_function().unwrap_or_default());
+
+        if callback.is_some() {
+            // The callback is not going to be called, so the task is faulted here and
+            // the user data released.
+            emit!(
+                writer,
+                "if ({0} != 0) {1}.{2}(userData, \
+                 new FfiException({0}, {1}.CopyToString({3}())));\n",
+                code_name,
+                context.utils_section.class,
+                if progress { "FailProgressTask" } else { "FailTask" },
+                last_error
+            );
+        } else {
+            emit!(
+                writer,
+                "if ({0} != 0) throw new FfiException({0}, {1}.CopyToString({2}()));\n",
+                return_name,
+                context.utils_section.class,
+                last_error
+            );
+        }
+    }
+
+    // Convert native structs, strings (and blittable bools) written to out-parameters.
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
+        if let Type::Out(ref ty) = *ty {
+            let name = out_param_name(fun, name, index);
+            let value = match **ty {
+                Type::User(ref type_name) if context.is_native_name(type_name) => {
+                    format!("new {}({}Native)", type_name, name)
+                }
+                Type::String => {
+                    format!("{}.CopyToString({}Native)", context.utils_section.class, name)
+                }
+                Type::Bool if context.blittable => format!("{}Native != 0", name),
+                _ => continue,
+            };
+
+            if returns_out_params(fun) {
+                emit!(writer, "var ");
+            }
+
+            emit!(writer, "{} = {};\n", name, value);
+
+            if let (&Type::String, Some(free)) = (&**ty, context.string_free_function()) {
+                emit!(
+                    writer,
+                    "if ({0}Native != IntPtr.Zero) {1}({0}Native);\n",
+                    name,
+                    extern_function_name(free)
+                );
+            }
+        }
+    }
+
+    if returns_out_params(fun) {
+        let mut values = Vec::new();
+        if has_return && !error_code {
+            values.push(return_name.to_string());
+        }
+
+        for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
+            if let Type::Out(_) = *ty {
+                values.push(out_param_name(fun, name, index));
+            }
+        }
+
+        if values.len() == 1 {
+            emit!(writer, "return {};\n", values[0]);
+        } else {
+            emit!(writer, "return ({});\n", values.join(", "));
+        }
+    } else if has_return && !error_code {
         emit!(writer, "return {};\n", return_name);
     }
 
+    if !pins.is_empty() {
+        writer.unindent();
+        emit!(writer, "}}\n");
+    }
+
     writer.unindent();
     emit!(writer, "}}\n\n");
 }
@@ -131,257 +394,1629 @@ pub fn emit_function_extern_decl(
 ) {
     let name = extern_function_name(native_name);
 
-    emit!(
-        writer,
-        "[DllImport(DllName, EntryPoint = \"{}\")]\n",
-        native_name
-    );
+    emit_dll_import(writer, context, "DllName", native_name);
     emit!(writer, "internal static extern ");
     emit_type(writer, context, &fun.output, Mode::ExternFunc);
     emit!(writer, " {}(", name);
-    emit_native_function_params(writer, context, &fun.inputs);
+    emit_native_function_params(writer, context, &fun.inputs, false);
     emit!(writer, ");\n\n");
 }
 
-pub fn emit_callback_delegate(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
-    emit!(writer, "internal delegate void ");
-    emit_callback_wrapper_name(writer, callback);
-    emit!(writer, "(");
-    emit_callback_params(writer, context, &callback.inputs);
-    emit!(writer, ");\n\n");
+/// Returns whether to generate an overload of the function taking `ReadOnlySpan<T>`
+/// instead of lists and/or passing the result array to a handler as a span.
+pub fn has_span_overload(context: &Context, name: &str, fun: &Function) -> bool {
+    context.is_span_function(name) && !has_progress_callbacks(&fun.inputs) &&
+        !returns_error_code(context, fun) && !is_cancellable(context, fun) &&
+        !fun.inputs.iter().any(|&(_, ref ty)| {
+            context.is_native_type(ty) || is_out_param(ty)
+        }) &&
+        (fun.inputs.iter().any(|&(_, ref ty)| span_elem_type(ty).is_some()) ||
+             extract_span_callback(&fun.inputs).is_some())
 }
 
-pub fn emit_callback_wrapper(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
-    emit!(writer, "#if __IOS__\n");
-    emit!(writer, "[MonoPInvokeCallback(typeof(");
-    emit_callback_wrapper_name(writer, callback);
-    emit!(writer, "))]\n");
-    emit!(writer, "#endif\n");
-
-    emit!(writer, "private static void On");
-    emit_callback_wrapper_name(writer, callback);
-    emit!(writer, "(");
-    emit_callback_params(writer, context, &callback.inputs);
-    emit!(writer, ") {{\n");
-    writer.indent();
-
-    emit!(writer, "{}.CompleteTask(", &context.utils_section.class);
-    emit_args(writer, context, &callback.inputs[0..2], 0, Mode::Callback);
-
-    if callback.inputs.len() > 2 {
-        emit!(writer, ", () => ");
+pub fn span_function_signature(context: &Context, name: &str, fun: &Function) -> Signature {
+    let mut writer = IndentedWriter::new(0);
+    let mut name = name.to_pascal_case();
+    let span_callback = extract_span_callback(&fun.inputs);
 
-        if callback.inputs.len() > 3 {
-            emit!(writer, "(");
+    if let Some(callback) = extract_task_callback(&fun.inputs) {
+        if span_callback.is_some() {
+            emit!(writer, "Task");
+        } else {
+            emit_task(&mut writer, context, &callback.inputs);
         }
+        name.push_str("Async");
+    } else {
+        emit_type(&mut writer, context, &fun.output, Mode::WrapperFunc);
+    }
 
-        emit_args(writer, context, &callback.inputs[2..], 2, Mode::Callback);
+    let mut params = Vec::new();
+    for &(ref name, ref ty) in &fun.inputs {
+        if is_user_data(name, ty) || extract_callback(ty).is_some() {
+            continue;
+        }
 
-        if callback.inputs.len() > 3 {
-            emit!(writer, ")");
+        let mut ty_writer = IndentedWriter::new(0);
+        if let Some(elem) = span_elem_type(ty) {
+            emit!(ty_writer, "ReadOnlySpan<");
+            emit_type(&mut ty_writer, context, elem, Mode::Generic);
+            emit!(ty_writer, ">");
+        } else {
+            emit_type(&mut ty_writer, context, ty, Mode::WrapperFunc);
         }
+
+        let name = param_name(name, params.len());
+        params.push((ty_writer.into_inner(), name));
     }
 
-    emit!(writer, ");\n");
+    if let Some((_, elem)) = span_callback {
+        let mut ty_writer = IndentedWriter::new(0);
+        emit!(ty_writer, "{}.ReadOnlySpanAction<", context.utils_section.class);
+        emit_type(&mut ty_writer, context, elem, Mode::Generic);
+        emit!(ty_writer, ">");
+        params.push((ty_writer.into_inner(), "handler".to_string()));
+    }
 
-    writer.unindent();
-    emit!(writer, "}}\n\n");
+    Signature {
+        return_type: writer.into_inner(),
+        name,
+        params,
+        cancellable: false,
+    }
 }
 
-pub fn emit_callback_wrapper_name(writer: &mut IndentedWriter, callback: &Function) {
-    emit_delegate_base_name(writer, callback);
-    emit!(writer, "Cb");
+pub fn emit_span_function_decl(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    modifiers: &str,
+    name: &str,
+    fun: &Function,
+) {
+    let signature = span_function_signature(context, name, fun);
+    emit_signature(writer, modifiers, &signature);
 }
 
-pub fn emit_const(writer: &mut IndentedWriter, context: &Context, name: &str, item: &Const) {
-    emit!(writer, "public ");
+/// Emit the implementation of the interface function in the mock class. It delegates to
+/// a settable property (`Func<..>`, `Action<..>` or a delegate if the parameters can't be
+/// generic arguments) and throws `NotImplementedException` until the property is set.
+pub fn emit_mock_function(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    signature: &Signature,
+    suffix: &str,
+) {
+    let property = format!("{}{}Func", signature.name, suffix);
+    let generic = signature.params.iter().all(|&(ref ty, _)| {
+        !ty.starts_with("out ") && !ty.starts_with("ref ") && !ty.starts_with("ReadOnlySpan<")
+    });
 
-    match item.value {
-        ConstValue::Array(..) |
-        ConstValue::Struct(..) => emit!(writer, "static readonly "),
-        _ => emit!(writer, "const "),
+    let mut property_type = if generic {
+        let mut args: Vec<_> = signature.params.iter().map(|&(ref ty, _)| ty.as_str()).collect();
+
+        if signature.return_type == "void" {
+            if args.is_empty() {
+                "Action".to_string()
+            } else {
+                format!("Action<{}>", args.join(", "))
+            }
+        } else {
+            args.push(&signature.return_type);
+            format!("Func<{}>", args.join(", "))
+        }
+    } else {
+        let delegate = Signature {
+            return_type: signature.return_type.clone(),
+            name: format!("{}{}Delegate", signature.name, suffix),
+            params: signature.params.clone(),
+            cancellable: false,
+        };
+        emit_signature(writer, "public delegate", &delegate);
+        emit!(writer, ";\n\n");
+
+        delegate.name
+    };
+
+    if context.platform.nullable == Some(true) {
+        property_type.push('?');
     }
 
-    emit_type(writer, context, &item.ty, Mode::Const);
-    emit!(writer, " {} = ", name.to_pascal_case());
-    emit_const_value(writer, context, Some(&item.ty), &item.value);
-    emit!(writer, ";\n");
-}
+    emit!(writer, "public {} {} {{ get; set; }}\n\n", property_type, property);
 
-pub fn emit_enum(writer: &mut IndentedWriter, context: &Context, name: &str, item: &Enum) {
-    emit!(writer, "[PublicAPI]\n");
-    emit!(writer, "public enum {} {{\n", name);
+    emit_signature(writer, "public", signature);
+    emit!(writer, " {{\n");
     writer.indent();
 
-    for variant in &item.variants {
-        emit_docs(writer, context, &variant.docs);
+    emit!(
+        writer,
+        "if ({} == null) throw new NotImplementedException(nameof({}));\n",
+        property,
+        signature.name
+    );
 
-        if let Some(value) = variant.value {
-            emit!(writer, "{} = {},\n", variant.name, value);
-        } else {
-            emit!(writer, "{},\n", variant.name);
-        }
+    if signature.return_type != "void" {
+        emit!(writer, "return ");
     }
 
+    let args: Vec<_> = signature
+        .params
+        .iter()
+        .map(|&(ref ty, ref name)| {
+            // Pass `out` and `ref` parameters on with the same modifier.
+            match ty.split_whitespace().next() {
+                Some(modifier @ "out") | Some(modifier @ "ref") => format!("{} {}", modifier, name),
+                _ => name.clone(),
+            }
+        })
+        .collect();
+    emit!(writer, "{}({});\n", property, args.join(", "));
+
     writer.unindent();
     emit!(writer, "}}\n\n");
 }
 
-pub fn emit_normal_struct(
+/// Emit the overload of the function using spans, together with its extern declaration.
+/// Input spans are pinned for the duration of the call, result spans are valid only
+/// during the handler call.
+pub fn emit_span_function(
     writer: &mut IndentedWriter,
     context: &Context,
-    name: &str,
-    item: &Struct,
+    native_name: &str,
+    fun: &Function,
 ) {
-    emit!(writer, "[PublicAPI]\n");
-    emit!(writer, "public struct {} {{\n", name);
+    let callback = extract_task_callback(&fun.inputs);
+    let span_callback = extract_span_callback(&fun.inputs);
+    let extern_name = span_extern_function_name(native_name);
+
+    emit_span_function_decl(writer, context, "public unsafe", native_name, fun);
+    emit!(writer, " {{\n");
     writer.indent();
 
-    for field in &item.fields {
-        emit_docs(writer, context, &field.docs);
-        emit_struct_field(writer, context, field, StructMode::Normal);
+    if let Some(callback) = callback {
+        if span_callback.is_some() {
+            emit!(
+                writer,
+                "var (ret, userData) = {}.PrepareSpanTask(handler);\n",
+                context.utils_section.class
+            );
+        } else {
+            emit!(writer, "var (ret, userData) = {}.PrepareTask", context.utils_section.class);
+            emit_task_generic_args(writer, context, &callback.inputs);
+            emit!(writer, "();\n");
+        }
+    }
+
+    // Pin the spans, and the other params passed as raw pointers.
+    let mut pins = Vec::new();
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
+        if let Some(elem) = span_elem_type(ty) {
+            let mut pin = IndentedWriter::new(0);
+            emit!(pin, "fixed (");
+            emit_type(&mut pin, context, elem, Mode::Generic);
+            emit!(pin, "* {0}Ptr = {0})", param_name(name, index));
+            pins.push(pin.into_inner());
+        }
+    }
+
+    pins.extend(blittable_pins(context, &fun.inputs, true));
+    emit_fixed_statements(writer, &pins);
+
+    if callback.is_none() {
+        match fun.output {
+            Type::Unit => (),
+            _ => emit!(writer, "return "),
+        }
+    }
+
+    emit!(writer, "{}(", extern_name);
+
+    for (index, &(ref name, ref ty)) in fun.inputs.iter().enumerate() {
+        if index > 0 {
+            emit!(writer, ", ");
+        }
+
+        let name = param_name(name, index);
+
+        if let Some(callback) = extract_callback(ty) {
+            if context.blittable {
+                emit!(writer, "&");
+            }
+
+            if span_callback.is_some() {
+                emit!(writer, "{}", span_callback_wrapper_name(callback));
+            } else {
+                emit!(writer, "On");
+                emit_callback_wrapper_name(writer, callback);
+            }
+        } else if span_elem_type(ty).is_some() {
+            emit!(writer, "(IntPtr) {0}Ptr, (ulong) {0}.Length", name);
+        } else if emit_blittable_arg(writer, context, ty, &name) {
+            continue;
+        } else if ty.is_dynamic_array() {
+            emit!(writer, "{0}.ToArray(), (ulong) {0}.Count", name);
+        } else if let Type::Pointer(ref ty) = *ty {
+            emit_pointer_use(writer, context, ty, &name, Mode::ExternFunc);
+        } else {
+            emit!(writer, "{}", name);
+        }
+    }
+
+    emit!(writer, ");\n");
+
+    if !pins.is_empty() {
+        writer.unindent();
+        emit!(writer, "}}\n");
+    }
+
+    if callback.is_some() {
+        emit!(writer, "return ret;\n");
     }
 
     writer.unindent();
     emit!(writer, "}}\n\n");
+
+    emit_dll_import(writer, context, "DllName", native_name);
+    emit!(writer, "internal static extern ");
+    emit_type(writer, context, &fun.output, Mode::ExternFunc);
+    emit!(writer, " {}(", extern_name);
+    emit_native_function_params(writer, context, &fun.inputs, true);
+    emit!(writer, ");\n\n");
 }
 
-pub fn emit_native_struct(
+/// Emit wrapper of a callback passing its result array to the span handler.
+pub fn emit_span_callback_wrapper(
     writer: &mut IndentedWriter,
     context: &Context,
-    name: &str,
-    item: &Struct,
+    callback: &Function,
 ) {
-    emit!(writer, "internal struct {}Native {{\n", name);
-    writer.indent();
+    let name = span_callback_wrapper_name(callback);
+    emit_callback_wrapper_header(writer, context, callback, &name);
 
-    for field in &item.fields {
-        emit_docs(writer, context, &field.docs);
-        emit_struct_field(writer, context, field, StructMode::Normal);
+    let (ref array_name, ref array_ty) = callback.inputs[2];
+    let array_name = param_name(array_name, 2);
+
+    emit!(writer, "{}.CompleteSpanTask<", &context.utils_section.class);
+    if let Some(elem) = span_elem_type(array_ty) {
+        emit_type(writer, context, elem, Mode::Generic);
     }
+    emit!(writer, ">(");
+    emit_args(writer, context, &callback.inputs[0..2], 0, Mode::Callback);
+    emit!(writer, ", {0}Ptr, {0}Len);\n", array_name);
 
-    // Emit `Free` method.
-    emit!(writer, "\n");
-    emit!(writer, "internal void Free() {{\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+/// Result callback of the function whose only value is an array which can be
+/// exposed as a span, together with the array element type.
+pub fn extract_span_callback(inputs: &[(String, Type)]) -> Option<(&Function, &Type)> {
+    if has_progress_callbacks(inputs) {
+        return None;
+    }
+
+    extract_task_callback(inputs).and_then(|callback| if callback.inputs.len() == 3 {
+        span_elem_type(&callback.inputs[2].1).map(|elem| (callback, elem))
+    } else {
+        None
+    })
+}
+
+fn span_callback_wrapper_name(callback: &Function) -> String {
+    let mut writer = IndentedWriter::new(0);
+    emit!(writer, "On");
+    emit_delegate_base_name(&mut writer, callback);
+    emit!(writer, "SpanCb");
+    writer.into_inner()
+}
+
+fn span_extern_function_name(name: &str) -> String {
+    let mut name = name.to_pascal_case();
+    name.push_str("SpanNative");
+    name
+}
+
+/// Element type of a dynamic array which can be passed as a span, which is
+/// possible only for blittable types.
+fn span_elem_type(ty: &Type) -> Option<&Type> {
+    match *ty {
+        Type::Array(ref elem, ArraySize::Dynamic) if is_blittable_primitive(elem) => Some(elem),
+        _ => None,
+    }
+}
+
+/// Returns whether the type is a fixed-size array which can be emitted as a `fixed`
+/// buffer in the blittable mode.
+pub fn is_fixed_buffer(ty: &Type) -> bool {
+    match *ty {
+        Type::Array(_, ArraySize::Dynamic) => false,
+        Type::Array(ref elem, _) => is_blittable_primitive(elem),
+        _ => false,
+    }
+}
+
+/// Returns whether the wrapper function parameter is a reference which can't be null.
+fn is_null_checked(context: &Context, ty: &Type) -> bool {
+    match *ty {
+        Type::String | Type::Array(..) => true,
+        Type::Pointer(ref ty) => {
+            match **ty {
+                Type::Array(..) => true,
+                Type::User(ref name) => context.has_safe_handle(name),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// If the type is a nullable pointer to a struct, returns name of the struct.
+fn nullable_struct<'a>(context: &Context, ty: &'a Type) -> Option<&'a str> {
+    match *ty {
+        Type::Nullable(ref ty) => context.pointed_struct(ty),
+        _ => None,
+    }
+}
+
+fn is_out_param(ty: &Type) -> bool {
+    if let Type::Out(_) = *ty { true } else { false }
+}
+
+/// Returns whether the function writes strings to its out-parameters.
+pub fn has_string_out_params(fun: &Function) -> bool {
+    fun.inputs.iter().any(|&(_, ref ty)| if let Type::Out(ref ty) = *ty {
+        is_string(ty)
+    } else {
+        false
+    })
+}
+
+/// Returns whether the wrapper function returns the values written to the out-parameters
+/// of the native function. Functions returning a task keep them as `out` parameters.
+fn returns_out_params(fun: &Function) -> bool {
+    extract_task_callback(&fun.inputs).is_none() &&
+        fun.inputs.iter().any(|&(_, ref ty)| is_out_param(ty))
+}
+
+/// Returns whether the native function reports failures through non-zero error code,
+/// as configured by `LangCSharp::set_last_error_function`. The wrapper function then
+/// throws `FfiException`, or faults the returned task, instead of returning the code.
+pub fn returns_error_code(context: &Context, fun: &Function) -> bool {
+    if let Type::I32 = fun.output {
+        context.last_error_function().is_some()
+    } else {
+        false
+    }
+}
+
+/// Returns whether the function returning a task takes the native cancellation token,
+/// as configured by `LangCSharp::set_cancel_convention`. The wrapper function then
+/// takes `CancellationToken` instead.
+pub fn is_cancellable(context: &Context, fun: &Function) -> bool {
+    extract_task_callback(&fun.inputs).is_some() && !has_progress_callbacks(&fun.inputs) &&
+        fun.inputs.iter().any(|&(_, ref ty)| context.is_cancel_token(ty))
+}
+
+// Name of the variable holding the value of an out-parameter. The `o_` prefix is
+// dropped if the value is returned.
+fn out_param_name(fun: &Function, name: &str, index: usize) -> String {
+    if returns_out_params(fun) {
+        output_param_name(name, index)
+    } else {
+        param_name(name, index)
+    }
+}
+
+// Emit the return type of a wrapper function returning the values of the out-parameters,
+// which is a tuple if there is more than one value.
+fn emit_out_return_type(writer: &mut IndentedWriter, context: &Context, fun: &Function) {
+    let outs: Vec<_> = fun.inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &(ref name, ref ty))| match *ty {
+            Type::Out(ref ty) => Some((output_param_name(name, index), ty)),
+            _ => None,
+        })
+        .collect();
+
+    let has_return = match fun.output {
+        Type::Unit => false,
+        _ => !returns_error_code(context, fun),
+    };
+
+    if !has_return && outs.len() == 1 {
+        emit_out_type(writer, context, outs[0].1, Mode::WrapperFunc);
+        return;
+    }
+
+    emit!(writer, "(");
+
+    if has_return {
+        emit_type(writer, context, &fun.output, Mode::WrapperFunc);
+        emit!(writer, " ret, ");
+    }
+
+    for (index, &(ref name, ty)) in outs.iter().enumerate() {
+        if index > 0 {
+            emit!(writer, ", ");
+        }
+
+        emit_out_type(writer, context, ty, Mode::WrapperFunc);
+        emit!(writer, " {}", name);
+    }
+
+    emit!(writer, ")");
+}
+
+// Emit the type of the value written to an out-parameter.
+fn emit_out_type(writer: &mut IndentedWriter, context: &Context, ty: &Type, mode: Mode) {
+    match *ty {
+        // Strings written by the native code are passed as raw pointers and copied
+        // by the wrapper after the call. They are then released by the configured
+        // string free function, or stay owned by the native code (see
+        // `LangCSharp::set_string_free_function`).
+        Type::String if mode == Mode::ExternFunc => emit!(writer, "IntPtr"),
+        Type::String => emit!(writer, "string"),
+        Type::Pointer(ref ty) => {
+            match **ty {
+                Type::User(ref name) if context.has_safe_handle(name) => {
+                    emit!(writer, "{}", safe_handle_name(name))
+                }
+                _ => emit!(writer, "IntPtr"),
+            }
+        }
+        Type::User(ref name) if mode == Mode::ExternFunc && context.is_native_name(name) => {
+            emit!(writer, "{}Native", name)
+        }
+        _ => emit_type(writer, context, ty, Mode::Generic),
+    }
+}
+
+fn is_blittable_primitive(ty: &Type) -> bool {
+    match *ty {
+        Type::F32 | Type::F64 | Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::ISize |
+        Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::USize => true,
+        _ => false,
+    }
+}
+
+fn is_string(ty: &Type) -> bool {
+    if let Type::String = *ty { true } else { false }
+}
+
+// Is the type an (out-parameter) bool passed as byte in the blittable mode?
+fn is_blittable_bool(context: &Context, ty: &Type) -> bool {
+    match *ty {
+        Type::Bool => context.blittable,
+        _ => false,
+    }
+}
+
+// Is the param pinned and passed as a raw pointer in the blittable mode?
+fn is_pinned(ty: &Type) -> bool {
+    pinned_elem_type(ty).is_some() ||
+        match *ty {
+            Type::String => true,
+            Type::Nullable(ref ty) => {
+                match **ty {
+                    Type::String => true,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+}
+
+fn pinned_elem_type(ty: &Type) -> Option<&Type> {
+    match *ty {
+        Type::Array(ref elem, _) => Some(elem),
+        Type::Pointer(ref ty) => {
+            match **ty {
+                Type::Array(ref elem, _) => Some(elem),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Type of the extern function param converted by the wrapper function in the blittable
+// mode, so it is passed without runtime marshalling.
+fn blittable_param_type(context: &Context, ty: &Type) -> Option<&'static str> {
+    if !context.blittable {
+        return None;
+    }
+
+    match *ty {
+        Type::Bool => Some("byte"),
+        Type::Out(ref ty) if is_blittable_bool(context, ty) => Some("out byte"),
+        _ if is_pinned(ty) => Some("IntPtr"),
+        _ => None,
+    }
+}
+
+// `fixed` statements pinning the params passed as raw pointers in the blittable mode.
+// Strings are pinned as null-terminated UTF-8. Spans, pinned by the span function
+// itself, are skipped if `spans` is set.
+fn blittable_pins(context: &Context, inputs: &[(String, Type)], spans: bool) -> Vec<String> {
+    let mut result = Vec::new();
+    if !context.blittable {
+        return result;
+    }
+
+    for (index, &(ref name, ref ty)) in inputs.iter().enumerate() {
+        if !is_pinned(ty) || spans && span_elem_type(ty).is_some() {
+            continue;
+        }
+
+        let name = param_name(name, index);
+        let mut writer = IndentedWriter::new(0);
+        emit!(writer, "fixed (");
+
+        match (ty, pinned_elem_type(ty)) {
+            (&Type::Array(_, ArraySize::Dynamic), Some(elem)) => {
+                emit_list_item_type(&mut writer, context, elem);
+                emit!(writer, "* {}Ptr = ", name);
+                emit_list_to_native(&mut writer, context, elem, &name);
+                emit!(writer, ".ToArray())");
+            }
+            (_, Some(elem)) => {
+                emit_type(&mut writer, context, elem, Mode::Generic);
+                emit!(writer, "* {0}Ptr = {0})", name);
+            }
+            (_, None) => {
+                emit!(
+                    writer,
+                    "byte* {0}Ptr = {1}.ToUtf8Bytes({0}))",
+                    name,
+                    context.utils_section.class
+                )
+            }
+        }
+
+        result.push(writer.into_inner());
+    }
+
+    result
+}
+
+// Emit the `fixed` statements opening a block, if there are any.
+fn emit_fixed_statements(writer: &mut IndentedWriter, pins: &[String]) {
+    if pins.is_empty() {
+        return;
+    }
+
+    emit!(writer, "{} {{\n", pins.join("\n"));
+    writer.indent();
+}
+
+// Emit the argument of the extern function converted in the blittable mode. Returns
+// false if the argument is passed as is.
+fn emit_blittable_arg(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    ty: &Type,
+    name: &str,
+) -> bool {
+    if !context.blittable {
+        return false;
+    }
+
+    match *ty {
+        Type::Bool => emit!(writer, "(byte) ({} ? 1 : 0)", name),
+        Type::Array(_, ArraySize::Dynamic) => {
+            emit!(writer, "(IntPtr) {0}Ptr, (ulong) {0}.Count", name)
+        }
+        _ if is_pinned(ty) => emit!(writer, "(IntPtr) {}Ptr", name),
+        _ => return false,
+    }
+
+    true
+}
+
+pub fn emit_callback_delegate(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    if let Some(ref convention) = context.platform.calling_convention {
+        emit!(
+            writer,
+            "[UnmanagedFunctionPointer(CallingConvention.{})]\n",
+            convention
+        );
+    }
+
+    emit!(writer, "internal delegate void ");
+    emit_callback_wrapper_name(writer, callback);
+    emit!(writer, "(");
+    emit_callback_params(writer, context, callback);
+    emit!(writer, ");\n\n");
+}
+
+pub fn emit_callback_wrapper(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    let mut name = String::from("On");
+    name.push_str(&delegate_name(callback));
+
+    emit_callback_wrapper_header(writer, context, callback, &name);
+
+    emit!(writer, "{}.CompleteTask(", &context.utils_section.class);
+    emit_callback_result_args(writer, context, callback);
+    emit!(writer, ");\n");
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+/// Emit wrappers of callbacks of a function taking progress callbacks. Unlike
+/// the shared callback wrappers, these are specific to the function, because
+/// the `user_data` holds both the progress reporters and the task.
+pub fn emit_progress_callback_wrappers(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    fun: &Function,
+) {
+    let mut progress_index = 0;
+
+    for (index, callback) in extract_callbacks(&fun.inputs).into_iter().enumerate() {
+        let wrapper_name = progress_callback_wrapper_name(name, index);
+        emit_callback_wrapper_header(writer, context, callback, &wrapper_name);
+
+        if is_result_callback(callback) {
+            emit!(writer, "{}.CompleteProgressTask(", &context.utils_section.class);
+            emit_callback_result_args(writer, context, callback);
+        } else {
+            emit!(writer, "{}.ReportProgress(", &context.utils_section.class);
+            emit_args(writer, context, &callback.inputs[0..1], 0, Mode::Callback);
+            emit!(writer, ", {}", progress_index);
+
+            if callback.inputs.len() > 1 {
+                emit!(writer, ", () => ");
+
+                if callback.inputs.len() > 2 {
+                    emit!(writer, "(");
+                }
+
+                emit_args(writer, context, &callback.inputs[1..], 1, Mode::Callback);
+
+                if callback.inputs.len() > 2 {
+                    emit!(writer, ")");
+                }
+            }
+
+            progress_index += 1;
+        }
+
+        emit!(writer, ");\n");
+
+        writer.unindent();
+        emit!(writer, "}}\n\n");
+    }
+}
+
+fn emit_callback_wrapper_header(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    callback: &Function,
+    name: &str,
+) {
+    if context.blittable {
+        if let Some(convention) = unmanaged_calling_convention(context) {
+            emit!(
+                writer,
+                "[UnmanagedCallersOnly(CallConvs = new[] {{ typeof(CallConv{}) }})]\n",
+                convention
+            );
+        } else {
+            emit!(writer, "[UnmanagedCallersOnly]\n");
+        }
+    } else if let Some(ref attribute) = context.platform.callback_attribute {
+        let condition = context.platform.callback_attribute_condition.as_ref();
+        if let Some(condition) = condition {
+            emit!(writer, "#if {}\n", condition);
+        }
+
+        emit!(writer, "[{}]\n", attribute.replace("{}", &delegate_name(callback)));
+
+        if condition.is_some() {
+            emit!(writer, "#endif\n");
+        }
+    }
+
+    emit!(writer, "private static void {}(", name);
+    emit_callback_params(writer, context, callback);
+    emit!(writer, ") {{\n");
+    writer.indent();
+}
+
+// Note: assuming here the first param is user_data and the second is result.
+fn emit_callback_result_args(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    emit_args(writer, context, &callback.inputs[0..2], 0, Mode::Callback);
+
+    if callback.inputs.len() > 2 {
+        emit!(writer, ", () => ");
+
+        if callback.inputs.len() > 3 {
+            emit!(writer, "(");
+        }
+
+        emit_args(writer, context, &callback.inputs[2..], 2, Mode::Callback);
+
+        if callback.inputs.len() > 3 {
+            emit!(writer, ")");
+        }
+    }
+}
+
+pub fn emit_safe_handle(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    destructor: &str,
+    fun: &Function,
+) {
+    let class = safe_handle_name(name);
+    let param = param_name(&fun.inputs[0].0, 0);
+
+    emit!(writer, "public sealed class {} : SafeHandle {{\n", class);
+    writer.indent();
+
+    emit!(
+        writer,
+        "internal {}() : base(IntPtr.Zero, true) {{}}\n\n",
+        class
+    );
+    emit!(
+        writer,
+        "internal {}(IntPtr handle, bool ownsHandle) : base(IntPtr.Zero, ownsHandle) {{\n",
+        class
+    );
+    writer.indent();
+    emit!(writer, "SetHandle(handle);\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+
+    emit!(
+        writer,
+        "public override bool IsInvalid => handle == IntPtr.Zero;\n\n"
+    );
+
+    emit!(writer, "protected override bool ReleaseHandle() {{\n");
+    writer.indent();
+    emit!(writer, "{}(handle);\n", extern_function_name(destructor));
+    emit!(writer, "return true;\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+
+    let lib_name = format!("{}.DllName", context.functions_section.class);
+    emit_dll_import(writer, context, &lib_name, destructor);
+    emit!(
+        writer,
+        "private static extern void {}(IntPtr {});\n",
+        extern_function_name(destructor),
+        param
+    );
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+/// Emit the nullable directive and the `using` directives of a generated file.
+pub fn emit_file_header(writer: &mut IndentedWriter, context: &Context, usings: &[&str]) {
+    match context.platform.nullable {
+        Some(true) => emit!(writer, "#nullable enable\n\n"),
+        Some(false) => emit!(writer, "#nullable disable\n\n"),
+        None => (),
+    }
+
+    for using in usings {
+        emit!(writer, "using {};\n", using);
+    }
+
+    for using in &context.platform.usings {
+        if !usings.contains(&using.as_str()) {
+            emit!(writer, "using {};\n", using);
+        }
+    }
+
+    emit!(writer, "\n");
+}
+
+/// Emit the `DllName` constant, selecting the native library name by the platform.
+pub fn emit_lib_name(writer: &mut IndentedWriter, context: &Context) {
+    for (index, &(ref condition, ref name)) in context.platform.lib_names.iter().enumerate() {
+        emit!(
+            writer,
+            "#{} {}\n",
+            if index == 0 { "if" } else { "elif" },
+            condition
+        );
+        emit!(writer, "internal const string DllName = \"{}\";\n", name);
+    }
+
+    if !context.platform.lib_names.is_empty() {
+        emit!(writer, "#else\n");
+    }
+
+    emit!(
+        writer,
+        "internal const string DllName = \"{}\";\n",
+        context.lib_name
+    );
+
+    if !context.platform.lib_names.is_empty() {
+        emit!(writer, "#endif\n");
+    }
+
+    emit!(writer, "\n");
+}
+
+fn emit_dll_import(writer: &mut IndentedWriter, context: &Context, lib_name: &str, name: &str) {
+    emit!(writer, "[DllImport({}, ", lib_name);
+
+    if let Some(ref convention) = context.platform.calling_convention {
+        emit!(writer, "CallingConvention = CallingConvention.{}, ", convention);
+    }
+
+    emit!(writer, "EntryPoint = \"{}\")]\n", name);
+}
+
+// Calling convention of the function pointers and of the `[UnmanagedCallersOnly]`
+// callbacks, named as the `CallConv*` types (e.g. `Stdcall` for `StdCall`). Defaults
+// to `Cdecl`, the convention of `extern "C"` functions. `Winapi` has no such name,
+// so the default of the platform is used.
+fn unmanaged_calling_convention(context: &Context) -> Option<String> {
+    let convention = context
+        .platform
+        .calling_convention
+        .as_ref()
+        .map(String::as_str)
+        .unwrap_or("Cdecl");
+
+    if convention == "Winapi" {
+        return None;
+    }
+
+    let mut chars = convention.chars();
+    chars.next().map(|first| {
+        format!("{}{}", first, chars.as_str().to_lowercase())
+    })
+}
+
+fn emit_public_api_attribute(writer: &mut IndentedWriter, context: &Context) {
+    if context.platform.annotations {
+        emit!(writer, "[PublicAPI]\n");
+    }
+}
+
+pub fn emit_callback_wrapper_name(writer: &mut IndentedWriter, callback: &Function) {
+    emit_delegate_base_name(writer, callback);
+    emit!(writer, "Cb");
+}
+
+pub fn emit_const(writer: &mut IndentedWriter, context: &Context, name: &str, item: &Const) {
+    emit!(writer, "public ");
+
+    match item.value {
+        ConstValue::Array(..) |
+        ConstValue::Struct(..) => emit!(writer, "static readonly "),
+        _ => emit!(writer, "const "),
+    }
+
+    emit_type(writer, context, &item.ty, Mode::Const);
+    emit!(writer, " {} = ", name.to_pascal_case());
+    emit_const_value(writer, context, Some(&item.ty), &item.value);
+    emit!(writer, ";\n");
+}
+
+pub fn emit_enum(writer: &mut IndentedWriter, context: &Context, name: &str, item: &Enum) {
+    emit_public_api_attribute(writer, context);
+
+    if item.flags {
+        emit!(writer, "[Flags]\n");
+    }
+
+    emit!(writer, "public enum {}", name);
+
+    if let Some(ref ty) = item.ty {
+        emit!(writer, " : ");
+        emit_type(writer, context, ty, Mode::Const);
+    }
+
+    emit!(writer, " {{\n");
+    writer.indent();
+
+    for variant in &item.variants {
+        emit_docs(writer, context, &variant.docs);
+
+        if let Some(ref value) = variant.value {
+            emit!(writer, "{} = ", variant.name);
+            emit_int_expr(writer, value, true);
+            emit!(writer, ",\n");
+        } else {
+            emit!(writer, "{},\n", variant.name);
+        }
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+pub fn emit_normal_struct(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    item: &Struct,
+) {
+    emit_public_api_attribute(writer, context);
+    emit!(writer, "public struct {} {{\n", name);
+    writer.indent();
+
+    for field in &item.fields {
+        emit_docs(writer, context, &field.docs);
+        emit_struct_field(writer, context, field, StructMode::Normal);
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+pub fn emit_native_struct(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    item: &Struct,
+) {
+    emit!(writer, "internal {}struct {}Native {{\n", unsafe_modifier(context), name);
+    writer.indent();
+
+    for field in &item.fields {
+        emit_docs(writer, context, &field.docs);
+        emit_struct_field(writer, context, field, StructMode::Normal);
+    }
+
+    emit!(writer, "\n");
+    emit_free_method(writer, context, item, false);
+
+    if context.has_transferable_vectors(name) {
+        emit!(writer, "\n");
+        emit_free_method(writer, context, item, true);
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+// Emit the `Free` method of the native struct, or the `FreeAfterTransfer` method
+// skipping the vectors with capacity, whose ownership was transferred to Rust.
+fn emit_free_method(writer: &mut IndentedWriter, context: &Context, item: &Struct, transfer: bool) {
+    emit!(
+        writer,
+        "internal void {}() {{\n",
+        if transfer { "FreeAfterTransfer" } else { "Free" }
+    );
     writer.indent();
 
     for field in &item.fields {
         let name = field.name.to_pascal_case();
 
-        if field.ty.is_dynamic_array() {
+        if transfer && field.has_cap && field.ty.is_dynamic_array() {
+            continue;
+        }
+
+        // Free the native items before the list itself.
+        if let Type::Array(ref ty, ArraySize::Dynamic) = field.ty {
+            if context.is_native_type(ty) {
+                let method = struct_name(ty).map_or("Free", |type_name| {
+                    free_method(context, type_name, transfer)
+                });
+
+                emit!(writer, "{}.FreeListItems<", context.utils_section.class);
+                emit_list_item_type(writer, context, ty);
+                emit!(writer, ">({0}Ptr, {0}Len, item => item.{1}());\n", name, method);
+            }
+        }
+
+        if let (&Type::Array(ref ty, ArraySize::Dynamic), true) = (&field.ty, field.has_cap) {
+            // Vectors with capacity are allocated by the Rust allocator.
+            emit!(writer, "{}.FreeList<", context.utils_section.class);
+            emit_list_item_type(writer, context, ty);
+            emit!(writer, ">(ref {0}Ptr, ref {0}Len, ref {0}Cap, ", name);
+            emit_alignment(writer, context, ty);
+            emit!(
+                writer,
+                ", {}.{});\n",
+                context.functions_section.class,
+                extern_function_name(context.free_function())
+            );
+        } else if field.ty.is_dynamic_array() {
             emit!(
                 writer,
                 "{0}.FreeList(ref {1}Ptr, ref {1}Len);\n",
                 context.utils_section.class,
                 name
             );
+        } else if let Some(type_name) = context.pointed_struct(&field.ty) {
+            emit_free_object(writer, context, type_name, &name, transfer);
+        } else if context.is_native_type(&field.ty) {
+            let method = struct_name(&field.ty).map_or("Free", |type_name| {
+                free_method(context, type_name, transfer)
+            });
+            emit!(writer, "{}.{}();\n", name, method)
+        } else if let (&Type::String, true) = (&field.ty, context.copies_strings()) {
+            emit!(
+                writer,
+                "{}.FreeString(ref {});\n",
+                context.utils_section.class,
+                name
+            );
+        }
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n");
+}
+
+// Method releasing the native struct. After a consuming call, `FreeAfterTransfer`
+// skips the vectors now owned by Rust.
+fn free_method(context: &Context, name: &str, transfer: bool) -> &'static str {
+    if transfer && context.has_transferable_vectors(name) {
+        "FreeAfterTransfer"
+    } else {
+        "Free"
+    }
+}
+
+pub fn emit_wrapper_struct(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    item: &Struct,
+) {
+    let disposable = context.is_disposable(name);
+    let destructor = context.disposable_destructor(name);
+
+    emit_public_api_attribute(writer, context);
+
+    if context.is_class(name) {
+        emit!(
+            writer,
+            "public {}sealed class {}{} {{\n",
+            unsafe_modifier(context),
+            name,
+            if disposable { " : IDisposable" } else { "" }
+        );
+    } else {
+        emit!(writer, "public {}struct {} {{\n", unsafe_modifier(context), name);
+    }
+
+    writer.indent();
+
+    for field in &item.fields {
+        emit_struct_field(writer, context, field, StructMode::Wrapper);
+    }
+
+    if destructor.is_some() {
+        emit!(writer, "private IntPtr _nativePtr;\n");
+    }
+
+    emit!(writer, "\n");
+
+    if context.is_class(name) {
+        emit!(writer, "public {}() {{}}\n\n", name);
+    }
+
+    // Emit constructor.
+    emit!(writer, "internal {0}({0}Native native) {{\n", name);
+    writer.indent();
+
+    for field in &item.fields {
+        let name = field.name.to_pascal_case();
+
+        emit!(writer, "{} = ", name);
+
+        if let Type::Array(ref ty, ArraySize::Dynamic) = field.ty {
+            emit_copy_to_utility_name(writer, context, ty, "List");
+            emit!(writer, "(native.{0}Ptr, (int) native.{0}Len)", name);
+
+            // Convert the items element-wise.
+            if let Type::User(ref type_name) = **ty {
+                if context.is_native_name(type_name) {
+                    emit!(writer, ".ConvertAll(item => new {}(item))", type_name);
+                }
+            }
+
+            emit!(writer, ";\n");
+        } else if let Some(type_name) = context.pointed_struct(&field.ty) {
+            emit!(writer, "native.{} != IntPtr.Zero ? ", name);
+
+            if context.is_native_name(type_name) {
+                emit!(writer, "new {}(", type_name);
+                emit_struct_pointer_use(writer, context, &format!("{}Native", type_name), &name);
+                emit!(writer, ")");
+            } else {
+                emit_struct_pointer_use(writer, context, type_name, &name);
+            }
+
+            if context.is_class(type_name) {
+                emit!(writer, " : null;\n");
+            } else {
+                emit!(writer, " : ({}?) null;\n", type_name);
+            }
+        } else if let (&Type::User(ref type_name), true) =
+            (&field.ty, context.is_native_type(&field.ty))
+        {
+            emit!(writer, "new {}(native.{});\n", type_name, name)
+        } else if context.needs_field_conversion(&field.ty) {
+            match field.ty {
+                Type::Bool => emit!(writer, "native.{} != 0;\n", name),
+                Type::String => {
+                    emit!(
+                        writer,
+                        "{}.CopyToString(native.{});\n",
+                        context.utils_section.class,
+                        name
+                    )
+                }
+                Type::Array(ref ty, ref size) => {
+                    emit!(writer, "new ReadOnlySpan<");
+                    emit_type(writer, context, ty, Mode::Generic);
+                    emit!(writer, ">(native.{}, ", name);
+                    emit_array_size(writer, context, size);
+                    emit!(writer, ").ToArray();\n");
+                }
+                _ => unreachable!(),
+            }
+        } else {
+            emit!(writer, "native.{};\n", name)
+        }
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+
+    if disposable {
+        emit_disposable_members(writer, context, name, destructor);
+    }
+
+    // Emit `ToNative` method.
+    emit!(writer, "internal {}Native ToNative() {{\n", name);
+    writer.indent();
+
+    if context.blittable {
+        emit_blittable_to_native_body(writer, context, name, item);
+    } else {
+        emit_to_native_body(writer, context, name, item);
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n");
+
+    if let Some(destructor) = destructor {
+        emit!(writer, "\n");
+        emit!(writer, "private void FreeNative() {{\n");
+        writer.indent();
+        emit!(writer, "if (_nativePtr != IntPtr.Zero) {{\n");
+        writer.indent();
+        emit!(writer, "{}(_nativePtr);\n", extern_function_name(destructor));
+        emit!(writer, "_nativePtr = IntPtr.Zero;\n");
+        writer.unindent();
+        emit!(writer, "}}\n");
+        writer.unindent();
+        emit!(writer, "}}\n\n");
+
+        let lib_name = format!("{}.DllName", context.functions_section.class);
+        emit_dll_import(writer, context, &lib_name, destructor);
+        emit!(
+            writer,
+            "private static extern void {}(IntPtr ptr);\n",
+            extern_function_name(destructor)
+        );
+    }
+
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+// Emit read of the struct the field of the native struct points to.
+fn emit_struct_pointer_use(writer: &mut IndentedWriter, context: &Context, ty: &str, name: &str) {
+    if context.blittable {
+        emit!(writer, "*({}*) native.{}", ty, name);
+    } else {
+        emit!(writer, "Marshal.PtrToStructure<{}>(native.{})", ty, name);
+    }
+}
+
+// Emit constructor taking ownership of the native struct, finaliser and `Dispose`.
+// Wrappers of borrowed native structs own no native memory, so their `Dispose` is empty.
+fn emit_disposable_members(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    destructor: Option<&str>,
+) {
+    if destructor.is_none() {
+        emit!(writer, "public void Dispose() {{}}\n\n");
+        return;
+    }
+
+    emit!(writer, "internal {}(IntPtr nativePtr) : this(", name);
+
+    if context.blittable {
+        emit!(writer, "*({}Native*) nativePtr", name);
+    } else {
+        emit!(writer, "Marshal.PtrToStructure<{}Native>(nativePtr)", name);
+    }
+
+    emit!(writer, ") {{\n");
+    writer.indent();
+    emit!(writer, "_nativePtr = nativePtr;\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+
+    emit!(writer, "~{}() {{\n", name);
+    writer.indent();
+    emit!(writer, "FreeNative();\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+
+    emit!(writer, "public void Dispose() {{\n");
+    writer.indent();
+    emit!(writer, "FreeNative();\n");
+    emit!(writer, "GC.SuppressFinalize(this);\n");
+    writer.unindent();
+    emit!(writer, "}}\n\n");
+}
+
+fn emit_to_native_body(writer: &mut IndentedWriter, context: &Context, name: &str, item: &Struct) {
+    emit!(writer, "return new {}Native() {{\n", name);
+    writer.indent();
+
+    for (index, field) in item.fields.iter().enumerate() {
+        let name = field.name.to_pascal_case();
+
+        if let Type::Array(ref ty, ArraySize::Dynamic) = field.ty {
+            emit!(writer, "{}Ptr = ", name);
+            emit_copy_from_utility_name(writer, context, ty);
+            emit!(writer, "(");
+            emit_list_to_native(writer, context, ty, &name);
+
+            if field.has_cap {
+                emit_alloc_args(writer, context, ty);
+            }
+
+            emit!(writer, "),\n");
+            emit!(writer, "{0}Len = (ulong) {0}.Count", name);
+
+            if field.has_cap {
+                emit!(writer, ",\n");
+                emit!(writer, "{0}Cap = (ulong) {0}.Count", name);
+            }
+        } else if let Some(type_name) = context.pointed_struct(&field.ty) {
+            emit!(writer, "{} = ", name);
+            emit_struct_to_pointer(writer, context, type_name, &name);
         } else if context.is_native_type(&field.ty) {
-            emit!(writer, "{}.Free();\n", name)
+            emit!(writer, "{0} = {0}.ToNative()", name);
+        } else if let (&Type::String, true) = (&field.ty, context.copies_strings()) {
+            emit!(
+                writer,
+                "{0} = {1}.CopyFromString({0})",
+                name,
+                context.utils_section.class
+            );
+        } else {
+            emit!(writer, "{0} = {0}", name);
+        }
+
+        if index < item.fields.len() - 1 {
+            emit!(writer, ",\n");
+        } else {
+            emit!(writer, "\n")
+        }
+    }
+
+    writer.unindent();
+    emit!(writer, "}};\n");
+}
+
+// Fixed buffers can't be initialized in an object initializer, so the native struct
+// is populated field by field.
+fn emit_blittable_to_native_body(
+    writer: &mut IndentedWriter,
+    context: &Context,
+    name: &str,
+    item: &Struct,
+) {
+    emit!(writer, "var native = new {}Native();\n", name);
+
+    for field in &item.fields {
+        let name = field.name.to_pascal_case();
+
+        if let Some(type_name) = context.pointed_struct(&field.ty) {
+            emit!(writer, "native.{} = ", name);
+            emit_struct_to_pointer(writer, context, type_name, &name);
+            emit!(writer, ";\n");
+            continue;
+        }
+
+        match field.ty {
+            Type::Array(ref ty, ArraySize::Dynamic) => {
+                emit!(writer, "native.{}Ptr = ", name);
+                emit_copy_from_utility_name(writer, context, ty);
+                emit!(writer, "(");
+                emit_list_to_native(writer, context, ty, &name);
+
+                if field.has_cap {
+                    emit_alloc_args(writer, context, ty);
+                }
+
+                emit!(writer, ");\n");
+                emit!(writer, "native.{0}Len = (ulong) {0}.Count;\n", name);
+
+                if field.has_cap {
+                    emit!(writer, "native.{0}Cap = (ulong) {0}.Count;\n", name);
+                }
+            }
+            Type::Array(ref ty, ref size) if is_fixed_buffer(&field.ty) => {
+                emit!(writer, "{}.AsSpan().CopyTo(new Span<", name);
+                emit_type(writer, context, ty, Mode::Generic);
+                emit!(writer, ">(native.{}, ", name);
+                emit_array_size(writer, context, size);
+                emit!(writer, "));\n");
+            }
+            Type::Bool => emit!(writer, "native.{0} = (byte) ({0} ? 1 : 0);\n", name),
+            Type::String => {
+                emit!(
+                    writer,
+                    "native.{0} = {1}.CopyFromString({0});\n",
+                    name,
+                    context.utils_section.class
+                )
+            }
+            _ if context.is_native_type(&field.ty) => {
+                emit!(writer, "native.{0} = {0}.ToNative();\n", name)
+            }
+            _ => emit!(writer, "native.{0} = {0};\n", name),
         }
     }
 
-    writer.unindent();
-    emit!(writer, "}}\n");
+    emit!(writer, "return native;\n");
+}
+
+// Emit the list passed to the copy utility, converting the items to their native
+// representation if needed.
+fn emit_list_to_native(writer: &mut IndentedWriter, context: &Context, ty: &Type, name: &str) {
+    emit!(writer, "{}", name);
+
+    if context.is_native_type(ty) {
+        emit!(writer, ".ConvertAll(item => item.ToNative())");
+    }
+}
+
+// Emit copy of the struct to native memory, or null pointer if it's null.
+fn emit_struct_to_pointer(writer: &mut IndentedWriter, context: &Context, ty: &str, name: &str) {
+    emit!(writer, "{}.CopyFromObject({}", context.utils_section.class, name);
 
-    writer.unindent();
-    emit!(writer, "}}\n\n");
+    if context.is_native_name(ty) {
+        emit!(writer, "?.ToNative()");
+    }
+
+    emit!(writer, ")");
 }
 
-pub fn emit_wrapper_struct(
+// Emit release of the struct copied to native memory by `CopyFromObject`.
+fn emit_free_object(
     writer: &mut IndentedWriter,
     context: &Context,
+    ty: &str,
     name: &str,
-    item: &Struct,
+    transfer: bool,
 ) {
-    emit!(writer, "[PublicAPI]\n");
-    emit!(writer, "public struct {} {{\n", name);
-    writer.indent();
-
-    for field in &item.fields {
-        emit_struct_field(writer, context, field, StructMode::Wrapper);
+    if context.is_native_name(ty) {
+        emit!(
+            writer,
+            "{}.FreeObject<{}Native>(ref {}, item => item.{}());\n",
+            context.utils_section.class,
+            ty,
+            name,
+            free_method(context, ty, transfer)
+        );
+    } else {
+        emit!(writer, "{}.FreeObject(ref {});\n", context.utils_section.class, name);
     }
+}
 
-    emit!(writer, "\n");
-
-    // Emit constructor.
-    emit!(writer, "internal {0}({0}Native native) {{\n", name);
-    writer.indent();
+// Emit the type of the items of the list in native memory.
+fn emit_list_item_type(writer: &mut IndentedWriter, context: &Context, ty: &Type) {
+    emit_type(writer, context, ty, Mode::Generic);
 
-    for field in &item.fields {
-        let name = field.name.to_pascal_case();
+    if context.is_native_type(ty) {
+        emit!(writer, "Native");
+    }
+}
 
-        emit!(writer, "{} = ", name);
+// Emit the extra arguments of the copy utility allocating the list by the Rust allocator.
+fn emit_alloc_args(writer: &mut IndentedWriter, context: &Context, ty: &Type) {
+    emit!(writer, ", ");
+    emit_alignment(writer, context, ty);
+    emit!(
+        writer,
+        ", {}.{}",
+        context.functions_section.class,
+        extern_function_name(context.alloc_function())
+    );
+}
 
-        if let Type::Array(ref ty, ArraySize::Dynamic) = field.ty {
-            emit_copy_to_utility_name(writer, context, ty, "List");
-            emit!(writer, "(native.{0}Ptr, (int) native.{0}Len);\n", name);
-        } else if context.is_native_type(&field.ty) {
-            emit!(writer, "new {0}(native.{0});\n", name)
-        } else {
-            emit!(writer, "native.{};\n", name)
-        }
+fn emit_alignment(writer: &mut IndentedWriter, context: &Context, ty: &Type) {
+    // Unknown alignments are rejected by `finalise_output`.
+    match context.alignment_of(ty).expect("alignment is resolved") {
+        Alignment::Bytes(bytes) => emit!(writer, "{}", bytes),
+        Alignment::Pointer => emit!(writer, "(ulong) IntPtr.Size"),
+        Alignment::Int64 => emit!(writer, "{}.Int64Alignment", context.utils_section.class),
     }
+}
 
-    writer.unindent();
-    emit!(writer, "}}\n\n");
+/// Emit declarations of the Rust functions allocating and releasing the memory of
+/// vectors with capacity.
+pub fn emit_allocator_decls(writer: &mut IndentedWriter, context: &Context) {
+    emit_dll_import(writer, context, "DllName", context.alloc_function());
+    emit!(
+        writer,
+        "internal static extern IntPtr {}(ulong size, ulong align);\n\n",
+        extern_function_name(context.alloc_function())
+    );
 
-    // Emit `ToNative` method.
-    emit!(writer, "internal {}Native ToNative() {{\n", name);
-    writer.indent();
+    emit_dll_import(writer, context, "DllName", context.free_function());
+    emit!(
+        writer,
+        "internal static extern void {}(IntPtr ptr, ulong size, ulong align);\n\n",
+        extern_function_name(context.free_function())
+    );
+}
 
-    emit!(writer, "return new {}Native() {{\n", name);
-    writer.indent();
+pub fn emit_last_error_decl(writer: &mut IndentedWriter, context: &Context) {
+    let name = context.last_error_function().unwrap_or_default();
 
-    for (index, field) in item.fields.iter().enumerate() {
-        let name = field.name.to_pascal_case();
+    emit_dll_import(writer, context, "DllName", name);
+    emit!(writer, "internal static extern IntPtr {}();\n\n", extern_function_name(name));
+}
 
-        if let Type::Array(ref ty, ArraySize::Dynamic) = field.ty {
-            emit!(writer, "{}Ptr = ", name);
-            emit_copy_from_utility_name(writer, context, ty);
-            emit!(writer, "({}),\n", name);
-            emit!(writer, "{0}Len = (ulong) {0}.Count", name);
+pub fn emit_string_free_decl(writer: &mut IndentedWriter, context: &Context) {
+    let name = context.string_free_function().unwrap_or_default();
 
-            if field.has_cap {
-                emit!(writer, ",\n");
-                emit!(writer, "{0}Cap = 0", name);
-            }
-        } else if context.is_native_type(&field.ty) {
-            emit!(writer, "{0} = {0}.ToNative()", name);
-        } else {
-            emit!(writer, "{0} = {0}", name);
-        }
+    emit_dll_import(writer, context, "DllName", name);
+    emit!(writer, "internal static extern void {}(IntPtr ptr);\n\n", extern_function_name(name));
+}
 
-        if index < item.fields.len() - 1 {
-            emit!(writer, ",\n");
-        } else {
-            emit!(writer, "\n")
-        }
-    }
+pub fn emit_cancel_decls(writer: &mut IndentedWriter, context: &Context) {
+    let convention = context.cancel_convention().expect("cancellation is enabled");
 
-    writer.unindent();
-    emit!(writer, "}};\n");
+    emit_dll_import(writer, context, "DllName", &convention.new_function);
+    emit!(
+        writer,
+        "internal static extern IntPtr {}();\n\n",
+        extern_function_name(&convention.new_function)
+    );
 
-    writer.unindent();
-    emit!(writer, "}}\n");
+    for name in &[&convention.cancel_function, &convention.free_function] {
+        emit_dll_import(writer, context, "DllName", name);
+        emit!(
+            writer,
+            "internal static extern void {}(IntPtr token);\n\n",
+            extern_function_name(name)
+        );
+    }
+}
 
-    writer.unindent();
-    emit!(writer, "}}\n\n");
+pub fn unsafe_modifier(context: &Context) -> &'static str {
+    if context.blittable { "unsafe " } else { "" }
 }
 
 pub fn emit_utilities(writer: &mut IndentedWriter, context: &Context) {
     let content = include_str!("../../resources/csharp/Utils.cs.template");
     let content = content.replace("@Namespace", &context.utils_section.namespace);
     let content = content.replace("@Class", &context.utils_section.class);
+    let content = content.replace("@StringType", &string_unmanaged_type(context, false));
+    let content = content.replace("@ResultFields", &result_fields(context, false));
+    let content = content.replace("@ResultNativeFields", &result_fields(context, true));
+    // Span tasks require unsafe code and `System.Memory`, so they're emitted only when used.
+    let content = content.replace(
+        "    @SpanTasks\n\n",
+        if context.span_functions.is_empty() {
+            ""
+        } else {
+            include_str!("../../resources/csharp/SpanTask.cs.template")
+        },
+    );
 
     emit!(writer, "{}", content);
 }
 
+// Fields of `FfiResult` (or of its native counterpart) in the order of the native struct.
+fn result_fields(context: &Context, native: bool) -> String {
+    let code = "public int ErrorCode;";
+    let description = if native {
+        "public IntPtr Description;".to_string()
+    } else if context.copies_strings() {
+        // Read through `FfiResultNative` only, so never marshaled by the runtime.
+        "public string Description;".to_string()
+    } else {
+        format!(
+            "[MarshalAs(UnmanagedType.{})]\n    public string Description;",
+            string_unmanaged_type(context, false)
+        )
+    };
+
+    if context.callbacks.description_first {
+        format!("{}\n    {}", description, code)
+    } else {
+        format!("{}\n    {}", code, description)
+    }
+}
+
 pub fn emit_docs(writer: &mut IndentedWriter, context: &Context, docs: &str) {
     if context.preserve_comments {
         emit!(writer, "{}", docs);
     }
 }
 
+/// Callback completing the task returned by the wrapper function.
+fn extract_task_callback(inputs: &[(String, Type)]) -> Option<&Function> {
+    if has_progress_callbacks(inputs) {
+        extract_result_callback(inputs)
+    } else {
+        extract_first_callback(inputs)
+    }
+}
+
+fn delegate_name(callback: &Function) -> String {
+    let mut writer = IndentedWriter::new(0);
+    emit_callback_wrapper_name(&mut writer, callback);
+    writer.into_inner()
+}
+
+fn progress_callback_wrapper_name(name: &str, index: usize) -> String {
+    format!("On{}Cb{}", name.to_pascal_case(), index)
+}
+
+fn output_param_name(name: &str, index: usize) -> String {
+    // Output callbacks and out-parameters are conventionally prefixed with `o_`.
+    if name.starts_with("o_") {
+        param_name(&name[2..], index)
+    } else {
+        param_name(name, index)
+    }
+}
+
+fn emit_progress_type(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    // Skip the user data param.
+    let params = &callback.inputs[1..];
+
+    if params.is_empty() {
+        emit!(writer, "Action");
+        return;
+    }
+
+    emit!(writer, "IProgress<");
+
+    if params.len() > 1 {
+        emit!(writer, "(");
+    }
+
+    for (index, &(_, ref ty)) in params.into_iter().enumerate() {
+        if index > 0 {
+            emit!(writer, ", ");
+        }
+
+        emit_type(writer, context, ty, Mode::Generic);
+    }
+
+    if params.len() > 1 {
+        emit!(writer, ")");
+    }
+
+    emit!(writer, ">");
+}
+
+fn safe_handle_name(name: &str) -> String {
+    format!("{}Handle", name)
+}
+
 fn extern_function_name(name: &str) -> String {
     let mut name = name.to_pascal_case();
     name.push_str("Native");
@@ -435,9 +2070,17 @@ fn emit_const_value(
         ConstValue::Bool(true) => emit!(writer, "true"),
         ConstValue::Bool(false) => emit!(writer, "false"),
         ConstValue::Char(value) => emit!(writer, "{:?}", value),
-        ConstValue::Int(value) => emit!(writer, "{}", value),
+        ConstValue::Int(value) => {
+            // Values of `u64` above `i64::MAX` are stored as their bit pattern.
+            if let Some(&Type::U64) = ty {
+                emit!(writer, "{}", value as u64)
+            } else {
+                emit!(writer, "{}", value)
+            }
+        }
         ConstValue::Float(ref value) => emit!(writer, "{}", value),
         ConstValue::String(ref value) => emit!(writer, "{:?}", value),
+        ConstValue::Expr(ref expr) => emit_int_expr(writer, expr, true),
         ConstValue::Array(ref elements) => {
             if let Some(&Type::Array(ref ty, ..)) = ty {
                 emit!(writer, "new ");
@@ -474,6 +2117,30 @@ fn emit_const_value(
     }
 }
 
+fn emit_int_expr(writer: &mut IndentedWriter, expr: &IntExpr, top_level: bool) {
+    match *expr {
+        IntExpr::Lit(value) => emit!(writer, "{}", value),
+        IntExpr::Const(ref name) => emit!(writer, "{}", name.to_pascal_case()),
+        IntExpr::Unary(op, ref expr) => {
+            emit!(writer, "{}", if let ast::UnOp::Not = op { "~" } else { "-" });
+            emit_int_expr(writer, expr, false);
+        }
+        IntExpr::Binary(op, ref left, ref right) => {
+            if !top_level {
+                emit!(writer, "(");
+            }
+
+            emit_int_expr(writer, left, false);
+            emit!(writer, " {} ", op.to_string());
+            emit_int_expr(writer, right, false);
+
+            if !top_level {
+                emit!(writer, ")");
+            }
+        }
+    }
+}
+
 fn emit_const_use(writer: &mut IndentedWriter, context: &Context, name: &str) {
     emit!(
         writer,
@@ -498,6 +2165,18 @@ fn emit_struct_field(
         if field.has_cap {
             emit!(writer, "public ulong {}Cap;\n", name);
         }
+    } else if context.needs_field_conversion(&field.ty) && mode == StructMode::Normal {
+        if let Type::Array(ref ty, ref size) = field.ty {
+            emit!(writer, "public fixed ");
+            emit_type(writer, context, ty, Mode::NormalStruct);
+            emit!(writer, " {}[", name);
+            emit_array_size(writer, context, size);
+            emit!(writer, "];\n");
+        } else {
+            emit!(writer, "public ");
+            emit_type(writer, context, &field.ty, Mode::NormalStruct);
+            emit!(writer, " {};\n", name);
+        }
     } else {
         if mode == StructMode::Normal {
             emit_marshal_as(writer, context, &field.ty, None, "\n");
@@ -509,43 +2188,73 @@ fn emit_struct_field(
     }
 }
 
-fn emit_wrapper_function_params(
-    writer: &mut IndentedWriter,
+fn emit_array_size(writer: &mut IndentedWriter, context: &Context, size: &ArraySize) {
+    match *size {
+        ArraySize::Lit(value) => emit!(writer, "{}", value),
+        ArraySize::Const(ref name) => {
+            emit!(writer, "(int) ");
+            emit_const_use(writer, context, name);
+        }
+        ArraySize::Dynamic => unreachable!(),
+    }
+}
+
+fn wrapper_function_params(
     context: &Context,
     params: &[(String, Type)],
-    skip_user_data: bool,
-) {
-    let mut index = 0;
+    skip_out: bool,
+    progress: bool,
+    skip_cancel_token: bool,
+) -> Vec<(String, String)> {
+    let mut result = Vec::new();
     for &(ref name, ref ty) in params {
         // Skip the user data pointer.
-        if skip_user_data && is_user_data(name, ty) {
+        if is_user_data(name, ty) {
             continue;
         }
 
-        // Skip callbacks.
-        if extract_callback(ty).is_some() {
+        // Skip the out-parameters returned from the function.
+        if skip_out && is_out_param(ty) {
             continue;
         }
 
-        if index > 0 {
-            emit!(writer, ", ");
+        // Skip the native cancellation token, created by the wrapper.
+        if skip_cancel_token && context.is_cancel_token(ty) {
+            continue;
         }
 
-        emit_type(writer, context, ty, Mode::WrapperFunc);
-        if name.is_empty() {
-            emit!(writer, " arg{}", index);
-        } else {
-            emit!(writer, " {}", name.to_camel_case());
+        let index = result.len();
+        let mut writer = IndentedWriter::new(0);
+
+        if let Some(callback) = extract_callback(ty) {
+            // Progress callbacks are exposed as `IProgress<T>` (or `Action`),
+            // other callbacks are skipped.
+            if progress && !is_result_callback(callback) {
+                emit_progress_type(&mut writer, context, callback);
+                result.push((writer.into_inner(), output_param_name(name, index)));
+            }
+
+            continue;
         }
 
-        index += 1;
+        emit_type(&mut writer, context, ty, Mode::WrapperFunc);
+        let name = if name.is_empty() {
+            format!("arg{}", index)
+        } else {
+            name.to_camel_case()
+        };
+
+        result.push((writer.into_inner(), name));
     }
+
+    result
 }
 
 fn emit_native_function_params(
     writer: &mut IndentedWriter,
     context: &Context,
     params: &[(String, Type)],
+    spans: bool,
 ) {
     let mut index = 0;
     for &(ref name, ref ty) in params {
@@ -553,10 +2262,40 @@ fn emit_native_function_params(
             emit!(writer, ", ");
         }
 
+        // Pinned spans are passed as raw pointers.
+        if spans && span_elem_type(ty).is_some() {
+            emit!(writer, "IntPtr {0}, ulong {0}Len", param_name(name, index));
+            index += 1;
+            continue;
+        }
+
+        if context.is_cancel_token(ty) {
+            emit!(writer, "IntPtr {}", param_name(name, index));
+            index += 1;
+            continue;
+        }
+
+        // Params converted by the wrapper function, to avoid runtime marshalling.
+        if let Some(native) = blittable_param_type(context, ty) {
+            let name = param_name(name, index);
+            emit!(writer, "{} {}", native, name);
+
+            if ty.is_dynamic_array() {
+                emit!(writer, ", ulong {}Len", name);
+            }
+
+            index += 1;
+            continue;
+        }
+
         emit_marshal_as(writer, context, ty, Some(index), " ");
 
         if let Some(callback) = extract_callback(ty) {
-            emit_callback_wrapper_name(writer, &callback);
+            if context.blittable {
+                emit_function_pointer_type(writer, context, callback);
+            } else {
+                emit_callback_wrapper_name(writer, &callback);
+            }
         } else {
             emit_type(writer, context, ty, Mode::ExternFunc);
         }
@@ -572,9 +2311,33 @@ fn emit_native_function_params(
     }
 }
 
-fn emit_callback_params(writer: &mut IndentedWriter, context: &Context, params: &[(String, Type)]) {
-    for (index, &(ref name, ref ty)) in params.into_iter().enumerate() {
-        if index > 0 {
+// Function pointer type of the callback, used instead of delegates in the blittable mode.
+fn emit_function_pointer_type(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    if let Some(convention) = unmanaged_calling_convention(context) {
+        emit!(writer, "delegate* unmanaged[{}]<", convention);
+    } else {
+        emit!(writer, "delegate* unmanaged<");
+    }
+
+    for (_, &(_, ref ty)) in callback.native_inputs() {
+        if let Type::Array(_, ArraySize::Dynamic) = *ty {
+            emit!(writer, "IntPtr, ulong, ");
+        } else {
+            emit_type(writer, context, ty, Mode::Callback);
+            emit!(writer, ", ");
+        }
+    }
+
+    emit_type(writer, context, &callback.output, Mode::Callback);
+    emit!(writer, ">");
+}
+
+// Emit the params of the callback in the order of its native signature.
+fn emit_callback_params(writer: &mut IndentedWriter, context: &Context, callback: &Function) {
+    for (position, (index, &(ref name, ref ty))) in
+        callback.native_inputs().into_iter().enumerate()
+    {
+        if position > 0 {
             emit!(writer, ", ");
         }
 
@@ -583,6 +2346,15 @@ fn emit_callback_params(writer: &mut IndentedWriter, context: &Context, params:
         if let Type::Array(_, ArraySize::Dynamic) = *ty {
             emit!(writer, "IntPtr {0}Ptr, ulong {0}Len", name);
         } else {
+            if let (&Type::String, false) = (ty, context.blittable) {
+                // Strings passed to callbacks are owned by the native code.
+                emit!(
+                    writer,
+                    "[MarshalAs(UnmanagedType.{})] ",
+                    borrowed_string_unmanaged_type(context)
+                );
+            }
+
             emit_type(writer, context, ty, Mode::Callback);
             emit!(writer, " {}", name);
         }
@@ -604,7 +2376,12 @@ fn emit_marshal_as(
     index: Option<usize>,
     append: &str,
 ) {
-    if let Some(unmanaged) = unmanaged_type(ty, index.is_none()) {
+    let ty = match *ty {
+        Type::Nullable(ref ty) => ty,
+        _ => ty,
+    };
+
+    if let Some(unmanaged) = unmanaged_type(context, ty, index.is_none()) {
         emit!(writer, "[MarshalAs(UnmanagedType.{}", unmanaged);
 
         match *ty {
@@ -623,22 +2400,60 @@ fn emit_marshal_as(
     }
 }
 
-fn unmanaged_type(ty: &Type, field: bool) -> Option<&str> {
-    match *ty {
-        Type::Bool => Some("U1"),
-        // TODO: consider marshaling as "LPUTF8Str", is possible and useful.
-        Type::String => Some("LPStr"),
-        Type::Array(_, ArraySize::Dynamic) => Some("LPArray"),
-        Type::Array(..) if field => Some("ByValArray"),
-        Type::Array(..) => Some("LPArray"),
+fn unmanaged_type(context: &Context, ty: &Type, field: bool) -> Option<String> {
+    let result = match *ty {
+        Type::Nullable(ref ty) => return unmanaged_type(context, ty, field),
+        Type::Out(ref ty) => {
+            match **ty {
+                Type::Bool => "U1",
+                _ => return None,
+            }
+        }
+        Type::Bool => "U1",
+        Type::String => return Some(string_unmanaged_type(context, !field)),
+        Type::Array(_, ArraySize::Dynamic) => "LPArray",
+        Type::Array(..) if field => "ByValArray",
+        Type::Array(..) => "LPArray",
         Type::Pointer(ref ty) => {
             if let Type::Array(_, _) = **ty {
-                Some("LPArray")
+                "LPArray"
             } else {
-                None
+                return None;
             }
         }
-        _ => None,
+        _ => return None,
+    };
+
+    Some(result.to_string())
+}
+
+// Unmanaged type of strings passed from managed to native code. Custom marshalers
+// can be used only for function parameters, not for struct fields or array elements.
+fn string_unmanaged_type(context: &Context, custom_allowed: bool) -> String {
+    match context.string_encoding {
+        StringEncoding::Utf8 => "LPUTF8Str".to_string(),
+        StringEncoding::Utf8Marshaler if custom_allowed => {
+            format!(
+                "CustomMarshaler, MarshalTypeRef = typeof({}.Utf8Marshaler)",
+                context.utils_section.class
+            )
+        }
+        StringEncoding::Utf8Marshaler | StringEncoding::Ansi => "LPStr".to_string(),
+    }
+}
+
+// Unmanaged type of strings passed from native to managed code. The marshaler
+// must not free them, as they are still owned by the native code.
+fn borrowed_string_unmanaged_type(context: &Context) -> String {
+    match context.string_encoding {
+        StringEncoding::Utf8Marshaler => {
+            format!(
+                "CustomMarshaler, MarshalTypeRef = typeof({}.Utf8Marshaler), \
+                 MarshalCookie = \"borrowed\"",
+                context.utils_section.class
+            )
+        }
+        _ => string_unmanaged_type(context, true),
     }
 }
 
@@ -649,7 +2464,13 @@ fn emit_array_marshal_as(
     size: &ArraySize,
     index: Option<usize>,
 ) {
-    if let Some(unmanaged) = unmanaged_type(ty, false) {
+    let unmanaged = if let Type::String = *ty {
+        Some(string_unmanaged_type(context, false))
+    } else {
+        unmanaged_type(context, ty, false)
+    };
+
+    if let Some(unmanaged) = unmanaged {
         emit!(writer, ", ArraySubType = UnmanagedType.{}", unmanaged);
     }
 
@@ -702,9 +2523,15 @@ impl From<StructMode> for Mode {
     }
 }
 
+// Is the mode one where blittable types must be used in the blittable mode?
+fn is_native_mode(mode: Mode) -> bool {
+    mode == Mode::Callback || mode == Mode::NormalStruct
+}
+
 fn emit_type(writer: &mut IndentedWriter, context: &Context, ty: &Type, mode: Mode) {
     match *ty {
         Type::Unit => emit!(writer, "void"),
+        Type::Bool if context.blittable && is_native_mode(mode) => emit!(writer, "byte"),
         Type::Bool => emit!(writer, "bool"),
         Type::CChar => emit!(writer, "sbyte"),
         Type::Char => emit!(writer, "char"),
@@ -720,19 +2547,36 @@ fn emit_type(writer: &mut IndentedWriter, context: &Context, ty: &Type, mode: Mo
         Type::U32 => emit!(writer, "uint"),
         Type::U64 => emit!(writer, "ulong"),
         Type::USize => emit!(writer, "ulong"),
+        Type::String if context.blittable && is_native_mode(mode) ||
+                            context.copies_strings() && mode == Mode::NormalStruct => {
+            emit!(writer, "IntPtr")
+        }
         Type::String => emit!(writer, "string"),
         Type::Pointer(ref ty) => {
             match **ty {
                 Type::Array(ref ty, ref size) => emit_array(writer, context, ty, size, mode),
                 Type::User(ref name) => {
                     match mode {
+                        Mode::WrapperStruct if context.is_struct(name) => {
+                            if context.is_class(name) {
+                                emit!(writer, "{}", name)
+                            } else {
+                                emit!(writer, "{}?", name)
+                            }
+                        }
                         Mode::Callback | Mode::Const | Mode::NormalStruct | Mode::WrapperStruct => {
                             emit!(writer, "IntPtr")
                         }
+                        Mode::Generic if context.has_safe_handle(name) => {
+                            emit!(writer, "{}", safe_handle_name(name))
+                        }
                         Mode::Generic => emit!(writer, "{}", name),
                         Mode::ExternFunc if context.is_native_name(name) => {
                             emit!(writer, "ref {}Native", name);
                         }
+                        _ if context.has_safe_handle(name) => {
+                            emit!(writer, "{}", safe_handle_name(name))
+                        }
                         _ if context.is_opaque(name) => emit!(writer, "IntPtr"),
                         _ => {
                             emit!(writer, "ref {}", name);
@@ -751,6 +2595,33 @@ fn emit_type(writer: &mut IndentedWriter, context: &Context, ty: &Type, mode: Mo
         }
         Type::Array(ref ty, ref size) => emit_array(writer, context, ty, size, mode),
         Type::Function(..) => unimplemented!(),
+        Type::Out(ref ty) => {
+            emit!(writer, "out ");
+            emit_out_type(writer, context, ty, mode);
+        }
+        Type::Nullable(ref ty) => {
+            match (context.pointed_struct(ty), mode) {
+                (Some(name), Mode::WrapperFunc) |
+                (Some(name), Mode::Generic) => {
+                    if context.is_class(name) {
+                        emit!(writer, "{}", name)
+                    } else {
+                        emit!(writer, "{}?", name)
+                    }
+                }
+                (Some(_), Mode::ExternFunc) => emit!(writer, "IntPtr"),
+                _ => {
+                    emit_type(writer, context, ty, mode);
+
+                    // Annotate references if nullable reference types are enabled.
+                    if mode == Mode::WrapperFunc && context.platform.nullable == Some(true) &&
+                        is_null_checked(context, ty)
+                    {
+                        emit!(writer, "?");
+                    }
+                }
+            }
+        }
         Type::User(ref name) => {
             if context.is_native_name(name) &&
                 (mode == Mode::Callback || mode == Mode::Const || mode == Mode::ExternFunc ||
@@ -805,6 +2676,21 @@ fn emit_args(
                     Type::Array(ref ty, ref size) => {
                         emit_array_use(writer, context, ty, size, &name)
                     }
+                    // Pointers are borrowed, unless the callback takes their ownership.
+                    Type::User(ref type_name) if context.has_safe_handle(type_name) => {
+                        emit!(
+                            writer,
+                            "new {}({}, {})",
+                            safe_handle_name(type_name),
+                            name,
+                            context.owns_callback_handle(type_name)
+                        );
+                    }
+                    // The wrapper takes ownership of the native struct.
+                    Type::User(ref type_name)
+                        if context.disposable_destructor(type_name).is_some() => {
+                        emit!(writer, "new {}({})", type_name, name);
+                    }
                     Type::User(ref type_name) if context.is_native_name(type_name) => {
                         emit!(writer, "new {}(", type_name);
                         emit_pointer_use(writer, context, ty, &name, mode);
@@ -816,6 +2702,12 @@ fn emit_args(
             Type::User(ref type_name) if context.is_native_name(type_name) => {
                 emit!(writer, "new {}({})", type_name, name);
             }
+            Type::Bool if context.blittable && mode == Mode::Callback => {
+                emit!(writer, "{} != 0", name)
+            }
+            Type::String if context.blittable && mode == Mode::Callback => {
+                emit!(writer, "{}.CopyToString({})", context.utils_section.class, name)
+            }
             _ => emit!(writer, "{}", name),
         }
     }
@@ -834,6 +2726,26 @@ fn emit_pointer_use(
                    mode == Mode::ExternFunc && !context.is_opaque(pointee) => {
             emit!(writer, "ref {}", name);
         }
+        Type::User(ref pointee)
+            if mode == Mode::Callback && context.blittable && !context.is_opaque(pointee) => {
+            // `FfiResult` contains a string, so it's read through its native counterpart.
+            if pointee == "FfiResult" {
+                emit!(writer, "new {0}(*({0}Native*) {1})", pointee, name);
+            } else if context.is_native_name(pointee) {
+                emit!(writer, "*({}Native*) {}", pointee, name);
+            } else {
+                emit!(writer, "*({}*) {}", pointee, name);
+            }
+        }
+        Type::User(ref pointee) if mode == Mode::Callback && pointee == "FfiResult" &&
+                                       context.copies_strings() => {
+            emit!(
+                writer,
+                "new {0}(Marshal.PtrToStructure<{0}Native>({1}))",
+                pointee,
+                name
+            );
+        }
         Type::User(ref pointee) if mode == Mode::Callback => {
             emit!(writer, "Marshal.PtrToStructure<{}", pointee);
 
@@ -1,8 +1,10 @@
 //! Types and utilities for the intermediate representation between the rust code
 //! and the target language code.
 
-use common;
+use common::{self, CallbackConvention};
+use const_eval::ConstEvaluator;
 use std::collections::BTreeMap;
+use std::mem;
 use syntax::ast;
 use syntax::print::pprust;
 use syntax::ptr;
@@ -40,6 +42,11 @@ pub enum Type {
     Array(Box<Type>, ArraySize),
     Function(Box<Function>),
     User(String),
+    /// String or pointer which can be null. Only parameters of functions are nullable,
+    /// pointers in struct fields and callback parameters are nullable implicitly.
+    Nullable(Box<Type>),
+    /// Out-parameter of a function, holding the type of the value written by the function.
+    Out(Box<Type>),
 }
 
 impl Type {
@@ -50,6 +57,30 @@ impl Type {
             false
         }
     }
+
+    /// Wrap the type in `Nullable` if it's a string or pointer.
+    pub fn into_nullable(self) -> Type {
+        match self {
+            ty @ Type::String | ty @ Type::Pointer(_) => Type::Nullable(Box::new(ty)),
+            ty => ty,
+        }
+    }
+
+    /// Turn a pointer into an out-parameter writing the type it points to.
+    pub fn into_out(self) -> Type {
+        match self {
+            Type::Pointer(ty) => Type::Out(ty),
+            ty => ty,
+        }
+    }
+
+    /// Type without the `Nullable` wrapper.
+    pub fn into_non_nullable(self) -> Type {
+        match self {
+            Type::Nullable(ty) => *ty,
+            ty => ty,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -66,12 +97,105 @@ pub enum ArraySize {
 pub struct Function {
     pub inputs: Vec<(String, Type)>,
     pub output: Type,
+    /// Positions of the inputs in the native signature, if they were reordered
+    /// by `apply_callback_convention`.
+    pub native_positions: Option<Vec<usize>>,
+}
+
+impl Function {
+    /// Normalise the function according to the callback convention: its user data
+    /// is renamed to `user_data` and the inputs of its callbacks are reordered so that
+    /// the user data comes first, followed by the result (as `FfiResult`). The native
+    /// order is kept in `native_positions`.
+    pub fn apply_callback_convention(&mut self, convention: &CallbackConvention) {
+        for &mut (ref mut name, ref mut ty) in &mut self.inputs {
+            if is_convention_user_data(name, ty, convention) {
+                *name = "user_data".to_string();
+            } else if let Type::Function(ref mut callback) = *ty {
+                callback.reorder_callback_inputs(convention);
+            }
+        }
+    }
+
+    fn reorder_callback_inputs(&mut self, convention: &CallbackConvention) {
+        let user_data = convention.user_data_index;
+        let result = convention.result_index;
+
+        match self.inputs.get(user_data) {
+            Some(&(ref name, ref ty)) if is_convention_user_data(name, ty, convention) => (),
+            _ => return,
+        }
+
+        let has_result = match self.inputs.get(result) {
+            Some(&(ref name, ref ty)) if result != user_data => {
+                is_convention_result(name, ty, convention)
+            }
+            _ => false,
+        };
+
+        let mut order = vec![user_data];
+        if has_result {
+            order.push(result);
+        }
+        order.extend((0..self.inputs.len()).filter(|index| !order.contains(index)));
+
+        let mut inputs: Vec<_> = self.inputs.drain(..).map(Some).collect();
+        self.inputs = order
+            .iter()
+            .map(|&index| inputs[index].take().expect("each input is moved once"))
+            .collect();
+
+        if !self.inputs[0].0.is_empty() {
+            self.inputs[0].0 = "user_data".to_string();
+        }
+        if has_result {
+            self.inputs[1].1 = Type::Pointer(Box::new(Type::User("FfiResult".to_string())));
+        }
+
+        if order.iter().enumerate().any(|(position, &index)| position != index) {
+            self.native_positions = Some(order);
+        }
+    }
+
+    /// Inputs with their indices, in the order of the native signature.
+    pub fn native_inputs(&self) -> Vec<(usize, &(String, Type))> {
+        let mut inputs: Vec<_> = self.inputs.iter().enumerate().collect();
+        if let Some(ref positions) = self.native_positions {
+            inputs.sort_by_key(|&(index, _)| positions[index]);
+        }
+        inputs
+    }
+}
+
+/// Is the given parameter the user data according to the callback convention?
+fn is_convention_user_data(name: &str, ty: &Type, convention: &CallbackConvention) -> bool {
+    if let Type::Pointer(ref ty) = *ty {
+        if let Type::Unit = **ty {
+            return name == "" || name == convention.user_data_name;
+        }
+    }
+
+    false
+}
+
+/// Is the given parameter the result according to the callback convention?
+fn is_convention_result(name: &str, ty: &Type, convention: &CallbackConvention) -> bool {
+    if let Type::Pointer(ref ty) = *ty {
+        if let Type::User(ref type_name) = **ty {
+            return (name == "" || name == convention.result_name) &&
+                *type_name == convention.result_type;
+        }
+    }
+
+    false
 }
 
 pub struct Snippet<T> {
     pub docs: String,
     pub name: String,
     pub item: T,
+    /// Path of the Rust module the item is defined in (e.g. `ffi::mdata`).
+    pub module: String,
 }
 
 pub struct Const {
@@ -87,6 +211,17 @@ pub enum ConstValue {
     String(String),
     Array(Vec<ConstValue>),
     Struct(String, BTreeMap<String, ConstValue>),
+    Expr(IntExpr),
+}
+
+/// Integer constant expression, e.g. `1 << 2` or `PERM_READ | PERM_WRITE`, emitted
+/// as is. Its value is computed by `ConstEvaluator` where needed.
+#[derive(Clone, Debug)]
+pub enum IntExpr {
+    Lit(i64),
+    Const(String),
+    Unary(ast::UnOp, Box<IntExpr>),
+    Binary(ast::BinOpKind, Box<IntExpr>, Box<IntExpr>),
 }
 
 pub struct Struct {
@@ -103,35 +238,48 @@ pub struct StructField {
 
 pub struct Enum {
     pub variants: Vec<EnumVariant>,
+    /// Whether the enum represents bit flags.
+    pub flags: bool,
+    /// Underlying type, if not the default one.
+    pub ty: Option<Type>,
 }
 
 pub struct EnumVariant {
     pub docs: String,
     pub name: String,
-    pub value: Option<i64>,
+    pub value: Option<IntExpr>,
 }
 
 
-pub fn transform_type(input: &ast::Ty) -> Option<Type> {
+pub fn transform_type(input: &ast::Ty, evaluator: &ConstEvaluator) -> Option<Type> {
     match input.node {
-        ast::TyKind::Array(ref ty, ref size) => transform_array(ty, size),
-        ast::TyKind::Path(None, _) => transform_path(input),
-        ast::TyKind::Ptr(ref ptr) => transform_pointer(ptr),
-        ast::TyKind::Rptr(ref lifetime, ast::MutTy { ref ty, .. }) => {
-            transform_reference(lifetime, ty)
+        ast::TyKind::Array(ref ty, ref size) => transform_array(ty, size, evaluator),
+        ast::TyKind::Path(None, _) => {
+            if let Some(bare_fn) = common::nullable_fn(input) {
+                // Delegates can be null.
+                transform_callback(&*bare_fn.decl, evaluator)
+            } else if let Some(ty) = common::nullable_pointee(input) {
+                transform_pointee(ty, evaluator).map(Type::into_nullable)
+            } else if let Some(ty) = common::generic_param(input, "NonNull") {
+                transform_pointee(ty, evaluator)
+            } else {
+                transform_path(input)
+            }
         }
-        ast::TyKind::BareFn(ref bare_fn) => {
-            transform_function(&*bare_fn.decl).map(|fun| Type::Function(Box::new(fun)))
+        ast::TyKind::Ptr(ref ptr) => transform_pointer(ptr, evaluator),
+        ast::TyKind::Rptr(ref lifetime, ast::MutTy { ref ty, .. }) => {
+            transform_reference(lifetime, ty, evaluator)
         }
+        ast::TyKind::BareFn(ref bare_fn) => transform_callback(&*bare_fn.decl, evaluator),
         _ => None,
     }
 }
 
-pub fn transform_function(decl: &ast::FnDecl) -> Option<Function> {
+pub fn transform_function(decl: &ast::FnDecl, evaluator: &ConstEvaluator) -> Option<Function> {
     let output = match decl.output {
         ast::FunctionRetTy::Default(..) => Type::Unit,
         ast::FunctionRetTy::Ty(ref ty) => {
-            match transform_type(ty) {
+            match transform_type(ty, evaluator) {
                 Some(ty) => ty,
                 None => return None,
             }
@@ -148,14 +296,14 @@ pub fn transform_function(decl: &ast::FnDecl) -> Option<Function> {
             Some(param)
         } else {
             if let Some(arg) = iter.next() {
-                Some(try_opt!(transform_function_param(arg)))
+                Some(try_opt!(transform_function_param(arg, evaluator)))
             } else {
                 None
             }
         };
 
         let two = if let Some(arg) = iter.next() {
-            Some(try_opt!(transform_function_param(arg)))
+            Some(try_opt!(transform_function_param(arg, evaluator)))
         } else {
             None
         };
@@ -183,11 +331,27 @@ pub fn transform_function(decl: &ast::FnDecl) -> Option<Function> {
         }
     }
 
-    Some(Function { inputs, output })
+    Some(Function {
+        inputs,
+        output,
+        native_positions: None,
+    })
 }
 
-pub fn transform_function_param(arg: &ast::Arg) -> Option<(String, Type)> {
-    if let Some(ty) = transform_type(&*arg.ty) {
+fn transform_callback(decl: &ast::FnDecl, evaluator: &ConstEvaluator) -> Option<Type> {
+    let mut fun = try_opt!(transform_function(decl, evaluator));
+    for &mut (_, ref mut ty) in &mut fun.inputs {
+        *ty = mem::replace(ty, Type::Unit).into_non_nullable();
+    }
+
+    Some(Type::Function(Box::new(fun)))
+}
+
+pub fn transform_function_param(
+    arg: &ast::Arg,
+    evaluator: &ConstEvaluator,
+) -> Option<(String, Type)> {
+    if let Some(ty) = transform_type(&*arg.ty, evaluator) {
         let name = pprust::pat_to_string(&*arg.pat);
         Some((name, ty))
     } else {
@@ -195,14 +359,27 @@ pub fn transform_function_param(arg: &ast::Arg) -> Option<(String, Type)> {
     }
 }
 
-pub fn transform_const(ty: &ast::Ty, value: &ast::Expr) -> Option<Const> {
-    let ty = try_opt!(transform_type(ty));
-    let value = try_opt!(transform_const_value(value));
+pub fn transform_const(
+    ty: &ast::Ty,
+    value: &ast::Expr,
+    evaluator: &ConstEvaluator,
+) -> Option<Const> {
+    let const_ty = try_opt!(transform_const_type(ty, evaluator));
+
+    // The result of `!` and casts depends on the type, so they are evaluated
+    // in the declared type instead of being translated to C#.
+    let value = match const_ty {
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::ISize | Type::U8 | Type::U16 |
+        Type::U32 | Type::U64 | Type::USize if depends_on_type(value) => {
+            ConstValue::Int(try_opt!(evaluator.eval_as(value, ty)))
+        }
+        _ => try_opt!(transform_const_value(value, evaluator)),
+    };
 
-    Some(Const { ty, value })
+    Some(Const { ty: const_ty, value })
 }
 
-pub fn transform_enum(variants: &[ast::Variant]) -> Option<Enum> {
+pub fn transform_enum(variants: &[ast::Variant], evaluator: &ConstEvaluator) -> Option<Enum> {
     let variants: Option<Vec<_>> = variants
         .into_iter()
         .map(|variant| {
@@ -212,22 +389,39 @@ pub fn transform_enum(variants: &[ast::Variant]) -> Option<Enum> {
 
             let (_, docs) = common::parse_attr(&variant.node.attrs, |_| true, retrieve_docstring);
             let name = variant.node.name.name.as_str().to_string();
-            let value = extract_enum_variant_value(variant);
+            let value = match variant.node.disr_expr {
+                Some(ref expr) if depends_on_type(expr) => {
+                    Some(IntExpr::Lit(try_opt!(evaluator.eval(expr))))
+                }
+                Some(ref expr) => {
+                    let value = transform_int_expr(expr).or_else(
+                        || evaluator.eval(expr).map(IntExpr::Lit),
+                    );
+                    Some(try_opt!(value))
+                }
+                None => None,
+            };
 
             Some(EnumVariant { docs, name, value })
         })
         .collect();
 
-    variants.map(|variants| Enum { variants })
+    variants.map(|variants| {
+        Enum {
+            variants,
+            flags: false,
+            ty: None,
+        }
+    })
 }
 
-pub fn transform_struct(fields: &[ast::StructField]) -> Option<Struct> {
+pub fn transform_struct(fields: &[ast::StructField], evaluator: &ConstEvaluator) -> Option<Struct> {
     let fields: Option<Vec<_>> = fields
         .into_iter()
         .map(|field| {
             let (_, docs) = common::parse_attr(&field.attrs, |_| true, retrieve_docstring);
             let name = field.ident.unwrap().name.as_str().to_string();
-            let ty = try_opt!(transform_type(&field.ty));
+            let ty = try_opt!(transform_type(&field.ty, evaluator)).into_non_nullable();
 
             Some(StructField {
                 docs,
@@ -273,6 +467,37 @@ pub fn num_callbacks(inputs: &[(String, Type)]) -> usize {
         .count()
 }
 
+/// Does the callback report the result of the operation (i.e. is its second
+/// parameter a `FfiResult`)?
+pub fn is_result_callback(callback: &Function) -> bool {
+    if let Some(&(_, Type::Pointer(ref ty))) = callback.inputs.get(1) {
+        if let Type::User(ref name) = **ty {
+            return name == "FfiResult";
+        }
+    }
+
+    false
+}
+
+/// Does the function take progress callbacks in addition to a single callback
+/// reporting the result?
+pub fn has_progress_callbacks(inputs: &[(String, Type)]) -> bool {
+    let callbacks = extract_callbacks(inputs);
+
+    callbacks.len() > 1 &&
+        callbacks
+            .into_iter()
+            .filter(|callback| is_result_callback(callback))
+            .count() == 1
+}
+
+pub fn extract_result_callback(inputs: &[(String, Type)]) -> Option<&Function> {
+    inputs
+        .into_iter()
+        .filter_map(|&(_, ref ty)| extract_callback(ty))
+        .find(|callback| is_result_callback(callback))
+}
+
 pub fn extract_callback(ty: &Type) -> Option<&Function> {
     if let Type::Function(ref fun) = *ty {
         let &(ref name, ref ty) = try_opt!(fun.inputs.get(0));
@@ -289,13 +514,78 @@ pub fn retrieve_docstring(attr: &ast::Attribute) -> Option<String> {
     common::retrieve_docstring(attr, "")
 }
 
-fn transform_const_value(expr: &ast::Expr) -> Option<ConstValue> {
+fn transform_const_value(expr: &ast::Expr, evaluator: &ConstEvaluator) -> Option<ConstValue> {
     match expr.node {
         ast::ExprKind::Lit(ref lit) => transform_const_literal(lit),
-        ast::ExprKind::Array(ref elements) => transform_const_array(elements),
-        ast::ExprKind::Struct(ref path, ref fields, None) => transform_const_struct(path, fields),
-        ast::ExprKind::AddrOf(_, ref expr) => transform_const_value(expr),
-        ast::ExprKind::Cast(ref expr, ref ty) => transform_const_cast(expr, ty),
+        ast::ExprKind::Array(ref elements) => transform_const_array(elements, evaluator),
+        ast::ExprKind::Struct(ref path, ref fields, None) => {
+            transform_const_struct(path, fields, evaluator)
+        }
+        ast::ExprKind::AddrOf(_, ref expr) => transform_const_value(expr, evaluator),
+        ast::ExprKind::Cast(ref expr, ref ty) => transform_const_cast(expr, ty, evaluator),
+        _ => transform_int_value(expr, evaluator),
+    }
+}
+
+/// Transform integer expression, preserving its structure if possible and
+/// falling back to its evaluated value otherwise (e.g. for `size_of::<T>()`).
+fn transform_int_value(expr: &ast::Expr, evaluator: &ConstEvaluator) -> Option<ConstValue> {
+    transform_int_expr(expr).map(ConstValue::Expr).or_else(|| {
+        evaluator.eval(expr).map(ConstValue::Int)
+    })
+}
+
+/// Does the integer expression contain operations whose result depends on its
+/// type (`!` and casts)? These can't be translated to C# as they are.
+fn depends_on_type(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::ExprKind::Cast(..) |
+        ast::ExprKind::Unary(ast::UnOp::Not, _) => true,
+        ast::ExprKind::Paren(ref expr) |
+        ast::ExprKind::Unary(_, ref expr) => depends_on_type(expr),
+        ast::ExprKind::Binary(_, ref left, ref right) => {
+            depends_on_type(left) || depends_on_type(right)
+        }
+        _ => false,
+    }
+}
+
+fn transform_int_expr(expr: &ast::Expr) -> Option<IntExpr> {
+    match expr.node {
+        ast::ExprKind::Lit(ref lit) => extract_int_literal(lit).map(IntExpr::Lit),
+        ast::ExprKind::Path(None, ref path) => {
+            // Currently supports only unqualified constants.
+            if path.segments.len() > 1 || path.segments[0].parameters.is_some() {
+                None
+            } else {
+                Some(IntExpr::Const(
+                    path.segments[0].identifier.name.as_str().to_string(),
+                ))
+            }
+        }
+        ast::ExprKind::Paren(ref expr) => transform_int_expr(expr),
+        ast::ExprKind::Cast(ref expr, _) => transform_int_expr(expr),
+        ast::ExprKind::Unary(op, ref expr) => {
+            if let ast::UnOp::Deref = op {
+                return None;
+            }
+
+            Some(IntExpr::Unary(op, Box::new(try_opt!(transform_int_expr(expr)))))
+        }
+        ast::ExprKind::Binary(op, ref left, ref right) => {
+            match op.node {
+                ast::BinOpKind::Add | ast::BinOpKind::Sub | ast::BinOpKind::Mul |
+                ast::BinOpKind::Div | ast::BinOpKind::Rem | ast::BinOpKind::BitAnd |
+                ast::BinOpKind::BitOr | ast::BinOpKind::BitXor | ast::BinOpKind::Shl |
+                ast::BinOpKind::Shr => {
+                    let left = try_opt!(transform_int_expr(left));
+                    let right = try_opt!(transform_int_expr(right));
+
+                    Some(IntExpr::Binary(op.node, Box::new(left), Box::new(right)))
+                }
+                _ => None,
+            }
+        }
         _ => None,
     }
 }
@@ -309,28 +599,43 @@ fn transform_const_literal(lit: &ast::Lit) -> Option<ConstValue> {
         ast::LitKind::Float(ref value, _) |
         ast::LitKind::FloatUnsuffixed(ref value) => ConstValue::Float(value.as_str().to_string()),
         ast::LitKind::Str(ref value, ..) => ConstValue::String(value.as_str().to_string()),
-        // TODO: LitKind::ByteStr
+        ast::LitKind::ByteStr(ref value) => {
+            ConstValue::Array(
+                value
+                    .iter()
+                    .map(|byte| ConstValue::Int(*byte as i64))
+                    .collect(),
+            )
+        }
         _ => return None,
     };
 
     Some(result)
 }
 
-fn transform_const_array(array: &[ptr::P<ast::Expr>]) -> Option<ConstValue> {
+fn transform_const_array(
+    array: &[ptr::P<ast::Expr>],
+    evaluator: &ConstEvaluator,
+) -> Option<ConstValue> {
     let elements: Option<Vec<_>> = array
         .into_iter()
-        .map(|expr| transform_const_value(expr))
+        .map(|expr| transform_const_value(expr, evaluator))
         .collect();
 
     elements.map(ConstValue::Array)
 }
 
-fn transform_const_struct(path: &ast::Path, fields: &[ast::Field]) -> Option<ConstValue> {
+fn transform_const_struct(
+    path: &ast::Path,
+    fields: &[ast::Field],
+    evaluator: &ConstEvaluator,
+) -> Option<ConstValue> {
     let name = pprust::path_to_string(path);
     let fields: Option<BTreeMap<_, _>> = fields
         .into_iter()
         .map(|field| if let Some(value) = transform_const_value(
             &*field.expr,
+            evaluator,
         )
         {
             let name = field.ident.node.name.as_str().to_string();
@@ -343,25 +648,31 @@ fn transform_const_struct(path: &ast::Path, fields: &[ast::Field]) -> Option<Con
     fields.map(|fields| ConstValue::Struct(name, fields))
 }
 
-fn transform_const_cast(expr: &ast::Expr, ty: &ast::Ty) -> Option<ConstValue> {
-    // Currently only supports null strings, e.g.: `0 as *const c_char`
+fn transform_const_cast(
+    expr: &ast::Expr,
+    ty: &ast::Ty,
+    evaluator: &ConstEvaluator,
+) -> Option<ConstValue> {
+    // Supports null strings, e.g.: `0 as *const c_char` and integer casts.
 
-    if let Some(Type::String) = transform_type(ty) {
+    if let Some(Type::String) = transform_type(ty, evaluator) {
         if &pprust::expr_to_string(expr) == "0" {
             return Some(ConstValue::String(String::new()));
         }
+
+        return None;
     }
 
-    None
+    transform_int_value(expr, evaluator)
 }
 
-fn transform_array(ty: &ast::Ty, size: &ast::Expr) -> Option<Type> {
-    let size = match extract_array_size(size) {
+fn transform_array(ty: &ast::Ty, size: &ast::Expr, evaluator: &ConstEvaluator) -> Option<Type> {
+    let size = match extract_array_size(size, evaluator) {
         None => return None,
         Some(size) => size,
     };
 
-    let ty = match transform_type(ty) {
+    let ty = match transform_type(ty, evaluator) {
         None => return None,
         Some(Type::Array { .. }) => return None, // multi-dimensional array not supported yet
         Some(ty) => ty,
@@ -370,7 +681,7 @@ fn transform_array(ty: &ast::Ty, size: &ast::Expr) -> Option<Type> {
     Some(Type::Array(Box::new(ty), size))
 }
 
-fn extract_array_size(expr: &ast::Expr) -> Option<ArraySize> {
+fn extract_array_size(expr: &ast::Expr, evaluator: &ConstEvaluator) -> Option<ArraySize> {
     match expr.node {
         ast::ExprKind::Lit(ref lit) => {
             extract_int_literal(lit).map(|value| ArraySize::Lit(value as usize))
@@ -385,7 +696,7 @@ fn extract_array_size(expr: &ast::Expr) -> Option<ArraySize> {
                 ))
             }
         }
-        _ => None,
+        _ => evaluator.eval(expr).map(|value| ArraySize::Lit(value as usize)),
     }
 }
 
@@ -459,15 +770,24 @@ fn transform_path(input: &ast::Ty) -> Option<Type> {
     Some(output)
 }
 
-fn transform_pointer(ptr: &ast::MutTy) -> Option<Type> {
-    match transform_type(&ptr.ty) {
+fn transform_pointer(ptr: &ast::MutTy, evaluator: &ConstEvaluator) -> Option<Type> {
+    transform_pointee(&ptr.ty, evaluator)
+}
+
+// Transform pointer to the given type.
+fn transform_pointee(ty: &ast::Ty, evaluator: &ConstEvaluator) -> Option<Type> {
+    match transform_type(ty, evaluator) {
         Some(Type::CChar) => Some(Type::String),
         Some(ty) => Some(Type::Pointer(Box::new(ty))),
         _ => None,
     }
 }
 
-fn transform_reference(lifetime: &Option<ast::Lifetime>, ty: &ast::Ty) -> Option<Type> {
+fn transform_reference(
+    lifetime: &Option<ast::Lifetime>,
+    ty: &ast::Ty,
+    evaluator: &ConstEvaluator,
+) -> Option<Type> {
     if lifetime
         .map(|lifetime| &*lifetime.name.as_str() != "'static")
         .unwrap_or(false)
@@ -475,21 +795,31 @@ fn transform_reference(lifetime: &Option<ast::Lifetime>, ty: &ast::Ty) -> Option
         return None;
     }
 
-    match transform_type(ty) {
+    match transform_type(ty, evaluator) {
         Some(Type::String) => Some(Type::String),
         Some(Type::User(name)) => Some(Type::User(name)),
         _ => None,
     }
 }
 
-fn extract_enum_variant_value(variant: &ast::Variant) -> Option<i64> {
-    if let Some(ref expr) = variant.node.disr_expr {
-        if let ast::ExprKind::Lit(ref lit) = expr.node {
-            return extract_int_literal(lit);
+/// Transform the type of a constant. Unlike other items, constants can be slices
+/// and arrays behind references (e.g. `&'static [u8]`), which are not FFI-safe.
+fn transform_const_type(ty: &ast::Ty, evaluator: &ConstEvaluator) -> Option<Type> {
+    if let ast::TyKind::Rptr(_, ast::MutTy { ty: ref pointee, .. }) = ty.node {
+        match pointee.node {
+            ast::TyKind::Slice(ref elem) => {
+                return transform_type(elem, evaluator).map(|elem| {
+                    Type::Array(Box::new(elem), ArraySize::Dynamic)
+                });
+            }
+            ast::TyKind::Array(ref elem, ref size) => {
+                return transform_array(elem, size, evaluator);
+            }
+            _ => (),
         }
     }
 
-    None
+    transform_type(ty, evaluator)
 }
 
 fn extract_int_literal(lit: &ast::Lit) -> Option<i64> {
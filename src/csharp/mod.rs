@@ -8,10 +8,11 @@ use self::emit::*;
 use self::intermediate::*;
 use Error;
 use Level;
-use common::{self, FilterMode, Lang, Outputs};
+use common::{self, CallbackConvention, CancelConvention, FilterMode, Lang, Outputs};
+use const_eval::ConstEvaluator;
 use inflector::Inflector;
 use output::IndentedWriter;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::collections::btree_map::Entry;
 use std::fmt::{Display, Write};
 use std::mem;
@@ -25,9 +26,13 @@ pub struct LangCSharp {
     filter: HashSet<String>,
     filter_mode: FilterMode,
     wrapper_function_blacklist: HashSet<String>,
+    flags_enums: HashSet<String>,
+    const_enums: Vec<(String, String)>,
+    const_evaluator: ConstEvaluator,
     consts_enabled: bool,
     types_enabled: bool,
     utils_enabled: bool,
+    mock_enabled: bool,
     context: Context,
     custom_consts: Vec<String>,
     consts: Vec<Snippet<Const>>,
@@ -35,6 +40,8 @@ pub struct LangCSharp {
     structs: Vec<Snippet<Struct>>,
     functions: Vec<Snippet<Function>>,
     aliases: HashMap<String, Type>,
+    module_sections: BTreeMap<String, Section>,
+    current_module: String,
 }
 
 pub struct Context {
@@ -44,20 +51,133 @@ pub struct Context {
     consts_section: Section,
     types_section: Section,
     utils_section: Section,
+    mock_section: Section,
     preserve_comments: bool,
-    opaque_types: HashSet<String>,
+    opaque_types: HashMap<String, Option<String>>,
     native_types: HashSet<String>,
+    safe_handles: BTreeMap<String, String>,
+    owned_callback_handles: HashSet<String>,
+    string_encoding: StringEncoding,
+    span_functions: HashSet<String>,
+    blittable: bool,
+    platform: Platform,
+    disposable_types: HashMap<String, Option<String>>,
+    allocator: (String, String),
+    consuming_functions: HashSet<String>,
+    transferable_types: HashSet<String>,
+    last_error: Option<String>,
+    string_free: Option<String>,
+    callbacks: CallbackConvention,
+    cancellation: Option<CancelConvention>,
+    alignments: HashMap<String, Alignment>,
+    structs: HashSet<String>,
+    recursive_types: HashSet<String>,
 }
 
 impl Context {
     pub fn is_opaque(&self, name: &str) -> bool {
-        self.opaque_types.contains(name)
+        self.opaque_types.contains_key(name)
+    }
+
+    /// Returns whether the opaque type is wrapped in a `SafeHandle`.
+    pub fn has_safe_handle(&self, name: &str) -> bool {
+        self.safe_handles.contains_key(name)
+    }
+
+    /// Returns whether callbacks take ownership of pointers to the opaque type, so the
+    /// `SafeHandle` releases them.
+    pub fn owns_callback_handle(&self, name: &str) -> bool {
+        self.owned_callback_handles.contains(name)
+    }
+
+    /// Returns whether the function is a destructor of a type wrapped in a `SafeHandle`.
+    pub fn is_safe_handle_destructor(&self, name: &str) -> bool {
+        self.safe_handles.values().any(|destructor| destructor == name)
+    }
+
+    /// Returns whether the wrapper of the native struct is a disposable class.
+    pub fn is_disposable(&self, name: &str) -> bool {
+        self.disposable_types.contains_key(name)
+    }
+
+    /// Function releasing the native struct owned by the disposable wrapper class.
+    pub fn disposable_destructor(&self, name: &str) -> Option<&str> {
+        self.disposable_types.get(name).and_then(
+            |destructor| destructor.as_ref().map(String::as_str),
+        )
+    }
+
+    /// Returns whether the function takes ownership of the vectors allocated by the Rust
+    /// allocator in the native structs passed to it.
+    pub fn is_consuming_function(&self, name: &str) -> bool {
+        self.consuming_functions.contains(name)
+    }
+
+    /// Returns whether the native struct contains vectors allocated by the Rust allocator,
+    /// directly or through other structs.
+    pub fn has_transferable_vectors(&self, name: &str) -> bool {
+        self.transferable_types.contains(name)
+    }
+
+    /// Returns whether the function is called only by the generated wrapper types.
+    pub fn is_destructor(&self, name: &str) -> bool {
+        self.is_safe_handle_destructor(name) ||
+            self.disposable_types.values().any(|destructor| {
+                destructor.as_ref().map_or(false, |destructor| destructor == name)
+            })
+    }
+
+    /// Returns whether the wrapper of the struct is emitted as a class. That is the case
+    /// for disposable types and for structs which contain themselves through nullable
+    /// fields, as C# structs can't.
+    pub fn is_class(&self, name: &str) -> bool {
+        self.is_disposable(name) || self.recursive_types.contains(name)
+    }
+
+    pub fn is_struct(&self, name: &str) -> bool {
+        self.structs.contains(name)
+    }
+
+    /// If the type is a pointer to a struct, returns name of the struct. Such struct
+    /// fields are nullable in the wrapper types.
+    pub fn pointed_struct<'a>(&self, ty: &'a Type) -> Option<&'a str> {
+        if let Type::Pointer(ref ty) = *ty {
+            if let Type::User(ref name) = **ty {
+                if self.is_struct(name) && !self.is_opaque(name) {
+                    return Some(name);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns whether to generate an overload of the function using spans.
+    pub fn is_span_function(&self, name: &str) -> bool {
+        self.span_functions.contains(name)
     }
 
     pub fn is_native_name(&self, name: &str) -> bool {
         self.native_types.contains(name)
     }
 
+    /// Returns whether struct field of the type has different managed and native
+    /// representation, converted by the wrapper struct.
+    pub fn needs_field_conversion(&self, ty: &Type) -> bool {
+        match *ty {
+            Type::String => self.copies_strings(),
+            Type::Bool => self.blittable,
+            _ => self.blittable && is_fixed_buffer(ty),
+        }
+    }
+
+    /// Returns whether strings in structs are copied from and to native memory
+    /// by the generated code instead of the runtime marshaler: in the blittable
+    /// mode, and with the custom UTF-8 marshaler, which can't be applied to fields.
+    pub fn copies_strings(&self) -> bool {
+        self.blittable || self.string_encoding == StringEncoding::Utf8Marshaler
+    }
+
     pub fn is_native_type(&self, ty: &Type) -> bool {
         match *ty {
             Type::Pointer(ref ty) => self.is_native_type(&*ty),
@@ -65,6 +185,185 @@ impl Context {
             _ => false,
         }
     }
+
+    /// Rust function allocating memory of vectors with capacity.
+    pub fn alloc_function(&self) -> &str {
+        &self.allocator.0
+    }
+
+    /// Rust function releasing memory of vectors with capacity.
+    pub fn free_function(&self) -> &str {
+        &self.allocator.1
+    }
+
+    /// Rust function returning description of the last error, if synchronous functions
+    /// returning `i32` report errors through it.
+    pub fn last_error_function(&self) -> Option<&str> {
+        self.last_error.as_ref().map(String::as_str)
+    }
+
+    /// Rust function releasing strings written to out-parameters, if configured.
+    pub fn string_free_function(&self) -> Option<&str> {
+        self.string_free.as_ref().map(String::as_str)
+    }
+
+    /// Conventions of asynchronous functions which can be cancelled, if enabled.
+    pub fn cancel_convention(&self) -> Option<&CancelConvention> {
+        self.cancellation.as_ref()
+    }
+
+    /// Returns whether the type is a pointer to the cancellation token.
+    pub fn is_cancel_token(&self, ty: &Type) -> bool {
+        match (ty, self.cancellation.as_ref()) {
+            (&Type::Pointer(ref ty), Some(convention)) => {
+                match **ty {
+                    Type::User(ref name) => *name == convention.token_type,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether the function creates, cancels or releases the cancellation tokens.
+    pub fn is_cancel_function(&self, name: &str) -> bool {
+        self.cancellation.as_ref().map_or(
+            false,
+            |convention| convention.is_token_function(name),
+        )
+    }
+
+    /// Alignment of the type in native memory. Returns `None` if it's not known yet.
+    pub fn alignment_of(&self, ty: &Type) -> Option<Alignment> {
+        match *ty {
+            Type::Bool | Type::CChar | Type::I8 | Type::U8 => Some(Alignment::Bytes(1)),
+            Type::I16 | Type::U16 => Some(Alignment::Bytes(2)),
+            Type::Char | Type::F32 | Type::I32 | Type::U32 => Some(Alignment::Bytes(4)),
+            Type::F64 | Type::I64 | Type::U64 => Some(Alignment::Int64),
+            Type::Array(ref ty, ArraySize::Lit(_)) |
+            Type::Array(ref ty, ArraySize::Const(_)) => self.alignment_of(ty),
+            Type::User(ref name) if !self.is_opaque(name) => {
+                self.alignments.get(name).cloned()
+            }
+            _ => Some(Alignment::Pointer),
+        }
+    }
+}
+
+/// Alignment of a type in native memory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alignment {
+    Bytes(usize),
+    /// Alignment of a pointer, which depends on the platform (4 or 8 bytes).
+    Pointer,
+    /// Alignment of 64-bit primitives, which is 4 bytes on 32-bit x86 and 8 bytes
+    /// on the other platforms (including 32-bit ARM).
+    Int64,
+}
+
+impl Alignment {
+    fn max(self, other: Alignment) -> Alignment {
+        match (self, other) {
+            (Alignment::Bytes(a), Alignment::Bytes(b)) => Alignment::Bytes(a.max(b)),
+            (Alignment::Bytes(a), other) |
+            (other, Alignment::Bytes(a)) => if a >= 8 { Alignment::Bytes(a) } else { other },
+            // 64-bit primitives are aligned at least as pointers on all platforms.
+            (Alignment::Int64, _) |
+            (_, Alignment::Int64) => Alignment::Int64,
+            _ => Alignment::Pointer,
+        }
+    }
+}
+
+/// Encoding used to marshal strings to and from native code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StringEncoding {
+    /// UTF-8, using `UnmanagedType.LPUTF8Str`.
+    Utf8,
+    /// UTF-8, using a custom marshaler. For runtimes which don't support `LPUTF8Str`.
+    /// Custom marshalers can't be applied to struct fields, so those are `IntPtr`s in
+    /// the native structs, converted by the wrapper structs. They can't be applied to
+    /// array elements either, so arrays of strings are rejected.
+    Utf8Marshaler,
+    /// ANSI, using `UnmanagedType.LPStr`.
+    Ansi,
+}
+
+/// Platform specific parts of the generated code. Presets for the supported platforms
+/// are provided, the default being `Platform::xamarin()`.
+#[derive(Clone, Debug)]
+pub struct Platform {
+    /// Names of the native library used when the preprocessor condition holds, checked
+    /// in order. The name set by `set_lib_name` is used if none of them holds.
+    pub lib_names: Vec<(String, String)>,
+    /// Attribute of the callback wrappers, with `{}` replaced by the name of the delegate.
+    pub callback_attribute: Option<String>,
+    /// Preprocessor condition under which the callback attribute is applied.
+    pub callback_attribute_condition: Option<String>,
+    /// Additional `using` directives of the generated files.
+    pub usings: Vec<String>,
+    /// Mark the public types with the JetBrains `[PublicAPI]` annotation.
+    pub annotations: bool,
+    /// Nullable context of the generated files: `Some(true)` emits `#nullable enable`,
+    /// `Some(false)` emits `#nullable disable` and `None` emits nothing.
+    pub nullable: Option<bool>,
+    /// Calling convention of the native functions and of the callbacks passed to them
+    /// (e.g. `Cdecl`). `None` uses the default of the platform, except for the function
+    /// pointers of the blittable mode, which default to `Cdecl`.
+    pub calling_convention: Option<String>,
+}
+
+impl Platform {
+    /// Xamarin (iOS and Android). On iOS, the native library is statically linked
+    /// and the callbacks need the `[MonoPInvokeCallback]` attribute.
+    pub fn xamarin() -> Self {
+        Platform {
+            lib_names: vec![("__IOS__".to_string(), "__Internal".to_string())],
+            callback_attribute: Some("MonoPInvokeCallback(typeof({}))".to_string()),
+            callback_attribute_condition: Some("__IOS__".to_string()),
+            usings: Vec::new(),
+            annotations: true,
+            nullable: None,
+            calling_convention: None,
+        }
+    }
+
+    /// Unity. IL2CPP requires the `[AOT.MonoPInvokeCallback]` attribute on all platforms.
+    pub fn unity() -> Self {
+        Platform {
+            lib_names: vec![
+                (
+                    "UNITY_IOS && !UNITY_EDITOR".to_string(),
+                    "__Internal".to_string()
+                ),
+            ],
+            callback_attribute: Some("AOT.MonoPInvokeCallback(typeof({}))".to_string()),
+            callback_attribute_condition: None,
+            usings: Vec::new(),
+            annotations: false,
+            nullable: None,
+            calling_convention: Some("Cdecl".to_string()),
+        }
+    }
+
+    /// Plain .NET Standard / .NET Core.
+    pub fn netstandard() -> Self {
+        Platform {
+            lib_names: Vec::new(),
+            callback_attribute: None,
+            callback_attribute_condition: None,
+            usings: Vec::new(),
+            annotations: false,
+            nullable: None,
+            calling_convention: Some("Cdecl".to_string()),
+        }
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self::xamarin()
+    }
 }
 
 pub struct Section {
@@ -95,9 +394,13 @@ impl LangCSharp {
             filter_mode: FilterMode::Blacklist,
             filter: Default::default(),
             wrapper_function_blacklist: Default::default(),
+            flags_enums: Default::default(),
+            const_enums: Vec::new(),
+            const_evaluator: ConstEvaluator::new(),
             consts_enabled: true,
             types_enabled: true,
             utils_enabled: true,
+            mock_enabled: false,
             context: Context {
                 lib_name: "backend".to_string(),
                 interface_section: Section::new("IBackend.cs", "Backend", "IBackend"),
@@ -105,9 +408,27 @@ impl LangCSharp {
                 consts_section: Section::new("Constants.cs", "Backend", "Constants"),
                 types_section: Section::new("Types.cs", "Backend", ""),
                 utils_section: Section::new("Utils.cs", "Backend", "Utils"),
+                mock_section: Section::new("BackendMock.cs", "Backend", "BackendMock"),
                 preserve_comments: false,
                 opaque_types: Default::default(),
                 native_types: Default::default(),
+                safe_handles: Default::default(),
+                owned_callback_handles: Default::default(),
+                string_encoding: StringEncoding::Utf8,
+                span_functions: Default::default(),
+                blittable: false,
+                platform: Platform::default(),
+                disposable_types: Default::default(),
+                allocator: ("ffi_alloc".to_string(), "ffi_free".to_string()),
+                consuming_functions: Default::default(),
+                transferable_types: Default::default(),
+                last_error: None,
+                string_free: None,
+                callbacks: Default::default(),
+                cancellation: None,
+                alignments: Default::default(),
+                structs: Default::default(),
+                recursive_types: Default::default(),
             },
             custom_consts: Vec::new(),
             consts: Vec::new(),
@@ -115,6 +436,8 @@ impl LangCSharp {
             structs: Vec::new(),
             functions: Vec::new(),
             aliases: Default::default(),
+            module_sections: Default::default(),
+            current_module: String::new(),
         }
     }
 
@@ -143,6 +466,21 @@ impl LangCSharp {
         self.context.functions_section = Section::new(path, namespace, class)
     }
 
+    /// Emit functions and types of the Rust module (e.g. `ffi::mdata`) into a separate
+    /// file. The functions are emitted as a part of the partial class of the functions
+    /// section, the types into the given namespace.
+    pub fn add_module_section<M, P, N>(&mut self, module: M, path: P, namespace: N)
+    where
+        M: Into<String>,
+        P: Into<String>,
+        N: Into<String>,
+    {
+        let _ = self.module_sections.insert(
+            module.into(),
+            Section::new(path, namespace, ""),
+        );
+    }
+
     /// Enabl/disable generation of constants.
     pub fn set_consts_enabled(&mut self, enabled: bool) {
         self.consts_enabled = enabled;
@@ -187,9 +525,178 @@ impl LangCSharp {
         self.context.utils_section = Section::new(path, namespace, class)
     }
 
+    /// Enable/disable generation of the mock class implementing the interface, whose
+    /// methods delegate to settable `Func<..>` properties (disabled by default).
+    pub fn set_mock_enabled(&mut self, enabled: bool) {
+        self.mock_enabled = enabled;
+    }
+
+    /// Set path, namespace and class name of the mock section.
+    pub fn set_mock_section<P, N, C>(&mut self, path: P, namespace: N, class: C)
+    where
+        P: Into<String>,
+        N: Into<String>,
+        C: Into<String>,
+    {
+        self.context.mock_section = Section::new(path, namespace, class)
+    }
+
+    /// Set the platform specific parts of the generated code (Xamarin by default).
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.context.platform = platform;
+    }
+
+    /// Set the encoding used to marshal strings (UTF-8 by default).
+    pub fn set_string_encoding(&mut self, encoding: StringEncoding) {
+        self.context.string_encoding = encoding;
+    }
+
+    /// Generate an overload of the function taking `ReadOnlySpan<T>` instead of
+    /// `List<T>` for arrays of primitive types, avoiding copies of the input data.
+    /// If the function returns such array through a callback, the overload passes it
+    /// to a handler as a span, valid only during the handler call.
+    pub fn add_span_function<T: Into<String>>(&mut self, name: T) {
+        let _ = self.context.span_functions.insert(name.into());
+    }
+
+    /// Enable/disable generation of blittable `unsafe` native structs and of callbacks
+    /// passed as `delegate* unmanaged` function pointers to `[UnmanagedCallersOnly]`
+    /// methods, which avoids the runtime marshalling. Requires C# 9 (.NET 5+ or IL2CPP).
+    /// Structs containing `bool`s, strings or fixed-size arrays become native structs,
+    /// with fixed-size arrays of primitive types emitted as `fixed` buffers.
+    pub fn set_blittable(&mut self, enabled: bool) {
+        self.context.blittable = enabled;
+    }
+
+    /// Set the Rust functions allocating and releasing the memory of vectors in structs
+    /// with `_ptr`, `_len` and `_cap` fields (`ffi_alloc` and `ffi_free` by default).
+    /// Rust reconstructs such vectors and frees them with its own allocator, so their
+    /// memory must come from it too. The functions must have these signatures:
+    ///
+    /// ```ignore
+    /// extern "C" fn ffi_alloc(size: usize, align: usize) -> *mut u8;
+    /// extern "C" fn ffi_free(ptr: *mut u8, size: usize, align: usize);
+    /// ```
+    ///
+    /// The vectors are allocated by `ToNative` with capacity equal to their length.
+    /// They stay owned by the wrapper, which releases them by `Free` of the native struct
+    /// after the native call returns, unless the function takes their ownership (see
+    /// `add_consuming_function`). Empty vectors aren't allocated, but passed as null
+    /// pointers with zero capacity.
+    pub fn set_allocator<A, F>(&mut self, alloc: A, free: F)
+    where
+        A: Into<String>,
+        F: Into<String>,
+    {
+        self.context.allocator = (alloc.into(), free.into());
+    }
+
+    /// Mark the function as taking ownership of the vectors with capacity in the native
+    /// structs passed to it (e.g. by rebuilding them with `Vec::from_raw_parts`). The
+    /// native code is then responsible for freeing them, along with their items, even
+    /// if the call fails. The wrapper releases only the rest of the native structs,
+    /// by their `FreeAfterTransfer` method.
+    pub fn add_consuming_function<T: Into<String>>(&mut self, name: T) {
+        let _ = self.context.consuming_functions.insert(name.into());
+    }
+
+    /// Treat `i32` returned by native functions as an error code. The wrappers of
+    /// synchronous functions throw `FfiException` with the description returned by
+    /// the given Rust function when the code is non-zero, and return nothing or
+    /// the values of the out-parameters otherwise. The wrappers of functions taking
+    /// a result callback fault the returned task instead, as the callback is not
+    /// going to be called. The function must have this signature:
+    ///
+    /// ```ignore
+    /// extern "C" fn last_error() -> *const c_char;
+    /// ```
+    ///
+    /// The returned string stays owned by Rust (usually in a thread-local) and is copied
+    /// by the wrapper right after the failed call.
+    pub fn set_last_error_function<T: Into<String>>(&mut self, name: T) {
+        self.context.last_error = Some(name.into());
+    }
+
+    /// Set the Rust function releasing strings written to out-parameters (e.g. by
+    /// `CString::into_raw`). The wrapper copies each such string into a managed one
+    /// after the native call returns, and then passes the native pointer, unless it's
+    /// null, to the function. The function must have this signature:
+    ///
+    /// ```ignore
+    /// extern "C" fn string_free(ptr: *mut c_char);
+    /// ```
+    ///
+    /// Without it, the strings stay owned by the native code. As they are copied only
+    /// after the call returns, they must then have `'static` storage duration.
+    pub fn set_string_free_function<T: Into<String>>(&mut self, name: T) {
+        self.context.string_free = Some(name.into());
+    }
+
+    /// Set the conventions of the callbacks reporting results of asynchronous functions.
+    /// The callback wrappers complete the tasks from the user data and the result found
+    /// at the configured positions. The result is exposed as `FfiResult`, with its error
+    /// code and description laid out in the configured order.
+    pub fn set_callback_convention(&mut self, convention: CallbackConvention) {
+        self.context.callbacks = convention;
+    }
+
+    /// Enable cancellation of asynchronous functions taking a pointer to the token type
+    /// of the convention. Their wrappers take a `CancellationToken` instead, create
+    /// the native token for the call and cancel it when cancellation is requested,
+    /// completing the task as cancelled. The functions managing the tokens are used
+    /// by the wrappers only and are not exposed. Functions with progress callbacks
+    /// can't be cancelled.
+    pub fn set_cancel_convention(&mut self, convention: CancelConvention) {
+        self.context.cancellation = Some(convention);
+    }
+
+    /// Emit the wrapper of the struct as a `sealed class` implementing `IDisposable`
+    /// instead of a `struct`. The native structs it's created from are borrowed, that is
+    /// they are copied and released by the native code.
+    pub fn add_disposable_type<T: Into<String>>(&mut self, name: T) {
+        let _ = self.context.disposable_types.insert(name.into(), None);
+    }
+
+    /// Emit the wrapper of the struct as a `sealed class` implementing `IDisposable`,
+    /// which takes ownership of the native structs passed to callbacks by pointer.
+    /// They are released by the given native function when the wrapper is disposed
+    /// or finalised.
+    pub fn add_disposable_type_with_destructor<T, D>(&mut self, name: T, destructor: D)
+    where
+        T: Into<String>,
+        D: Into<String>,
+    {
+        let _ = self.context.disposable_types.insert(
+            name.into(),
+            Some(destructor.into()),
+        );
+    }
+
     /// Add definition of opaque type (type represented by an opaque pointer).
+    /// If there is a `<type>_free` function, pointers to the type are wrapped
+    /// in a `SafeHandle` which calls the function when released.
     pub fn add_opaque_type<T: Into<String>>(&mut self, name: T) {
-        let _ = self.context.opaque_types.insert(name.into());
+        let _ = self.context.opaque_types.entry(name.into()).or_insert(None);
+    }
+
+    /// Add definition of opaque type whose pointers are wrapped in a `SafeHandle`
+    /// released by the given native function.
+    pub fn add_opaque_type_with_destructor<T, D>(&mut self, name: T, destructor: D)
+    where
+        T: Into<String>,
+        D: Into<String>,
+    {
+        let _ = self.context.opaque_types.insert(
+            name.into(),
+            Some(destructor.into()),
+        );
+    }
+
+    /// Pointers to the opaque type passed to callbacks are owned by the receiver (e.g.
+    /// a newly created object), so their `SafeHandle` releases them. By default, the
+    /// pointers are borrowed from the native code and are never released by the handle.
+    pub fn add_owned_callback_handle<T: Into<String>>(&mut self, name: T) {
+        let _ = self.context.owned_callback_handles.insert(name.into());
     }
 
     /// Add constant definition.
@@ -202,6 +709,17 @@ impl LangCSharp {
         ));
     }
 
+    /// Emit the given enum with the `[Flags]` attribute.
+    pub fn add_flags_enum<T: Into<String>>(&mut self, name: T) {
+        let _ = self.flags_enums.insert(name.into());
+    }
+
+    /// Group constants whose names start with `prefix` (e.g. `PERM_`) into
+    /// a `[Flags]` enum with the given name.
+    pub fn add_const_flags_enum<N: Into<String>, P: Into<String>>(&mut self, name: N, prefix: P) {
+        self.const_enums.push((name.into(), prefix.into()));
+    }
+
     /// Clears the current filter and sets the filter mode.
     pub fn reset_filter(&mut self, filter_mode: FilterMode) {
         self.filter.clear();
@@ -262,18 +780,325 @@ impl LangCSharp {
 
                 // Otherwise, check it one of its fields is native, and if it is,
                 // mark the struct as native and reprocess the whole thing again,
-                // to detect structs with newly identified native fields.
+                // to detect structs with newly identified native fields. Disposable
+                // types always have the native counterpart.
+                if self.context.is_disposable(&snippet.name) ||
+                    snippet.item.fields.iter().any(|field| {
+                        field.ty.is_dynamic_array() || self.context.is_native_type(&field.ty) ||
+                            self.context.pointed_struct(&field.ty).is_some() ||
+                            self.context.needs_field_conversion(&field.ty)
+                    })
+                {
+                    let _ = self.context.native_types.insert(snippet.name.clone());
+                    run = true;
+                }
+            }
+        }
+    }
+
+    // Finds structs containing vectors with capacity, directly or through other structs.
+    fn resolve_transferable_types(&mut self) {
+        // Only the wrappers of consuming functions transfer the vectors.
+        if self.context.consuming_functions.is_empty() {
+            return;
+        }
+
+        let mut run = true;
+        while run {
+            run = false;
+
+            for snippet in &self.structs {
+                if self.context.has_transferable_vectors(&snippet.name) {
+                    continue;
+                }
+
                 if snippet.item.fields.iter().any(|field| {
-                    field.ty.is_dynamic_array() || self.context.is_native_type(&field.ty)
+                    field.ty.is_dynamic_array() && field.has_cap ||
+                        struct_name(&field.ty).map_or(false, |name| {
+                            self.context.has_transferable_vectors(name)
+                        })
                 })
                 {
-                    let _ = self.context.native_types.insert(snippet.name.clone());
+                    let _ = self.context.transferable_types.insert(snippet.name.clone());
+                    run = true;
+                }
+            }
+        }
+    }
+
+    fn resolve_alignments(&mut self) {
+        // Enums are represented as `int`s.
+        for snippet in &self.enums {
+            let _ = self.context.alignments.insert(
+                snippet.name.clone(),
+                Alignment::Bytes(4),
+            );
+        }
+
+        let mut run = true;
+        while run {
+            run = false;
+
+            for snippet in &self.structs {
+                if self.context.alignments.contains_key(&snippet.name) {
+                    continue;
+                }
+
+                // Alignment of the struct is the largest alignment of its fields.
+                // Reprocess the whole thing until all nested structs are resolved.
+                let alignment = snippet.item.fields.iter().fold(
+                    Some(Alignment::Bytes(1)),
+                    |alignment, field| match (alignment, self.context.alignment_of(&field.ty)) {
+                        (Some(alignment), Some(field)) => Some(alignment.max(field)),
+                        _ => None,
+                    },
+                );
+
+                if let Some(alignment) = alignment {
+                    let _ = self.context.alignments.insert(snippet.name.clone(), alignment);
                     run = true;
                 }
             }
         }
     }
 
+    // Finds structs which contain themselves by value, possibly through nullable fields
+    // and other structs. Arrays are references in C#, so they don't form such cycles.
+    fn resolve_recursive_types(&mut self) {
+        let mut fields = HashMap::new();
+        for snippet in &self.structs {
+            let names: Vec<_> = snippet
+                .item
+                .fields
+                .iter()
+                .filter_map(|field| match field.ty {
+                    Type::User(ref name) => Some(name.as_str()),
+                    ref ty => self.context.pointed_struct(ty),
+                })
+                .collect();
+            let _ = fields.insert(snippet.name.as_str(), names);
+        }
+
+        for snippet in &self.structs {
+            let mut visited = HashSet::new();
+            let mut pending = vec![snippet.name.as_str()];
+
+            while let Some(name) = pending.pop() {
+                for &field in fields.get(name).into_iter().flat_map(|names| names) {
+                    if field == snippet.name {
+                        let _ = self.context.recursive_types.insert(snippet.name.clone());
+                    } else if visited.insert(field) {
+                        pending.push(field);
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolve_safe_handles(&mut self) -> Result<(), Error> {
+        for (name, destructor) in &self.context.opaque_types {
+            let (explicit, destructor) = match *destructor {
+                Some(ref destructor) => (true, destructor.clone()),
+                None => (false, format!("{}_free", name.to_snake_case())),
+            };
+
+            let valid = self.functions
+                .iter()
+                .find(|snippet| snippet.name == destructor)
+                .map(|snippet| is_destructor(&snippet.item, name));
+
+            match valid {
+                Some(true) => {
+                    let _ = self.context.safe_handles.insert(name.clone(), destructor);
+                }
+                _ if explicit => {
+                    return Err(Error {
+                        level: Level::Error,
+                        span: None,
+                        message: format!(
+                            "`{}` is not a destructor of the opaque type `{}`",
+                            destructor,
+                            name
+                        ),
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    // The vectors with capacity are allocated with the alignment of their items, which
+    // must match the one Rust uses to free them, so it can't be guessed.
+    fn check_vector_alignments(&self) -> Result<(), Error> {
+        for snippet in &self.structs {
+            for field in &snippet.item.fields {
+                if let (&Type::Array(ref ty, ArraySize::Dynamic), true) =
+                    (&field.ty, field.has_cap)
+                {
+                    if self.context.alignment_of(ty).is_none() {
+                        return Err(Error {
+                            level: Level::Error,
+                            span: None,
+                            message: format!(
+                                "bindgen can not determine the alignment of the items of `{}.{}`",
+                                snippet.name,
+                                field.name
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Arrays of strings would be marshalled as ANSI, as custom marshalers can't be
+    /// applied to array elements.
+    fn check_string_arrays(&self) -> Result<(), Error> {
+        if self.context.string_encoding != StringEncoding::Utf8Marshaler {
+            return Ok(());
+        }
+
+        let functions = self.functions.iter().flat_map(|snippet| {
+            snippet.item.inputs.iter().map(move |&(ref name, ref ty)| {
+                (&snippet.name, name, ty)
+            })
+        });
+        let fields = self.structs.iter().flat_map(|snippet| {
+            snippet.item.fields.iter().map(move |field| {
+                (&snippet.name, &field.name, &field.ty)
+            })
+        });
+
+        for (item, name, ty) in functions.chain(fields) {
+            if is_string_array(ty) {
+                return Err(Error {
+                    level: Level::Error,
+                    span: None,
+                    message: format!(
+                        "bindgen can not marshal the array of strings `{}.{}` \
+                         with `StringEncoding::Utf8Marshaler`",
+                        item,
+                        name
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_disposable_destructors(&self) -> Result<(), Error> {
+        for (name, destructor) in &self.context.disposable_types {
+            let destructor = match *destructor {
+                Some(ref destructor) => destructor,
+                None => continue,
+            };
+
+            let valid = self.functions.iter().any(|snippet| {
+                snippet.name == *destructor && is_destructor(&snippet.item, name)
+            });
+
+            if !valid {
+                return Err(Error {
+                    level: Level::Error,
+                    span: None,
+                    message: format!(
+                        "`{}` is not a destructor of the disposable type `{}`",
+                        destructor,
+                        name
+                    ),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate enum discriminants and create enums from grouped constants.
+    fn resolve_enums(&mut self) -> Result<(), Error> {
+        let mut values = Vec::new();
+
+        for snippet in &self.enums {
+            for variant in &snippet.item.variants {
+                let value = match variant.value {
+                    Some(ref expr) => {
+                        let evaluator = &self.const_evaluator;
+                        let value = expr.eval(&|name| evaluator.eval_const(name)).ok_or_else(|| {
+                            Error {
+                                level: Level::Error,
+                                span: None,
+                                message: format!(
+                                    "bindgen can not evaluate discriminant of {}::{}",
+                                    snippet.name,
+                                    variant.name
+                                ),
+                            }
+                        })?;
+                        Some(IntExpr::Lit(value))
+                    }
+                    None => None,
+                };
+
+                values.push(value);
+            }
+        }
+
+        let mut values = values.into_iter();
+        for snippet in &mut self.enums {
+            for variant in &mut snippet.item.variants {
+                variant.value = values.next().unwrap_or(None);
+            }
+        }
+
+        for &(ref name, ref prefix) in &self.const_enums {
+            let mut ty = None;
+            let mut variants = Vec::new();
+            let mut module = String::new();
+
+            for snippet in self.consts.iter().filter(
+                |snippet| snippet.name.starts_with(prefix.as_str()),
+            )
+            {
+                let value = self.const_evaluator.eval_const(&snippet.name).ok_or_else(|| {
+                    Error {
+                        level: Level::Error,
+                        span: None,
+                        message: format!(
+                            "bindgen can not evaluate constant {} of enum {}",
+                            snippet.name,
+                            name
+                        ),
+                    }
+                })?;
+
+                ty = Some(snippet.item.ty.clone());
+                module = snippet.module.clone();
+                variants.push(EnumVariant {
+                    docs: snippet.docs.clone(),
+                    name: snippet.name[prefix.len()..].to_pascal_case(),
+                    value: Some(IntExpr::Lit(value)),
+                });
+            }
+
+            self.enums.push(Snippet {
+                docs: String::new(),
+                name: name.clone(),
+                item: Enum {
+                    variants,
+                    flags: true,
+                    ty,
+                },
+                module,
+            });
+        }
+
+        Ok(())
+    }
+
     fn is_ignored(&self, ident: &str) -> bool {
         match self.filter_mode {
             FilterMode::Blacklist => self.filter.contains(ident),
@@ -282,11 +1107,268 @@ impl LangCSharp {
     }
 
     fn is_interface_function(&self, name: &str, item: &Function) -> bool {
-        !self.wrapper_function_blacklist.contains(name) && num_callbacks(&item.inputs) <= 1
+        !self.wrapper_function_blacklist.contains(name) &&
+            !self.context.is_destructor(name) &&
+            self.context.last_error_function() != Some(name) &&
+            !self.context.is_cancel_function(name) &&
+            (num_callbacks(&item.inputs) <= 1 || has_progress_callbacks(&item.inputs))
+    }
+
+    /// Returns whether the item is emitted into a module section.
+    fn is_module_item<T>(&self, snippet: &Snippet<T>) -> bool {
+        self.module_sections.contains_key(&snippet.module)
+    }
+
+    /// Namespaces of the module sections, except the given ones.
+    fn module_namespaces<'a>(&'a self, declared: &[&str]) -> Vec<&'a str> {
+        let namespaces: BTreeSet<_> = self.module_sections
+            .values()
+            .map(|section| section.namespace.as_str())
+            .filter(|namespace| !declared.contains(namespace))
+            .collect();
+
+        namespaces.into_iter().collect()
+    }
+
+    fn functions_usings(&self) -> Vec<&str> {
+        let mut usings = vec!["System", "System.Collections.Generic", "System.Linq"];
+        if self.context.blittable {
+            usings.push("System.Runtime.CompilerServices");
+        }
+        usings.push("System.Runtime.InteropServices");
+        if self.context.cancellation.is_some() {
+            usings.push("System.Threading");
+        }
+        usings.push("System.Threading.Tasks");
+        usings
+    }
+
+    fn interface_usings(&self) -> Vec<&str> {
+        let mut usings = vec![
+            "System",
+            "System.Collections.Generic",
+            "System.Runtime.InteropServices",
+        ];
+        if self.context.cancellation.is_some() {
+            usings.push("System.Threading");
+        }
+        usings.push("System.Threading.Tasks");
+        usings
+    }
+
+    fn types_usings(&self) -> Vec<&str> {
+        let mut usings = vec![
+            "System",
+            "System.Collections.Generic",
+            "System.Runtime.InteropServices",
+        ];
+        if self.context.platform.annotations {
+            usings.push("JetBrains.Annotations");
+        }
+        usings
+    }
+
+    fn emit_functions(&self, writer: &mut IndentedWriter, functions: &[&Snippet<Function>]) {
+        for snippet in functions {
+            // Destructors are called by the `SafeHandle` and disposable wrappers.
+            if self.context.is_destructor(&snippet.name) {
+                continue;
+            }
+
+            // The last error is fetched by the wrappers of the failed functions.
+            if self.context.last_error_function() == Some(snippet.name.as_str()) {
+                continue;
+            }
+
+            // The cancellation tokens are managed by the wrappers of cancellable functions.
+            if self.context.is_cancel_function(&snippet.name) {
+                continue;
+            }
+
+            emit_docs(writer, &self.context, &snippet.docs);
+            let interface = self.is_interface_function(&snippet.name, &snippet.item);
+            if interface {
+                emit_wrapper_function(writer, &self.context, &snippet.name, &snippet.item);
+            }
+            emit_function_extern_decl(writer, &self.context, &snippet.name, &snippet.item);
+
+            if interface && has_span_overload(&self.context, &snippet.name, &snippet.item) {
+                emit_span_function(writer, &self.context, &snippet.name, &snippet.item);
+            }
+
+            if interface && has_progress_callbacks(&snippet.item.inputs) {
+                emit_progress_callback_wrappers(
+                    writer,
+                    &self.context,
+                    &snippet.name,
+                    &snippet.item,
+                );
+            }
+        }
+    }
+
+    fn emit_types(
+        &self,
+        writer: &mut IndentedWriter,
+        enums: &[&Snippet<Enum>],
+        structs: &[&Snippet<Struct>],
+    ) {
+        // Enums
+        for snippet in enums {
+            emit_docs(writer, &self.context, &snippet.docs);
+            emit_enum(writer, &self.context, &snippet.name, &snippet.item);
+        }
+
+        // Structs
+        for snippet in structs {
+            emit_docs(writer, &self.context, &snippet.docs);
+
+            if self.context.is_native_name(&snippet.name) {
+                emit_wrapper_struct(writer, &self.context, &snippet.name, &snippet.item);
+                emit_native_struct(writer, &self.context, &snippet.name, &snippet.item);
+            } else {
+                emit_normal_struct(writer, &self.context, &snippet.name, &snippet.item);
+            }
+        }
+    }
+
+    /// Emit the mock class implementing the interface with the given functions.
+    fn emit_mock(&self, functions: &[Snippet<Function>]) -> String {
+        let interface_namespace = &self.context.interface_section.namespace[..];
+        let namespace = &self.context.mock_section.namespace[..];
+
+        let mut usings = self.interface_usings();
+        if interface_namespace != namespace {
+            usings.push(interface_namespace);
+        }
+        usings.extend(self.module_namespaces(&[namespace, interface_namespace]));
+
+        let mut writer = IndentedWriter::new(INDENT_WIDTH);
+        emit_file_header(&mut writer, &self.context, &usings);
+        emit!(writer, "namespace {} {{\n", namespace);
+        writer.indent();
+
+        emit!(
+            writer,
+            "public partial class {} : {} {{\n",
+            self.context.mock_section.class,
+            self.context.interface_section.class
+        );
+        writer.indent();
+
+        for snippet in functions {
+            let (name, item) = (&snippet.name, &snippet.item);
+
+            let signature = wrapper_function_signature(&self.context, name, item);
+            emit_mock_function(&mut writer, &self.context, &signature, "");
+
+            if has_span_overload(&self.context, name, item) {
+                let signature = span_function_signature(&self.context, name, item);
+                emit_mock_function(&mut writer, &self.context, &signature, "Span");
+            }
+        }
+
+        writer.unindent();
+        emit!(writer, "}}\n");
+
+        writer.unindent();
+        emit!(writer, "}}\n");
+
+        writer.into_inner()
+    }
+
+    /// Emit the file of the module section. The functions are emitted as a part of
+    /// the partial class of the functions section. The callbacks are shared by all
+    /// the functions, so they are emitted into the functions section only.
+    fn emit_module(&self, module: &str, section: &Section) -> Option<String> {
+        let functions: Vec<_> = self.functions
+            .iter()
+            .filter(|snippet| snippet.module == module)
+            .collect();
+        let enums: Vec<_> = self.enums
+            .iter()
+            .filter(|snippet| self.types_enabled && snippet.module == module)
+            .collect();
+        let structs: Vec<_> = self.structs
+            .iter()
+            .filter(|snippet| self.types_enabled && snippet.module == module)
+            .collect();
+        let has_types = !enums.is_empty() || !structs.is_empty();
+
+        if functions.is_empty() && !has_types {
+            return None;
+        }
+
+        let mut writer = IndentedWriter::new(INDENT_WIDTH);
+
+        let mut usings = Vec::new();
+        if !functions.is_empty() {
+            usings.extend(self.functions_usings());
+        }
+        if has_types {
+            for using in self.types_usings() {
+                if !usings.contains(&using) {
+                    usings.push(using);
+                }
+            }
+        }
+        // The functions need the types of the module, which are in a different namespace.
+        let declared = if functions.is_empty() {
+            &section.namespace
+        } else {
+            &self.context.functions_section.namespace
+        };
+        usings.extend(self.module_namespaces(&[&declared[..]]));
+        emit_file_header(&mut writer, &self.context, &usings);
+
+        if !functions.is_empty() {
+            emit!(
+                writer,
+                "namespace {} {{\n",
+                self.context.functions_section.namespace
+            );
+            writer.indent();
+
+            emit!(
+                writer,
+                "public {}partial class {} {{\n",
+                unsafe_modifier(&self.context),
+                self.context.functions_section.class
+            );
+            writer.indent();
+
+            self.emit_functions(&mut writer, &functions);
+
+            writer.unindent();
+            emit!(writer, "}}\n");
+
+            writer.unindent();
+            emit!(writer, "}}\n");
+        }
+
+        if has_types {
+            if !functions.is_empty() {
+                emit!(writer, "\n");
+            }
+
+            emit!(writer, "namespace {} {{\n", section.namespace);
+            writer.indent();
+
+            self.emit_types(&mut writer, &enums, &structs);
+
+            writer.unindent();
+            emit!(writer, "}}\n");
+        }
+
+        Some(writer.into_inner())
     }
 }
 
 impl Lang for LangCSharp {
+    fn set_current_module(&mut self, path: &[String]) {
+        self.current_module = path.join("::");
+    }
+
     fn parse_ty(&mut self, item: &ast::Item, _outputs: &mut Outputs) -> Result<(), Error> {
         let name = item.ident.name.as_str();
         if self.is_ignored(&name) {
@@ -302,7 +1384,7 @@ impl Lang for LangCSharp {
                 return Ok(());
             }
 
-            let ty = transform_type(ty).ok_or_else(|| {
+            let ty = transform_type(ty, &self.const_evaluator).ok_or_else(|| {
                 Error {
                     level: Level::Error,
                     span: Some(ty.span),
@@ -321,6 +1403,12 @@ impl Lang for LangCSharp {
 
     fn parse_const(&mut self, item: &ast::Item, _outputs: &mut Outputs) -> Result<(), Error> {
         let name = item.ident.name.as_str();
+
+        if let ast::ItemKind::Const(ref ty, ref expr) = item.node {
+            // Ignored constants can still be referenced by other constants or array sizes.
+            self.const_evaluator.define(&*name, ty, expr);
+        }
+
         if self.is_ignored(&name) {
             return Ok(());
         }
@@ -328,19 +1416,25 @@ impl Lang for LangCSharp {
         let docs = common::parse_attr(&item.attrs, |_| true, retrieve_docstring).1;
 
         if let ast::ItemKind::Const(ref ty, ref expr) = item.node {
-            let item = transform_const(ty, expr).ok_or_else(|| {
-                Error {
-                    level: Level::Error,
-                    span: Some(expr.span),
-                    message: format!(
-                        "bindgen can not handle constant {}",
+            let item = match transform_const(ty, expr, &self.const_evaluator) {
+                Some(item) => item,
+                None => {
+                    println!(
+                        "bindgen can not evaluate constant {}. Skipping.",
                         pprust::item_to_string(item)
-                    ),
+                    );
+                    return Ok(());
                 }
-            })?;
+            };
             let name = name.to_string();
 
-            self.consts.push(Snippet { docs, name, item });
+            let module = self.current_module.clone();
+            self.consts.push(Snippet {
+                docs,
+                name,
+                item,
+                module,
+            });
         }
 
         Ok(())
@@ -365,7 +1459,7 @@ impl Lang for LangCSharp {
                 return Err(unsupported_generics_error(item, "enums"));
             }
 
-            let item = transform_enum(variants).ok_or_else(|| {
+            let mut item = transform_enum(variants, &self.const_evaluator).ok_or_else(|| {
                 Error {
                     level: Level::Error,
                     span: Some(item.span),
@@ -376,8 +1470,15 @@ impl Lang for LangCSharp {
                 }
             })?;
             let name = name.to_string();
+            item.flags = self.flags_enums.contains(&name);
 
-            self.enums.push(Snippet { docs, name, item });
+            let module = self.current_module.clone();
+            self.enums.push(Snippet {
+                docs,
+                name,
+                item,
+                module,
+            });
         }
 
         Ok(())
@@ -410,7 +1511,7 @@ impl Lang for LangCSharp {
                 });
             }
 
-            let item = transform_struct(variants.fields()).ok_or_else(|| {
+            let item = transform_struct(variants.fields(), &self.const_evaluator).ok_or_else(|| {
                 Error {
                     level: Level::Error,
                     span: Some(item.span),
@@ -421,7 +1522,14 @@ impl Lang for LangCSharp {
                 }
             })?;
             let name = name.to_string();
-            self.structs.push(Snippet { docs, name, item });
+            let module = self.current_module.clone();
+            let _ = self.context.structs.insert(name.clone());
+            self.structs.push(Snippet {
+                docs,
+                name,
+                item,
+                module,
+            });
             self.resolve_native_types();
         }
 
@@ -453,7 +1561,7 @@ impl Lang for LangCSharp {
                 return Err(unsupported_generics_error(item, "extern functions"));
             }
 
-            let function = transform_function(&fn_decl).ok_or_else(|| {
+            let mut function = transform_function(&fn_decl, &self.const_evaluator).ok_or_else(|| {
                 let string =
                     pprust::fun_to_string(fn_decl, unsafety, constness.node, item.ident, generics);
 
@@ -464,10 +1572,22 @@ impl Lang for LangCSharp {
                 }
             })?;
 
+            let nullable = common::nullable_params(&item.attrs);
+            let out = common::out_params(fn_decl, &item.attrs);
+            for &mut (ref name, ref mut ty) in &mut function.inputs {
+                if nullable.contains(name) {
+                    *ty = mem::replace(ty, Type::Unit).into_nullable();
+                } else if out.contains(name) {
+                    *ty = mem::replace(ty, Type::Unit).into_out();
+                }
+            }
+            function.apply_callback_convention(&self.context.callbacks);
+
             self.functions.push(Snippet {
                 docs,
                 name: name.to_string(),
                 item: function,
+                module: self.current_module.clone(),
             });
         }
 
@@ -476,16 +1596,31 @@ impl Lang for LangCSharp {
 
     fn finalise_output(&mut self, outputs: &mut Outputs) -> Result<(), Error> {
         self.resolve_aliases();
+        self.resolve_safe_handles()?;
+        self.check_disposable_destructors()?;
+        self.check_string_arrays()?;
+        self.resolve_transferable_types();
+        self.resolve_enums()?;
+        self.resolve_alignments();
+        self.check_vector_alignments()?;
+        self.resolve_recursive_types();
+
+        // Modules
+        for (module, section) in &self.module_sections {
+            if let Some(content) = self.emit_module(module, section) {
+                outputs.insert(PathBuf::from(section.path.clone()), content);
+            }
+        }
 
         if !self.functions.is_empty() {
             // Functions
             let mut writer = IndentedWriter::new(INDENT_WIDTH);
 
-            emit!(writer, "using System;\n");
-            emit!(writer, "using System.Collections.Generic;\n");
-            emit!(writer, "using System.Linq;\n");
-            emit!(writer, "using System.Runtime.InteropServices;\n");
-            emit!(writer, "using System.Threading.Tasks;\n\n");
+            let mut usings = self.functions_usings();
+            usings.extend(self.module_namespaces(
+                &[&self.context.functions_section.namespace[..]],
+            ));
+            emit_file_header(&mut writer, &self.context, &usings);
             emit!(
                 writer,
                 "namespace {} {{\n",
@@ -493,9 +1628,20 @@ impl Lang for LangCSharp {
             );
             writer.indent();
 
+            // SafeHandle wrappers of opaque types.
+            for (name, destructor) in &self.context.safe_handles {
+                if let Some(snippet) = self.functions.iter().find(
+                    |snippet| &snippet.name == destructor,
+                )
+                {
+                    emit_safe_handle(&mut writer, &self.context, name, destructor, &snippet.item);
+                }
+            }
+
             emit!(
                 writer,
-                "public partial class {} : I{} {{\n",
+                "public {}partial class {} : I{} {{\n",
+                unsafe_modifier(&self.context),
                 self.context.functions_section.class,
                 self.context.functions_section.class
             );
@@ -503,22 +1649,43 @@ impl Lang for LangCSharp {
 
             // Define constant with the native library name, to be used in
             // the [DllImport] attributes.
-            emit!(writer, "#if __IOS__\n");
-            emit!(writer, "internal const string DllName = \"__Internal\";\n");
-            emit!(writer, "#else\n");
-            emit!(
-                writer,
-                "internal const string DllName = \"{}\";\n",
-                self.context.lib_name
-            );
-            emit!(writer, "#endif\n\n");
+            emit_lib_name(&mut writer, &self.context);
 
-            for snippet in &self.functions {
-                emit_docs(&mut writer, &self.context, &snippet.docs);
-                if self.is_interface_function(&snippet.name, &snippet.item) {
-                    emit_wrapper_function(&mut writer, &self.context, &snippet.name, &snippet.item);
-                }
-                emit_function_extern_decl(&mut writer, &self.context, &snippet.name, &snippet.item);
+            {
+                let functions: Vec<_> = self.functions
+                    .iter()
+                    .filter(|snippet| !self.is_module_item(snippet))
+                    .collect();
+                self.emit_functions(&mut writer, &functions);
+            }
+
+            if self.structs.iter().any(|snippet| {
+                snippet.item.fields.iter().any(|field| field.has_cap)
+            })
+            {
+                emit_allocator_decls(&mut writer, &self.context);
+            }
+
+            if self.functions.iter().any(|snippet| {
+                returns_error_code(&self.context, &snippet.item)
+            })
+            {
+                emit_last_error_decl(&mut writer, &self.context);
+            }
+
+            if self.context.string_free_function().is_some() &&
+                self.functions.iter().any(
+                    |snippet| has_string_out_params(&snippet.item),
+                )
+            {
+                emit_string_free_decl(&mut writer, &self.context);
+            }
+
+            if self.functions.iter().any(|snippet| {
+                is_cancellable(&self.context, &snippet.item)
+            })
+            {
+                emit_cancel_decls(&mut writer, &self.context);
             }
 
             // Callback delegates and wrappers.
@@ -526,13 +1693,32 @@ impl Lang for LangCSharp {
                 let callbacks = collect_callbacks(&self.functions);
                 if !callbacks.is_empty() {
                     for (callback, single) in callbacks {
-                        emit_callback_delegate(&mut writer, &self.context, callback);
+                        // Function pointers are used instead of delegates in the blittable mode.
+                        if !self.context.blittable {
+                            emit_callback_delegate(&mut writer, &self.context, callback);
+                        }
 
                         if single {
                             emit_callback_wrapper(&mut writer, &self.context, callback);
                         }
                     }
                 }
+
+                let mut span_callbacks = BTreeMap::new();
+                for snippet in &self.functions {
+                    if self.is_interface_function(&snippet.name, &snippet.item) &&
+                        has_span_overload(&self.context, &snippet.name, &snippet.item)
+                    {
+                        if let Some((callback, _)) = extract_span_callback(&snippet.item.inputs) {
+                            let name = callback_wrapper_name(callback);
+                            let _ = span_callbacks.insert(name, callback);
+                        }
+                    }
+                }
+
+                for callback in span_callbacks.values() {
+                    emit_span_callback_wrapper(&mut writer, &self.context, callback);
+                }
             }
 
             writer.unindent();
@@ -553,15 +1739,16 @@ impl Lang for LangCSharp {
                 .filter(|snippet| {
                     self.is_interface_function(&snippet.name, &snippet.item)
                 })
-                .peekable();
+                .collect::<Vec<_>>();
 
-            if functions.peek().is_some() {
+            if !functions.is_empty() {
                 let mut writer = IndentedWriter::new(INDENT_WIDTH);
 
-                emit!(writer, "using System;\n");
-                emit!(writer, "using System.Collections.Generic;\n");
-                emit!(writer, "using System.Runtime.InteropServices;\n");
-                emit!(writer, "using System.Threading.Tasks;\n\n");
+                let mut usings = self.interface_usings();
+                usings.extend(self.module_namespaces(
+                    &[&self.context.interface_section.namespace[..]],
+                ));
+                emit_file_header(&mut writer, &self.context, &usings);
                 emit!(
                     writer,
                     "namespace {} {{\n",
@@ -576,9 +1763,18 @@ impl Lang for LangCSharp {
                 );
                 writer.indent();
 
-                for snippet in functions {
-                    if num_callbacks(&snippet.item.inputs) <= 1 {
-                        emit_wrapper_function_decl(
+                for snippet in &functions {
+                    emit_wrapper_function_decl(
+                        &mut writer,
+                        &self.context,
+                        "",
+                        &snippet.name,
+                        &snippet.item,
+                    );
+                    emit!(writer, ";\n");
+
+                    if has_span_overload(&self.context, &snippet.name, &snippet.item) {
+                        emit_span_function_decl(
                             &mut writer,
                             &self.context,
                             "",
@@ -599,13 +1795,20 @@ impl Lang for LangCSharp {
                     PathBuf::from(self.context.interface_section.path.clone()),
                     writer.into_inner(),
                 );
+
+                if self.mock_enabled {
+                    outputs.insert(
+                        PathBuf::from(self.context.mock_section.path.clone()),
+                        self.emit_mock(&functions),
+                    );
+                }
             }
         }
 
         // Constants
         if self.consts_enabled && (!self.consts.is_empty() || !self.custom_consts.is_empty()) {
             let mut writer = IndentedWriter::new(INDENT_WIDTH);
-            emit!(writer, "using System;\n\n");
+            emit_file_header(&mut writer, &self.context, &["System"]);
             emit!(
                 writer,
                 "namespace {} {{\n",
@@ -645,46 +1848,42 @@ impl Lang for LangCSharp {
         }
 
         // Types
-        if self.types_enabled && (!self.enums.is_empty() || !self.structs.is_empty()) {
-            let mut writer = IndentedWriter::new(INDENT_WIDTH);
+        {
+            let enums: Vec<_> = self.enums
+                .iter()
+                .filter(|snippet| !self.is_module_item(snippet))
+                .collect();
+            let structs: Vec<_> = self.structs
+                .iter()
+                .filter(|snippet| !self.is_module_item(snippet))
+                .collect();
+
+            if self.types_enabled && (!enums.is_empty() || !structs.is_empty()) {
+                let mut writer = IndentedWriter::new(INDENT_WIDTH);
 
-            emit!(writer, "using System;\n");
-            emit!(writer, "using System.Collections.Generic;\n");
-            emit!(writer, "using System.Runtime.InteropServices;\n");
-            emit!(writer, "using JetBrains.Annotations;\n\n");
+                let mut usings = self.types_usings();
+                usings.extend(self.module_namespaces(
+                    &[&self.context.types_section.namespace[..]],
+                ));
+                emit_file_header(&mut writer, &self.context, &usings);
 
-            emit!(
-                writer,
-                "namespace {} {{\n",
-                self.context.types_section.namespace
-            );
-            writer.indent();
+                emit!(
+                    writer,
+                    "namespace {} {{\n",
+                    self.context.types_section.namespace
+                );
+                writer.indent();
 
-            // Enums
-            for snippet in self.enums.drain(..) {
-                emit_docs(&mut writer, &self.context, &snippet.docs);
-                emit_enum(&mut writer, &self.context, &snippet.name, &snippet.item);
-            }
+                self.emit_types(&mut writer, &enums, &structs);
 
-            // Structs
-            for snippet in &self.structs {
-                emit_docs(&mut writer, &self.context, &snippet.docs);
+                writer.unindent();
+                emit!(writer, "}}\n");
 
-                if self.context.is_native_name(&snippet.name) {
-                    emit_wrapper_struct(&mut writer, &self.context, &snippet.name, &snippet.item);
-                    emit_native_struct(&mut writer, &self.context, &snippet.name, &snippet.item);
-                } else {
-                    emit_normal_struct(&mut writer, &self.context, &snippet.name, &snippet.item);
-                }
+                outputs.insert(
+                    PathBuf::from(self.context.types_section.path.clone()),
+                    writer.into_inner(),
+                );
             }
-
-            writer.unindent();
-            emit!(writer, "}}\n");
-
-            outputs.insert(
-                PathBuf::from(self.context.types_section.path.clone()),
-                writer.into_inner(),
-            );
         }
 
         // Utilities
@@ -699,8 +1898,11 @@ impl Lang for LangCSharp {
         }
 
         // Other cleanup.
+        self.enums.clear();
         self.context.opaque_types.clear();
         self.context.native_types.clear();
+        self.context.safe_handles.clear();
+        self.context.owned_callback_handles.clear();
 
         Ok(())
     }
@@ -716,7 +1918,9 @@ fn resolve_alias(aliases: &HashMap<String, Type>, new_ty: &mut Type) {
                 return;
             }
         }
-        Type::Pointer(ref mut ty) => {
+        Type::Pointer(ref mut ty) |
+        Type::Nullable(ref mut ty) |
+        Type::Out(ref mut ty) => {
             resolve_alias(aliases, ty);
         }
         Type::Array(ref mut ty, _) => {
@@ -772,6 +1976,46 @@ fn collect_callbacks(functions: &[Snippet<Function>]) -> Vec<(&Function, bool)>
     stash.into_iter().map(|(_, entry)| entry).collect()
 }
 
+/// Name of the struct the type refers to, possibly through pointers or arrays.
+fn struct_name(ty: &Type) -> Option<&str> {
+    match *ty {
+        Type::User(ref name) => Some(name.as_str()),
+        Type::Pointer(ref ty) | Type::Nullable(ref ty) | Type::Array(ref ty, _) => {
+            struct_name(ty)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the type is an array of strings, possibly behind pointers.
+fn is_string_array(ty: &Type) -> bool {
+    match *ty {
+        Type::Array(ref ty, _) => {
+            if let Type::String = **ty { true } else { is_string_array(ty) }
+        }
+        Type::Pointer(ref ty) | Type::Nullable(ref ty) | Type::Out(ref ty) => is_string_array(ty),
+        _ => false,
+    }
+}
+
+/// Returns whether the function takes a single pointer to the type and returns
+/// nothing, so it can be used to release a `SafeHandle` or a disposable wrapper.
+fn is_destructor(fun: &Function, name: &str) -> bool {
+    if fun.inputs.len() != 1 {
+        return false;
+    }
+
+    match (&fun.inputs[0].1, &fun.output) {
+        (&Type::Pointer(ref ty), &Type::Unit) => {
+            match **ty {
+                Type::User(ref pointee) => pointee == name,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
 fn callback_wrapper_name(callback: &Function) -> String {
     let mut writer = IndentedWriter::new(INDENT_WIDTH);
     emit_callback_wrapper_name(&mut writer, callback);
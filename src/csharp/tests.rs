@@ -78,7 +78,7 @@ fn structs() {
              public ulong Id;
              [MarshalAs(UnmanagedType.U1)]
              public bool Enabled;
-             [MarshalAs(UnmanagedType.LPStr)]
+             [MarshalAs(UnmanagedType.LPUTF8Str)]
              public string Name;
              [MarshalAs(UnmanagedType.ByValArray, SizeConst = 10)]
              public int[] RandomNumbers;
@@ -97,6 +97,11 @@ fn structs() {
 #[test]
 fn native_structs() {
     let outputs = compile!(None, {
+        #[repr(C)]
+        pub struct Record {
+            id: usize,
+        }
+
         #[repr(C)]
         pub struct Entry {
             id: u32,
@@ -147,6 +152,11 @@ fn native_structs() {
          using JetBrains.Annotations;
 
          namespace Backend {
+           [PublicAPI]
+           public struct Record {
+             public ulong Id;
+           }
+
            [PublicAPI]
            public struct Entry {
              public uint Id;
@@ -165,9 +175,11 @@ fn native_structs() {
                  Id = Id,
                  KeyPtr = Utils.CopyFromByteList(Key),
                  KeyLen = (ulong) Key.Count,
-                 RecordsPtr = Utils.CopyFromObjectList(Records),
+                 RecordsPtr = Utils.CopyFromObjectList(Records, \
+                                                       (ulong) IntPtr.Size, \
+                                                       Backend.FfiAllocNative),
                  RecordsLen = (ulong) Records.Count,
-                 RecordsCap = 0
+                 RecordsCap = (ulong) Records.Count
                };
              }
            }
@@ -182,7 +194,11 @@ fn native_structs() {
 
              internal void Free() {
                Utils.FreeList(ref KeyPtr, ref KeyLen);
-               Utils.FreeList(ref RecordsPtr, ref RecordsLen);
+               Utils.FreeList<Record>(ref RecordsPtr, \
+                                      ref RecordsLen, \
+                                      ref RecordsCap, \
+                                      (ulong) IntPtr.Size, \
+                                      Backend.FfiFreeNative);
              }
            }
 
@@ -268,6 +284,12 @@ fn native_structs() {
              [DllImport(DllName, EntryPoint = \"fun3\")]
              internal static extern void Fun3Native(IntPtr userData, FfiResultEntryListCb cb);
 
+             [DllImport(DllName, EntryPoint = \"ffi_alloc\")]
+             internal static extern IntPtr FfiAllocNative(ulong size, ulong align);
+
+             [DllImport(DllName, EntryPoint = \"ffi_free\")]
+             internal static extern void FfiFreeNative(IntPtr ptr, ulong size, ulong align);
+
              internal delegate void FfiResultEntryCb(IntPtr userData, \
                                                      IntPtr result, \
                                                      IntPtr entry);
@@ -308,28 +330,128 @@ fn native_structs() {
         "
     );
     assert_multiline_eq!(actual, expected);
+
+    // Empty lists are passed as null pointers with zero capacity, which are not freed.
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains(
+        "    public static IntPtr CopyFromObjectList<T>(\
+         List<T> list, ulong align, Func<ulong, ulong, IntPtr> alloc) {\n      \
+         if (list.Count == 0) {\n        \
+         return IntPtr.Zero;\n      \
+         }\n",
+    ));
+    assert!(actual.contains(
+        "      if (ptr != IntPtr.Zero && cap > 0) {\n          \
+         free(ptr, cap * (ulong) Marshal.SizeOf<T>(), align);\n",
+    ));
 }
 
 #[test]
-fn type_aliases() {
-    let outputs = compile!(None, {
-        pub type Id = u64;
-        // Double indirection.
-        pub type UserId = Id;
+fn consuming_functions() {
+    let mut lang = LangCSharp::new();
+    lang.add_consuming_function("fun0");
+    lang.add_consuming_function("fun1");
 
+    let outputs = compile!(lang, {
         #[repr(C)]
-        pub struct Message {
-            id: Id,
-            sender_id: UserId,
-            receiver_ids: [Id; 10],
+        pub struct Record {
+            id: u64,
+        }
+
+        #[repr(C)]
+        pub struct Entry {
+            key_ptr: *const u8,
+            key_len: usize,
+            records_ptr: *const Record,
+            records_len: usize,
+            records_cap: usize,
+        }
+
+        #[repr(C)]
+        pub struct Wrapper {
+            entry: Entry,
         }
 
         #[no_mangle]
-        pub extern "C" fn fun(
-            id: Id,
-            user_data: *mut c_void,
-            cb: extern "C" fn(*mut c_void, *const FfiResult, Id),
-        ) {
+        pub extern "C" fn fun0(entry: *const Entry) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(wrapper: *const Wrapper) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun2(entry: *const Entry) {}
+    });
+
+    // The vectors with capacity are released by Rust after a consuming call.
+    let actual = fetch(&outputs, "Types.cs");
+    assert!(actual.contains(
+        "    internal void Free() {\n      \
+         Utils.FreeList(ref KeyPtr, ref KeyLen);\n      \
+         Utils.FreeList<Record>(ref RecordsPtr, ref RecordsLen, ref RecordsCap, \
+         Utils.Int64Alignment, Backend.FfiFreeNative);\n    \
+         }\n\n    \
+         internal void FreeAfterTransfer() {\n      \
+         Utils.FreeList(ref KeyPtr, ref KeyLen);\n    \
+         }\n",
+    ));
+    assert!(actual.contains(
+        "    internal void FreeAfterTransfer() {\n      \
+         Entry.FreeAfterTransfer();\n    \
+         }\n",
+    ));
+
+    let actual = fetch(&outputs, "Backend.cs");
+    assert!(actual.contains(
+        "    public void Fun0(ref Entry entry) {\n      \
+         var entryNative = entry.ToNative();\n      \
+         Fun0Native(ref entryNative);\n      \
+         entryNative.FreeAfterTransfer();\n    \
+         }\n",
+    ));
+    assert!(actual.contains("wrapperNative.FreeAfterTransfer();\n"));
+    assert!(actual.contains(
+        "      Fun2Native(ref entryNative);\n      \
+         entryNative.Free();\n",
+    ));
+}
+
+#[test]
+fn native_structs_with_capacity_of_unknown_alignment() {
+    // The alignment must match the one Rust frees the vector with, so it's not guessed.
+    let result = try_compile!(None, {
+        #[repr(C)]
+        pub struct Entry {
+            records_ptr: *const Record,
+            records_len: usize,
+            records_cap: usize,
+        }
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn nested_and_recursive_structs() {
+    let outputs = compile!(None, {
+        #[repr(C)]
+        pub struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[repr(C)]
+        pub struct Record {
+            id: u32,
+            tags_ptr: *const u8,
+            tags_len: usize,
+        }
+
+        #[repr(C)]
+        pub struct Node {
+            value: i32,
+            origin: *const Point,
+            records_ptr: *const Record,
+            records_len: usize,
+            next: *const Node,
         }
     });
 
@@ -342,82 +464,119 @@ fn type_aliases() {
 
          namespace Backend {
            [PublicAPI]
-           public struct Message {
-             public ulong Id;
-             public ulong SenderId;
-             [MarshalAs(UnmanagedType.ByValArray, SizeConst = 10)]
-             public ulong[] ReceiverIds;
+           public struct Point {
+             public int X;
+             public int Y;
            }
 
-         }
-         "
-    );
-    assert_multiline_eq!(actual, expected);
-
-    let actual = fetch(&outputs, "Backend.cs");
-    let expected = indoc!(
-        "using System;
-         using System.Collections.Generic;
-         using System.Linq;
-         using System.Runtime.InteropServices;
-         using System.Threading.Tasks;
+           [PublicAPI]
+           public struct Record {
+             public uint Id;
+             public List<byte> Tags;
 
-         namespace Backend {
-           public partial class Backend : IBackend {
-             #if __IOS__
-             internal const string DllName = \"__Internal\";
-             #else
-             internal const string DllName = \"backend\";
-             #endif
+             internal Record(RecordNative native) {
+               Id = native.Id;
+               Tags = Utils.CopyToByteList(native.TagsPtr, (int) native.TagsLen);
+             }
 
-             public Task<ulong> FunAsync(ulong id) {
-               var (ret, userData) = Utils.PrepareTask<ulong>();
-               FunNative(id, userData, OnFfiResultULongCb);
-               return ret;
+             internal RecordNative ToNative() {
+               return new RecordNative() {
+                 Id = Id,
+                 TagsPtr = Utils.CopyFromByteList(Tags),
+                 TagsLen = (ulong) Tags.Count
+               };
              }
+           }
 
-             [DllImport(DllName, EntryPoint = \"fun\")]
-             internal static extern void FunNative(ulong id, \
-                                                   IntPtr userData, \
-                                                   FfiResultULongCb cb);
+           internal struct RecordNative {
+             public uint Id;
+             public IntPtr TagsPtr;
+             public ulong TagsLen;
 
-             internal delegate void FfiResultULongCb(IntPtr arg0, \
-                                                     IntPtr arg1, \
-                                                     ulong arg2);
+             internal void Free() {
+               Utils.FreeList(ref TagsPtr, ref TagsLen);
+             }
+           }
 
-             #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultULongCb))]
-             #endif
-             private static void OnFfiResultULongCb(IntPtr arg0, \
-                                                    IntPtr arg1, \
-                                                    ulong arg2) {
-               Utils.CompleteTask(arg0, \
-                                  Marshal.PtrToStructure<FfiResult>(arg1), \
-                                  () => arg2);
+           [PublicAPI]
+           public sealed class Node {
+             public int Value;
+             public Point? Origin;
+             public List<Record> Records;
+             public Node Next;
+
+             public Node() {}
+
+             internal Node(NodeNative native) {
+               Value = native.Value;
+               Origin = native.Origin != IntPtr.Zero ? \
+                        Marshal.PtrToStructure<Point>(native.Origin) : \
+                        (Point?) null;
+               Records = Utils.CopyToObjectList<RecordNative>(\
+                 native.RecordsPtr, \
+                 (int) native.RecordsLen).ConvertAll(item => new Record(item));
+               Next = native.Next != IntPtr.Zero ? \
+                      new Node(Marshal.PtrToStructure<NodeNative>(native.Next)) : \
+                      null;
+             }
+
+             internal NodeNative ToNative() {
+               return new NodeNative() {
+                 Value = Value,
+                 Origin = Utils.CopyFromObject(Origin),
+                 RecordsPtr = Utils.CopyFromObjectList(\
+                   Records.ConvertAll(item => item.ToNative())),
+                 RecordsLen = (ulong) Records.Count,
+                 Next = Utils.CopyFromObject(Next?.ToNative())
+               };
              }
+           }
+
+           internal struct NodeNative {
+             public int Value;
+             public IntPtr Origin;
+             public IntPtr RecordsPtr;
+             public ulong RecordsLen;
+             public IntPtr Next;
 
+             internal void Free() {
+               Utils.FreeObject(ref Origin);
+               Utils.FreeListItems<RecordNative>(RecordsPtr, RecordsLen, item => item.Free());
+               Utils.FreeList(ref RecordsPtr, ref RecordsLen);
+               Utils.FreeObject<NodeNative>(ref Next, item => item.Free());
+             }
            }
+
          }
-         "
+        "
     );
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn enums() {
-    let outputs = compile!(None, {
+fn native_structs_with_capacity() {
+    let mut lang = LangCSharp::new();
+    lang.set_allocator("app_alloc", "app_free_buffer");
+
+    let outputs = compile!(lang, {
         #[repr(C)]
-        pub enum Mode {
-            ReadOnly,
-            WriteOnly,
-            ReadAndWrite,
+        pub struct Point {
+            x: i32,
+            y: i32,
         }
 
         #[repr(C)]
-        pub enum Binary {
-            Zero = 0,
-            One = 1,
+        pub struct Shape {
+            points_ptr: *const Point,
+            points_len: usize,
+            points_cap: usize,
+            tag_ptr: *const u8,
+            tag_len: usize,
+            tag_cap: usize,
         }
+
+        #[no_mangle]
+        pub extern "C" fn draw(shape: *const Shape) {}
     });
 
     let actual = fetch(&outputs, "Types.cs");
@@ -429,44 +588,56 @@ fn enums() {
 
          namespace Backend {
            [PublicAPI]
-           public enum Mode {
-             ReadOnly,
-             WriteOnly,
-             ReadAndWrite,
+           public struct Point {
+             public int X;
+             public int Y;
            }
 
            [PublicAPI]
-           public enum Binary {
-             Zero = 0,
-             One = 1,
+           public struct Shape {
+             public List<Point> Points;
+             public List<byte> Tag;
+
+             internal Shape(ShapeNative native) {
+               Points = Utils.CopyToObjectList<Point>(native.PointsPtr, (int) native.PointsLen);
+               Tag = Utils.CopyToByteList(native.TagPtr, (int) native.TagLen);
+             }
+
+             internal ShapeNative ToNative() {
+               return new ShapeNative() {
+                 PointsPtr = Utils.CopyFromObjectList(Points, 4, Backend.AppAllocNative),
+                 PointsLen = (ulong) Points.Count,
+                 PointsCap = (ulong) Points.Count,
+                 TagPtr = Utils.CopyFromByteList(Tag, 1, Backend.AppAllocNative),
+                 TagLen = (ulong) Tag.Count,
+                 TagCap = (ulong) Tag.Count
+               };
+             }
+           }
+
+           internal struct ShapeNative {
+             public IntPtr PointsPtr;
+             public ulong PointsLen;
+             public ulong PointsCap;
+             public IntPtr TagPtr;
+             public ulong TagLen;
+             public ulong TagCap;
+
+             internal void Free() {
+               Utils.FreeList<Point>(ref PointsPtr, \
+                                     ref PointsLen, \
+                                     ref PointsCap, \
+                                     4, \
+                                     Backend.AppFreeBufferNative);
+               Utils.FreeList<byte>(ref TagPtr, ref TagLen, ref TagCap, 1, \
+                                    Backend.AppFreeBufferNative);
+             }
            }
 
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
-}
-
-#[test]
-fn functions_without_extern_and_no_mangle_are_ignored() {
-    let outputs = compile!(None, {
-        pub extern "C" fn fun1() {}
-
-        #[no_mangle]
-        pub fn fun2() {}
-    });
-
-    let actual = fetch(&outputs, "Backend.cs");
-    assert!(actual.is_empty());
-}
-
-#[test]
-fn functions_taking_no_callbacks() {
-    let outputs = compile!(None, {
-        #[no_mangle]
-        pub extern "C" fn fun0(engine: *mut Engine) {}
-    });
 
     let actual = fetch(&outputs, "Backend.cs");
     let expected = indoc!(
@@ -484,151 +655,201 @@ fn functions_taking_no_callbacks() {
              internal const string DllName = \"backend\";
              #endif
 
-             public void Fun0(ref Engine engine) {
-               Fun0Native(ref engine);
+             public void Draw(ref Shape shape) {
+               var shapeNative = shape.ToNative();
+               DrawNative(ref shapeNative);
+               shapeNative.Free();
              }
 
-             [DllImport(DllName, EntryPoint = \"fun0\")]
-             internal static extern void Fun0Native(ref Engine engine);
+             [DllImport(DllName, EntryPoint = \"draw\")]
+             internal static extern void DrawNative(ref ShapeNative shape);
+
+             [DllImport(DllName, EntryPoint = \"app_alloc\")]
+             internal static extern IntPtr AppAllocNative(ulong size, ulong align);
+
+             [DllImport(DllName, EntryPoint = \"app_free_buffer\")]
+             internal static extern void AppFreeBufferNative(IntPtr ptr, ulong size, ulong align);
 
            }
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn functions_taking_one_callback() {
-    let outputs = compile!(None, {
+fn blittable_structs_and_callbacks() {
+    let mut lang = LangCSharp::new();
+    lang.set_blittable(true);
+
+    let outputs = compile!(lang, {
+        #[repr(C)]
+        pub struct Record {
+            id: u64,
+            enabled: bool,
+            name: *const c_char,
+            key: [u8; 32],
+            data_ptr: *const u8,
+            data_len: usize,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun0(
+            record: *const Record,
+            user_data: *mut c_void,
+            o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult, valid: bool),
+        ) {
+        }
+
         #[no_mangle]
         pub extern "C" fn fun1(
-            num: i32,
-            name: *const c_char,
             user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+            o_cb: extern "C" fn(user_data: *mut c_void,
+                                result: *const FfiResult,
+                                record: *const Record),
         ) {
         }
     });
 
-    let actual = fetch(&outputs, "Backend.cs");
+    let actual = fetch(&outputs, "Types.cs");
     let expected = indoc!(
         "using System;
          using System.Collections.Generic;
-         using System.Linq;
          using System.Runtime.InteropServices;
-         using System.Threading.Tasks;
+         using JetBrains.Annotations;
 
          namespace Backend {
-           public partial class Backend : IBackend {
-             #if __IOS__
-             internal const string DllName = \"__Internal\";
-             #else
-             internal const string DllName = \"backend\";
-             #endif
+           [PublicAPI]
+           public unsafe struct Record {
+             public ulong Id;
+             public bool Enabled;
+             public string Name;
+             public byte[] Key;
+             public List<byte> Data;
 
-             public Task Fun1Async(int num, string name) {
-               var (ret, userData) = Utils.PrepareTask();
-               Fun1Native(num, name, userData, OnFfiResultCb);
-               return ret;
+             internal Record(RecordNative native) {
+               Id = native.Id;
+               Enabled = native.Enabled != 0;
+               Name = Utils.CopyToString(native.Name);
+               Key = new ReadOnlySpan<byte>(native.Key, 32).ToArray();
+               Data = Utils.CopyToByteList(native.DataPtr, (int) native.DataLen);
              }
 
-             [DllImport(DllName, EntryPoint = \"fun1\")]
-             internal static extern void Fun1Native(\
-               int num, \
-               [MarshalAs(UnmanagedType.LPStr)] string name, \
-               IntPtr userData, \
-               FfiResultCb cb);
+             internal RecordNative ToNative() {
+               var native = new RecordNative();
+               native.Id = Id;
+               native.Enabled = (byte) (Enabled ? 1 : 0);
+               native.Name = Utils.CopyFromString(Name);
+               Key.AsSpan().CopyTo(new Span<byte>(native.Key, 32));
+               native.DataPtr = Utils.CopyFromByteList(Data);
+               native.DataLen = (ulong) Data.Count;
+               return native;
+             }
+           }
 
-             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+           internal unsafe struct RecordNative {
+             public ulong Id;
+             public byte Enabled;
+             public IntPtr Name;
+             public fixed byte Key[32];
+             public IntPtr DataPtr;
+             public ulong DataLen;
 
-             #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultCb))]
-             #endif
-             private static void OnFfiResultCb(IntPtr userData, IntPtr result) {
-               Utils.CompleteTask(userData, Marshal.PtrToStructure<FfiResult>(result));
+             internal void Free() {
+               Utils.FreeString(ref Name);
+               Utils.FreeList(ref DataPtr, ref DataLen);
              }
-
            }
+
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
-}
-
-#[test]
-fn functions_taking_multiple_callbacks() {
-    // Only the native declaration should be produced.
-
-    let outputs = compile!(None, {
-        #[no_mangle]
-        pub extern "C" fn fun(
-            input: i32,
-            user_data: *mut c_void,
-            cb0: extern "C" fn(user_data: *mut c_void),
-            cb1: extern "C" fn(user_data: *mut c_void,
-                               result: *const FfiResult,
-                               output: i32),
-        ) {
-        }
-    });
 
     let actual = fetch(&outputs, "Backend.cs");
     let expected = indoc!(
         "using System;
          using System.Collections.Generic;
          using System.Linq;
+         using System.Runtime.CompilerServices;
          using System.Runtime.InteropServices;
          using System.Threading.Tasks;
 
          namespace Backend {
-           public partial class Backend : IBackend {
+           public unsafe partial class Backend : IBackend {
              #if __IOS__
              internal const string DllName = \"__Internal\";
              #else
              internal const string DllName = \"backend\";
              #endif
 
-             [DllImport(DllName, EntryPoint = \"fun\")]
-             internal static extern void FunNative(int input, \
-                                                   IntPtr userData, \
-                                                   NoneCb cb0, \
-                                                   FfiResultIntCb cb1);
+             public Task<bool> Fun0Async(ref Record record) {
+               var recordNative = record.ToNative();
+               var (ret, userData) = Utils.PrepareTask<bool>();
+               Fun0Native(ref recordNative, userData, &OnFfiResultBoolCb);
+               recordNative.Free();
+               return ret;
+             }
 
-             internal delegate void FfiResultIntCb(IntPtr userData, \
-                                                   IntPtr result, \
-                                                   int output);
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(\
+               ref RecordNative record, \
+               IntPtr userData, \
+               delegate* unmanaged[Cdecl]<IntPtr, IntPtr, byte, void> oCb\
+             );
 
-             internal delegate void NoneCb(IntPtr userData);
+             public Task<Record> Fun1Async() {
+               var (ret, userData) = Utils.PrepareTask<Record>();
+               Fun1Native(userData, &OnFfiResultRecordCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               IntPtr userData, \
+               delegate* unmanaged[Cdecl]<IntPtr, IntPtr, IntPtr, void> oCb\
+             );
+
+             [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+             private static void OnFfiResultBoolCb(IntPtr userData, IntPtr result, byte valid) {
+               Utils.CompleteTask(userData, \
+                                  new FfiResult(*(FfiResultNative*) result), \
+                                  () => valid != 0);
+             }
+
+             [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+             private static void OnFfiResultRecordCb(IntPtr userData, \
+                                                     IntPtr result, \
+                                                     IntPtr record) {
+               Utils.CompleteTask(userData, \
+                                  new FfiResult(*(FfiResultNative*) result), \
+                                  () => new Record(*(RecordNative*) record));
+             }
 
            }
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn functions_taking_array() {
-    let outputs = compile!(None, {
-        #[no_mangle]
-        pub extern "C" fn fun0(data_ptr: *const u8, data_len: usize) {}
-
-        // Different naming convention
-        #[no_mangle]
-        pub extern "C" fn fun1(data: *const u8, data_len: usize) {}
+fn blittable_function_params() {
+    let mut lang = LangCSharp::new();
+    lang.set_blittable(true);
 
-        // Params before and/or after the array
+    let outputs = compile!(lang, {
         #[no_mangle]
-        pub extern "C" fn fun2(id: u64, data_ptr: *const u8, data_len: usize) {}
+        pub extern "C" fn fun0(
+            name: *const c_char,
+            enabled: bool,
+            data_ptr: *const u8,
+            data_len: usize,
+        ) -> i32 {
+        }
 
-        // This one does not follow the naming convention and thus is not transformed
-        // to array.
         #[no_mangle]
-        pub extern "C" fn fun3(result: *const FfiResult, len: usize) {}
+        pub extern "C" fn fun1(key: *const [u8; 32], o_valid: *mut bool) {}
     });
 
     let actual = fetch(&outputs, "Backend.cs");
@@ -636,90 +857,102 @@ fn functions_taking_array() {
         "using System;
          using System.Collections.Generic;
          using System.Linq;
+         using System.Runtime.CompilerServices;
          using System.Runtime.InteropServices;
          using System.Threading.Tasks;
 
          namespace Backend {
-           public partial class Backend : IBackend {
+           public unsafe partial class Backend : IBackend {
              #if __IOS__
              internal const string DllName = \"__Internal\";
              #else
              internal const string DllName = \"backend\";
              #endif
 
-             public void Fun0(List<byte> data) {
-               Fun0Native(data.ToArray(), (ulong) data.Count);
+             public int Fun0(string name, bool enabled, List<byte> data) {
+               if (name == null) throw new ArgumentNullException(nameof(name));
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               fixed (byte* namePtr = Utils.ToUtf8Bytes(name))
+               fixed (byte* dataPtr = data.ToArray()) {
+                 var ret = Fun0Native((IntPtr) namePtr, \
+                                      (byte) (enabled ? 1 : 0), \
+                                      (IntPtr) dataPtr, \
+                                      (ulong) data.Count);
+                 return ret;
+               }
              }
 
              [DllImport(DllName, EntryPoint = \"fun0\")]
-             internal static extern void Fun0Native(\
-               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 1)] byte[] data, \
+             internal static extern int Fun0Native(\
+               IntPtr name, \
+               byte enabled, \
+               IntPtr data, \
                ulong dataLen\
              );
 
-             public void Fun1(List<byte> data) {
-               Fun1Native(data.ToArray(), (ulong) data.Count);
+             public bool Fun1(byte[] key) {
+               if (key == null) throw new ArgumentNullException(nameof(key));
+               fixed (byte* keyPtr = key) {
+                 Fun1Native((IntPtr) keyPtr, out var validNative);
+                 var valid = validNative != 0;
+                 return valid;
+               }
              }
 
              [DllImport(DllName, EntryPoint = \"fun1\")]
-             internal static extern void Fun1Native(\
-               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 1)] byte[] data, \
-               ulong dataLen\
-             );
-
-             public void Fun2(ulong id, List<byte> data) {
-               Fun2Native(id, data.ToArray(), (ulong) data.Count);
-             }
-
-             [DllImport(DllName, EntryPoint = \"fun2\")]
-             internal static extern void Fun2Native(\
-               ulong id, \
-               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 2)] byte[] data, \
-               ulong dataLen\
-             );
-
-             public void Fun3(ref FfiResult result, ulong len) {
-               Fun3Native(ref result, len);
-             }
-
-             [DllImport(DllName, EntryPoint = \"fun3\")]
-             internal static extern void Fun3Native(ref FfiResult result, ulong len);
+             internal static extern void Fun1Native(IntPtr key, out byte oValid);
 
            }
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn functions_taking_callback_taking_const_size_array() {
+fn type_aliases() {
     let outputs = compile!(None, {
-        pub type Nonce = [u8; NONCE_LEN];
+        pub type Id = u64;
+        // Double indirection.
+        pub type UserId = Id;
 
-        // Literal.
-        #[no_mangle]
-        pub extern "C" fn fun2(
-            user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void,
-                              result: *const FfiResult,
-                              key: [u8; 32]),
-        ) {
+        #[repr(C)]
+        pub struct Message {
+            id: Id,
+            sender_id: UserId,
+            receiver_ids: [Id; 10],
         }
 
-        // Pointor to array via named constant.
         #[no_mangle]
-        pub extern "C" fn fun3(
+        pub extern "C" fn fun(
+            id: Id,
             user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void,
-                              result: *const FfiResult,
-                              nonce: *const Nonce),
+            cb: extern "C" fn(*mut c_void, *const FfiResult, Id),
         ) {
         }
-
     });
 
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           public struct Message {
+             public ulong Id;
+             public ulong SenderId;
+             [MarshalAs(UnmanagedType.ByValArray, SizeConst = 10)]
+             public ulong[] ReceiverIds;
+           }
+
+         }
+         "
+    );
+    assert_multiline_eq!(actual, expected);
+
     let actual = fetch(&outputs, "Backend.cs");
     let expected = indoc!(
         "using System;
@@ -736,87 +969,59 @@ fn functions_taking_callback_taking_const_size_array() {
              internal const string DllName = \"backend\";
              #endif
 
-             public Task<byte[]> Fun2Async() {
-               var (ret, userData) = Utils.PrepareTask<byte[]>();
-               Fun2Native(userData, OnFfiResultByteArray32Cb);
-               return ret;
-             }
-
-             [DllImport(DllName, EntryPoint = \"fun2\")]
-             internal static extern void Fun2Native(IntPtr userData, \
-                                                    FfiResultByteArray32Cb cb);
-
-             public Task<byte[]> Fun3Async() {
-               var (ret, userData) = Utils.PrepareTask<byte[]>();
-               Fun3Native(userData, OnFfiResultByteArrayNonceLenCb);
+             public Task<ulong> FunAsync(ulong id) {
+               var (ret, userData) = Utils.PrepareTask<ulong>();
+               FunNative(id, userData, OnFfiResultULongCb);
                return ret;
              }
 
-             [DllImport(DllName, EntryPoint = \"fun3\")]
-             internal static extern void Fun3Native(IntPtr userData, \
-                                                    FfiResultByteArrayNonceLenCb cb);
-
-             internal delegate void FfiResultByteArray32Cb(IntPtr userData, \
-                                                           IntPtr result, \
-                                                           IntPtr key);
-
-             #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultByteArray32Cb))]
-             #endif
-             private static void OnFfiResultByteArray32Cb(IntPtr userData, \
-                                                          IntPtr result, \
-                                                          IntPtr key) {
-               Utils.CompleteTask(userData, \
-                                  Marshal.PtrToStructure<FfiResult>(result), \
-                                  () => Utils.CopyToByteArray(key, 32));
-             }
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(ulong id, \
+                                                   IntPtr userData, \
+                                                   FfiResultULongCb cb);
 
-             internal delegate void FfiResultByteArrayNonceLenCb(IntPtr userData, \
-                                                                 IntPtr result, \
-                                                                 IntPtr nonce);
+             internal delegate void FfiResultULongCb(IntPtr arg0, \
+                                                     IntPtr arg1, \
+                                                     ulong arg2);
 
              #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultByteArrayNonceLenCb))]
+             [MonoPInvokeCallback(typeof(FfiResultULongCb))]
              #endif
-             private static void OnFfiResultByteArrayNonceLenCb(IntPtr userData, \
-                                                                IntPtr result, \
-                                                                IntPtr nonce) {
-               Utils.CompleteTask(\
-                 userData, \
-                 Marshal.PtrToStructure<FfiResult>(result), \
-                 () => Utils.CopyToByteArray(nonce, (int) Constants.NonceLen));
+             private static void OnFfiResultULongCb(IntPtr arg0, \
+                                                    IntPtr arg1, \
+                                                    ulong arg2) {
+               Utils.CompleteTask(arg0, \
+                                  Marshal.PtrToStructure<FfiResult>(arg1), \
+                                  () => arg2);
              }
 
            }
          }
-        "
+         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn functions_taking_callback_taking_dynamic_array() {
-    let outputs = compile!(None, {
-        // Primitive type.
-        #[no_mangle]
-        pub extern "C" fn fun0(
-            user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void,
-                              result: *const FfiResult,
-                              data_ptr: *const u8,
-                              data_len: usize),
-        ) {
-        }
+fn functions_with_custom_callback_convention() {
+    let mut lang = LangCSharp::new();
+    lang.set_callback_convention(CallbackConvention {
+        user_data_name: "ctx".to_string(),
+        user_data_index: 1,
+        result_name: "err".to_string(),
+        result_index: 0,
+        result_type: "Error".to_string(),
+        error_code_field: "code".to_string(),
+        description_field: "message".to_string(),
+        description_first: true,
+    });
 
-        // Structures.
+    let outputs = compile!(lang, {
         #[no_mangle]
-        pub extern "C" fn fun1(
-            user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void,
-                              result: *const FfiResult,
-                              records_ptr: *const Record,
-                              records_len: usize),
+        pub extern "C" fn fun(
+            id: u64,
+            ctx: *mut c_void,
+            cb: extern "C" fn(err: *const Error, ctx: *mut c_void, len: u32),
         ) {
         }
     });
@@ -837,101 +1042,2355 @@ fn functions_taking_callback_taking_dynamic_array() {
              internal const string DllName = \"backend\";
              #endif
 
-             public Task<List<byte>> Fun0Async() {
-               var (ret, userData) = Utils.PrepareTask<List<byte>>();
-               Fun0Native(userData, OnFfiResultByteListCb);
+             public Task<uint> FunAsync(ulong id) {
+               var (ret, userData) = Utils.PrepareTask<uint>();
+               FunNative(id, userData, OnFfiResultUIntCb);
                return ret;
              }
 
-             [DllImport(DllName, EntryPoint = \"fun0\")]
-             internal static extern void Fun0Native(IntPtr userData, \
-                                                    FfiResultByteListCb cb);
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(ulong id, \
+                                                   IntPtr userData, \
+                                                   FfiResultUIntCb cb);
 
-             public Task<List<Record>> Fun1Async() {
+             internal delegate void FfiResultUIntCb(IntPtr err, IntPtr userData, uint len);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultUIntCb))]
+             #endif
+             private static void OnFfiResultUIntCb(IntPtr err, IntPtr userData, uint len) {
+               Utils.CompleteTask(userData, \
+                                  Marshal.PtrToStructure<FfiResult>(err), \
+                                  () => len);
+             }
+
+           }
+         }
+         "
+    );
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains(
+        "  internal struct FfiResultNative {\n    \
+         public IntPtr Description;\n    \
+         public int ErrorCode;\n  \
+         }",
+    ));
+    assert!(!actual.contains("SpanTask"));
+}
+
+#[test]
+fn enums() {
+    let outputs = compile!(None, {
+        #[repr(C)]
+        pub enum Mode {
+            ReadOnly,
+            WriteOnly,
+            ReadAndWrite,
+        }
+
+        #[repr(C)]
+        pub enum Binary {
+            Zero = 0,
+            One = 1,
+        }
+    });
+
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           public enum Mode {
+             ReadOnly,
+             WriteOnly,
+             ReadAndWrite,
+           }
+
+           [PublicAPI]
+           public enum Binary {
+             Zero = 0,
+             One = 1,
+           }
+
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn flags_enums() {
+    let mut lang = LangCSharp::new();
+    lang.add_flags_enum("Access");
+    lang.add_const_flags_enum("Permission", "PERM_");
+
+    let outputs = compile!(lang, {
+        pub const PERM_READ: u32 = 1 << 0;
+        pub const PERM_WRITE: u32 = 1 << 1;
+        pub const PERM_ALL: u32 = PERM_READ | PERM_WRITE;
+        pub const ADMIN_FLAG: u32 = 0x10;
+
+        #[repr(C)]
+        pub enum Access {
+            None = 0,
+            Read = 1 << 0,
+            Write = 1 << 1,
+            All = (1 << 0) | (1 << 1),
+            Admin = ADMIN_FLAG | PERM_ALL,
+        }
+    });
+
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           [Flags]
+           public enum Access {
+             None = 0,
+             Read = 1,
+             Write = 2,
+             All = 3,
+             Admin = 19,
+           }
+
+           [PublicAPI]
+           [Flags]
+           public enum Permission : uint {
+             Read = 1,
+             Write = 2,
+             All = 3,
+           }
+
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Constants.cs");
+    let expected = indoc!(
+        "using System;
+
+         namespace Backend {
+           public static class Constants {
+             public const uint PermRead = 1 << 0;
+             public const uint PermWrite = 1 << 1;
+             public const uint PermAll = PermRead | PermWrite;
+             public const uint AdminFlag = 16;
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_without_extern_and_no_mangle_are_ignored() {
+    let outputs = compile!(None, {
+        pub extern "C" fn fun1() {}
+
+        #[no_mangle]
+        pub fn fun2() {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    assert!(actual.is_empty());
+}
+
+#[test]
+fn functions_taking_no_callbacks() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun0(engine: *mut Engine) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(ref Engine engine) {
+               Fun0Native(ref engine);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(ref Engine engine);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_one_callback() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun1(
+            num: i32,
+            name: *const c_char,
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task Fun1Async(int num, string name) {
+               if (name == null) throw new ArgumentNullException(nameof(name));
+               var (ret, userData) = Utils.PrepareTask();
+               Fun1Native(num, name, userData, OnFfiResultCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               int num, \
+               [MarshalAs(UnmanagedType.LPUTF8Str)] string name, \
+               IntPtr userData, \
+               FfiResultCb cb);
+
+             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultCb))]
+             #endif
+             private static void OnFfiResultCb(IntPtr userData, IntPtr result) {
+               Utils.CompleteTask(userData, Marshal.PtrToStructure<FfiResult>(result));
+             }
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn strings_with_custom_marshaler() {
+    let mut lang = LangCSharp::new();
+    lang.set_string_encoding(StringEncoding::Utf8Marshaler);
+
+    let outputs = compile!(lang, {
+        #[repr(C)]
+        pub struct Record {
+            name: *const c_char,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun(
+            name: *const c_char,
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              value: *const c_char),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           public struct Record {
+             public string Name;
+
+             internal Record(RecordNative native) {
+               Name = Utils.CopyToString(native.Name);
+             }
+
+             internal RecordNative ToNative() {
+               return new RecordNative() {
+                 Name = Utils.CopyFromString(Name)
+               };
+             }
+           }
+
+           internal struct RecordNative {
+             public IntPtr Name;
+
+             internal void Free() {
+               Utils.FreeString(ref Name);
+             }
+           }
+
+         }
+         "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<string> FunAsync(string name) {
+               if (name == null) throw new ArgumentNullException(nameof(name));
+               var (ret, userData) = Utils.PrepareTask<string>();
+               FunNative(name, userData, OnFfiResultStringCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(\
+               [MarshalAs(UnmanagedType.CustomMarshaler, \
+                          MarshalTypeRef = typeof(Utils.Utf8Marshaler))] \
+               string name, \
+               IntPtr userData, \
+               FfiResultStringCb cb);
+
+             internal delegate void FfiResultStringCb(IntPtr userData, \
+               IntPtr result, \
+               [MarshalAs(UnmanagedType.CustomMarshaler, \
+                          MarshalTypeRef = typeof(Utils.Utf8Marshaler), \
+                          MarshalCookie = \"borrowed\")] \
+               string value);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultStringCb))]
+             #endif
+             private static void OnFfiResultStringCb(IntPtr userData, \
+               IntPtr result, \
+               [MarshalAs(UnmanagedType.CustomMarshaler, \
+                          MarshalTypeRef = typeof(Utils.Utf8Marshaler), \
+                          MarshalCookie = \"borrowed\")] \
+               string value) {
+               Utils.CompleteTask(userData, \
+                                  new FfiResult(Marshal.PtrToStructure<FfiResultNative>(result)), \
+                                  () => value);
+             }
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains(
+        "  public struct FfiResult {\n    \
+         public int ErrorCode;\n    \
+         public string Description;\n",
+    ));
+}
+
+#[test]
+fn string_arrays_with_custom_marshaler() {
+    // Array elements can't use custom marshalers, so they would be marshalled as ANSI.
+    let mut lang = LangCSharp::new();
+    lang.set_string_encoding(StringEncoding::Utf8Marshaler);
+
+    let result = try_compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun(names: *const *const c_char, names_len: usize) {}
+    });
+    assert!(result.is_err());
+
+    let mut lang = LangCSharp::new();
+    lang.set_string_encoding(StringEncoding::Utf8Marshaler);
+
+    let result = try_compile!(lang, {
+        #[repr(C)]
+        pub struct Record {
+            names: [*const c_char; 2],
+        }
+    });
+    assert!(result.is_err());
+
+    let result = try_compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(names: *const *const c_char, names_len: usize) {}
+    });
+    assert!(result.is_ok());
+}
+
+#[test]
+fn functions_taking_multiple_callbacks() {
+    // The callback not taking the result is a progress callback.
+
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(
+            input: i32,
+            user_data: *mut c_void,
+            cb0: extern "C" fn(user_data: *mut c_void),
+            cb1: extern "C" fn(user_data: *mut c_void,
+                               result: *const FfiResult,
+                               output: i32),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<int> FunAsync(int input, Action cb0) {
+               var (ret, userData) = Utils.PrepareProgressTask<int>(cb0);
+               FunNative(input, userData, OnFunCb0, OnFunCb1);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(int input, \
+                                                   IntPtr userData, \
+                                                   NoneCb cb0, \
+                                                   FfiResultIntCb cb1);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(NoneCb))]
+             #endif
+             private static void OnFunCb0(IntPtr userData) {
+               Utils.ReportProgress(userData, 0);
+             }
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultIntCb))]
+             #endif
+             private static void OnFunCb1(IntPtr userData, IntPtr result, int output) {
+               Utils.CompleteProgressTask(\
+                 userData, \
+                 Marshal.PtrToStructure<FfiResult>(result), \
+                 () => output\
+               );
+             }
+
+             internal delegate void FfiResultIntCb(IntPtr userData, \
+                                                   IntPtr result, \
+                                                   int output);
+
+             internal delegate void NoneCb(IntPtr userData);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_multiple_result_callbacks() {
+    // Only the native declaration should be produced, as there is no single
+    // callback reporting the result.
+
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(
+            input: i32,
+            user_data: *mut c_void,
+            cb0: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+            cb1: extern "C" fn(user_data: *mut c_void,
+                               result: *const FfiResult,
+                               output: i32),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(int input, \
+                                                   IntPtr userData, \
+                                                   FfiResultCb cb0, \
+                                                   FfiResultIntCb cb1);
+
+             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+
+             internal delegate void FfiResultIntCb(IntPtr userData, \
+                                                   IntPtr result, \
+                                                   int output);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_progress_callbacks() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(
+            input: i32,
+            user_data: *mut c_void,
+            o_started: extern "C" fn(user_data: *mut c_void),
+            o_progress: extern "C" fn(user_data: *mut c_void, done: u64, total: u64),
+            o_cb: extern "C" fn(user_data: *mut c_void,
+                                result: *const FfiResult,
+                                output: i32),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<int> FunAsync(int input, \
+                                       Action started, \
+                                       IProgress<(ulong, ulong)> progress) {
+               var (ret, userData) = Utils.PrepareProgressTask<int>(started, progress);
+               FunNative(input, userData, OnFunCb0, OnFunCb1, OnFunCb2);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(int input, \
+                                                   IntPtr userData, \
+                                                   NoneCb oStarted, \
+                                                   ULongULongCb oProgress, \
+                                                   FfiResultIntCb oCb);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(NoneCb))]
+             #endif
+             private static void OnFunCb0(IntPtr userData) {
+               Utils.ReportProgress(userData, 0);
+             }
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(ULongULongCb))]
+             #endif
+             private static void OnFunCb1(IntPtr userData, ulong done, ulong total) {
+               Utils.ReportProgress(userData, 1, () => (done, total));
+             }
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultIntCb))]
+             #endif
+             private static void OnFunCb2(IntPtr userData, IntPtr result, int output) {
+               Utils.CompleteProgressTask(\
+                 userData, \
+                 Marshal.PtrToStructure<FfiResult>(result), \
+                 () => output\
+               );
+             }
+
+             internal delegate void FfiResultIntCb(IntPtr userData, \
+                                                   IntPtr result, \
+                                                   int output);
+
+             internal delegate void NoneCb(IntPtr userData);
+
+             internal delegate void ULongULongCb(IntPtr userData, ulong done, ulong total);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "IBackend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial interface IBackend {
+             Task<int> FunAsync(int input, Action started, IProgress<(ulong, ulong)> progress);
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    // Progress reported after the completion must not touch the released task state.
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains(
+        "    public static void ReportProgress(IntPtr userData, int index) {\n      \
+         var state = FindProgressTask(userData, false);\n      \
+         (state?.Reporters[index] as Action)?.Invoke();\n    \
+         }",
+    ));
+}
+
+#[test]
+fn functions_taking_array() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun0(data_ptr: *const u8, data_len: usize) {}
+
+        // Different naming convention
+        #[no_mangle]
+        pub extern "C" fn fun1(data: *const u8, data_len: usize) {}
+
+        // Params before and/or after the array
+        #[no_mangle]
+        pub extern "C" fn fun2(id: u64, data_ptr: *const u8, data_len: usize) {}
+
+        // This one does not follow the naming convention and thus is not transformed
+        // to array.
+        #[no_mangle]
+        pub extern "C" fn fun3(result: *const FfiResult, len: usize) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(List<byte> data) {
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               Fun0Native(data.ToArray(), (ulong) data.Count);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 1)] byte[] data, \
+               ulong dataLen\
+             );
+
+             public void Fun1(List<byte> data) {
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               Fun1Native(data.ToArray(), (ulong) data.Count);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 1)] byte[] data, \
+               ulong dataLen\
+             );
+
+             public void Fun2(ulong id, List<byte> data) {
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               Fun2Native(id, data.ToArray(), (ulong) data.Count);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun2\")]
+             internal static extern void Fun2Native(\
+               ulong id, \
+               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 2)] byte[] data, \
+               ulong dataLen\
+             );
+
+             public void Fun3(ref FfiResult result, ulong len) {
+               Fun3Native(ref result, len);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun3\")]
+             internal static extern void Fun3Native(ref FfiResult result, ulong len);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_with_span_overloads() {
+    let mut lang = LangCSharp::new();
+    lang.add_span_function("fun0");
+    lang.add_span_function("fun1");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun0(data: *const u8, data_len: usize) -> i32 {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(
+            id: u64,
+            data_ptr: *const u8,
+            data_len: usize,
+            user_data: *mut c_void,
+            o_cb: extern "C" fn(user_data: *mut c_void,
+                                result: *const FfiResult,
+                                data_ptr: *const u8,
+                                data_len: usize),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public int Fun0(List<byte> data) {
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               var ret = Fun0Native(data.ToArray(), (ulong) data.Count);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern int Fun0Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 1)] byte[] data, \
+               ulong dataLen\
+             );
+
+             public unsafe int Fun0(ReadOnlySpan<byte> data) {
+               fixed (byte* dataPtr = data) {
+                 return Fun0SpanNative((IntPtr) dataPtr, (ulong) data.Length);
+               }
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern int Fun0SpanNative(IntPtr data, ulong dataLen);
+
+             public Task<List<byte>> Fun1Async(ulong id, List<byte> data) {
+               if (data == null) throw new ArgumentNullException(nameof(data));
+               var (ret, userData) = Utils.PrepareTask<List<byte>>();
+               Fun1Native(id, \
+                          data.ToArray(), \
+                          (ulong) data.Count, \
+                          userData, \
+                          OnFfiResultByteListCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               ulong id, \
+               [MarshalAs(UnmanagedType.LPArray, SizeParamIndex = 2)] byte[] data, \
+               ulong dataLen, \
+               IntPtr userData, \
+               FfiResultByteListCb oCb\
+             );
+
+             public unsafe Task Fun1Async(\
+               ulong id, \
+               ReadOnlySpan<byte> data, \
+               Utils.ReadOnlySpanAction<byte> handler\
+             ) {
+               var (ret, userData) = Utils.PrepareSpanTask(handler);
+               fixed (byte* dataPtr = data) {
+                 Fun1SpanNative(id, \
+                                (IntPtr) dataPtr, \
+                                (ulong) data.Length, \
+                                userData, \
+                                OnFfiResultByteListSpanCb);
+               }
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1SpanNative(ulong id, \
+                                                        IntPtr data, \
+                                                        ulong dataLen, \
+                                                        IntPtr userData, \
+                                                        FfiResultByteListCb oCb);
+
+             internal delegate void FfiResultByteListCb(IntPtr userData, \
+                                                        IntPtr result, \
+                                                        IntPtr dataPtr, \
+                                                        ulong dataLen);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultByteListCb))]
+             #endif
+             private static void OnFfiResultByteListCb(IntPtr userData, \
+                                                       IntPtr result, \
+                                                       IntPtr dataPtr, \
+                                                       ulong dataLen) {
+               Utils.CompleteTask(userData, \
+                                  Marshal.PtrToStructure<FfiResult>(result), \
+                                  () => Utils.CopyToByteList(dataPtr, (int) dataLen));
+             }
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultByteListCb))]
+             #endif
+             private static void OnFfiResultByteListSpanCb(IntPtr userData, \
+                                                           IntPtr result, \
+                                                           IntPtr dataPtr, \
+                                                           ulong dataLen) {
+               Utils.CompleteSpanTask<byte>(userData, \
+                                            Marshal.PtrToStructure<FfiResult>(result), \
+                                            dataPtr, \
+                                            dataLen);
+             }
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "IBackend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial interface IBackend {
+             int Fun0(List<byte> data);
+             int Fun0(ReadOnlySpan<byte> data);
+             Task<List<byte>> Fun1Async(ulong id, List<byte> data);
+             Task Fun1Async(ulong id, \
+                            ReadOnlySpan<byte> data, \
+                            Utils.ReadOnlySpanAction<byte> handler);
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains("public static unsafe void CompleteSpanTask<T>("));
+    assert!(actual.contains(
+        "} catch (Exception exception) {\n        state.SetException(exception);",
+    ));
+}
+
+#[test]
+fn functions_taking_callback_taking_const_size_array() {
+    let outputs = compile!(None, {
+        pub type Nonce = [u8; NONCE_LEN];
+
+        // Literal.
+        #[no_mangle]
+        pub extern "C" fn fun2(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              key: [u8; 32]),
+        ) {
+        }
+
+        // Pointor to array via named constant.
+        #[no_mangle]
+        pub extern "C" fn fun3(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              nonce: *const Nonce),
+        ) {
+        }
+
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<byte[]> Fun2Async() {
+               var (ret, userData) = Utils.PrepareTask<byte[]>();
+               Fun2Native(userData, OnFfiResultByteArray32Cb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun2\")]
+             internal static extern void Fun2Native(IntPtr userData, \
+                                                    FfiResultByteArray32Cb cb);
+
+             public Task<byte[]> Fun3Async() {
+               var (ret, userData) = Utils.PrepareTask<byte[]>();
+               Fun3Native(userData, OnFfiResultByteArrayNonceLenCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun3\")]
+             internal static extern void Fun3Native(IntPtr userData, \
+                                                    FfiResultByteArrayNonceLenCb cb);
+
+             internal delegate void FfiResultByteArray32Cb(IntPtr userData, \
+                                                           IntPtr result, \
+                                                           IntPtr key);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultByteArray32Cb))]
+             #endif
+             private static void OnFfiResultByteArray32Cb(IntPtr userData, \
+                                                          IntPtr result, \
+                                                          IntPtr key) {
+               Utils.CompleteTask(userData, \
+                                  Marshal.PtrToStructure<FfiResult>(result), \
+                                  () => Utils.CopyToByteArray(key, 32));
+             }
+
+             internal delegate void FfiResultByteArrayNonceLenCb(IntPtr userData, \
+                                                                 IntPtr result, \
+                                                                 IntPtr nonce);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultByteArrayNonceLenCb))]
+             #endif
+             private static void OnFfiResultByteArrayNonceLenCb(IntPtr userData, \
+                                                                IntPtr result, \
+                                                                IntPtr nonce) {
+               Utils.CompleteTask(\
+                 userData, \
+                 Marshal.PtrToStructure<FfiResult>(result), \
+                 () => Utils.CopyToByteArray(nonce, (int) Constants.NonceLen));
+             }
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_callback_taking_dynamic_array() {
+    let outputs = compile!(None, {
+        // Primitive type.
+        #[no_mangle]
+        pub extern "C" fn fun0(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              data_ptr: *const u8,
+                              data_len: usize),
+        ) {
+        }
+
+        // Structures.
+        #[no_mangle]
+        pub extern "C" fn fun1(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              records_ptr: *const Record,
+                              records_len: usize),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<List<byte>> Fun0Async() {
+               var (ret, userData) = Utils.PrepareTask<List<byte>>();
+               Fun0Native(userData, OnFfiResultByteListCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(IntPtr userData, \
+                                                    FfiResultByteListCb cb);
+
+             public Task<List<Record>> Fun1Async() {
                var (ret, userData) = Utils.PrepareTask<List<Record>>();
                Fun1Native(userData, OnFfiResultRecordListCb);
                return ret;
              }
 
-             [DllImport(DllName, EntryPoint = \"fun1\")]
-             internal static extern void Fun1Native(IntPtr userData, \
-                                                    FfiResultRecordListCb cb);
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(IntPtr userData, \
+                                                    FfiResultRecordListCb cb);
+
+             internal delegate void FfiResultByteListCb(IntPtr userData, \
+                                                        IntPtr result, \
+                                                        IntPtr dataPtr, \
+                                                        ulong dataLen);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultByteListCb))]
+             #endif
+             private static void OnFfiResultByteListCb(IntPtr userData, \
+                                                       IntPtr result, \
+                                                       IntPtr dataPtr, \
+                                                       ulong dataLen) {
+               Utils.CompleteTask(userData, \
+                                  Marshal.PtrToStructure<FfiResult>(result), \
+                                  () => Utils.CopyToByteList(dataPtr, (int) dataLen));
+             }
+
+             internal delegate void FfiResultRecordListCb(IntPtr userData, \
+                                                          IntPtr result, \
+                                                          IntPtr recordsPtr, \
+                                                          ulong recordsLen);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultRecordListCb))]
+             #endif
+             private static void OnFfiResultRecordListCb(IntPtr userData, \
+                                                         IntPtr result, \
+                                                         IntPtr recordsPtr, \
+                                                         ulong recordsLen) {
+               Utils.CompleteTask(userData, \
+                                  Marshal.PtrToStructure<FfiResult>(result), \
+                                  () => Utils.CopyToObjectList<Record>(\
+                                    recordsPtr, \
+                                    (int) recordsLen));
+             }
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_with_return_values() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(arg: i32) -> bool {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public bool Fun(int arg) {
+               var ret = FunNative(arg);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern bool FunNative(int arg);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_out_param() {
+    let outputs = compile!(None, {
+        #[repr(C)]
+        pub struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun0(o_app: *mut *mut App) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(id: u64, o_len: *mut usize, o_point: *mut Point) -> i32 {}
+
+        #[no_mangle]
+        #[bindgen(out(count))]
+        pub extern "C" fn fun2(count: *mut u32) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun3(o_name: *mut *const c_char) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public IntPtr Fun0() {
+               Fun0Native(out var app);
+               return app;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(out IntPtr oApp);
+
+             public (int ret, ulong len, Point point) Fun1(ulong id) {
+               var ret = Fun1Native(id, out var len, out var point);
+               return (ret, len, point);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern int Fun1Native(ulong id, out ulong oLen, out Point oPoint);
+
+             public uint Fun2() {
+               Fun2Native(out var count);
+               return count;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun2\")]
+             internal static extern void Fun2Native(out uint count);
+
+             public string Fun3() {
+               Fun3Native(out var nameNative);
+               var name = Utils.CopyToString(nameNative);
+               return name;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun3\")]
+             internal static extern void Fun3Native(out IntPtr oName);
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_taking_string_out_param_with_free_function() {
+    let mut lang = LangCSharp::new();
+    lang.set_string_free_function("string_free");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun(id: u64, o_name: *mut *const c_char) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public string Fun(ulong id) {
+               FunNative(id, out var nameNative);
+               var name = Utils.CopyToString(nameNative);
+               if (nameNative != IntPtr.Zero) StringFreeNative(nameNative);
+               return name;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(ulong id, out IntPtr oName);
+
+             [DllImport(DllName, EntryPoint = \"string_free\")]
+             internal static extern void StringFreeNative(IntPtr ptr);
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn functions_returning_error_code() {
+    let mut lang = LangCSharp::new();
+    lang.set_last_error_function("last_error");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn last_error() -> *const c_char {}
+
+        #[no_mangle]
+        pub extern "C" fn fun0(id: u64) -> i32 {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(id: u64, o_len: *mut usize) -> i32 {}
+
+        #[no_mangle]
+        pub extern "C" fn fun2(id: u64) -> u32 {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(ulong id) {
+               var ret = Fun0Native(id);
+               if (ret != 0) throw new FfiException(ret, Utils.CopyToString(LastErrorNative()));
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern int Fun0Native(ulong id);
+
+             public ulong Fun1(ulong id) {
+               var ret = Fun1Native(id, out var len);
+               if (ret != 0) throw new FfiException(ret, Utils.CopyToString(LastErrorNative()));
+               return len;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern int Fun1Native(ulong id, out ulong oLen);
+
+             public uint Fun2(ulong id) {
+               var ret = Fun2Native(id);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun2\")]
+             internal static extern uint Fun2Native(ulong id);
+
+             [DllImport(DllName, EntryPoint = \"last_error\")]
+             internal static extern IntPtr LastErrorNative();
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn async_functions_returning_error_code() {
+    let mut lang = LangCSharp::new();
+    lang.set_last_error_function("last_error");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun(
+            id: u64,
+            user_data: *mut c_void,
+            cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+        ) -> i32 {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<uint> FunAsync(ulong id) {
+               var (ret, userData) = Utils.PrepareTask<uint>();
+               var code = FunNative(id, userData, OnFfiResultUIntCb);
+               if (code != 0) Utils.FailTask(userData, \
+                              new FfiException(code, Utils.CopyToString(LastErrorNative())));
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern int FunNative(ulong id, \
+                                                  IntPtr userData, \
+                                                  FfiResultUIntCb cb);
+
+             [DllImport(DllName, EntryPoint = \"last_error\")]
+             internal static extern IntPtr LastErrorNative();
+
+             internal delegate void FfiResultUIntCb(IntPtr arg0, IntPtr arg1, uint arg2);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultUIntCb))]
+             #endif
+             private static void OnFfiResultUIntCb(IntPtr arg0, IntPtr arg1, uint arg2) {
+               Utils.CompleteTask(arg0, \
+                                  Marshal.PtrToStructure<FfiResult>(arg1), \
+                                  () => arg2);
+             }
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+
+    // The user data of progress tasks is an id, not a handle.
+    let mut lang = LangCSharp::new();
+    lang.set_last_error_function("last_error");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun(
+            id: u64,
+            user_data: *mut c_void,
+            o_progress: extern "C" fn(user_data: *mut c_void, done: u64),
+            o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) -> i32 {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    assert!(actual.contains("if (code != 0) Utils.FailProgressTask(userData, "));
+
+    let actual = fetch(&outputs, "Utils.cs");
+    assert!(actual.contains("public static void FailProgressTask(IntPtr userData, "));
+    assert!(actual.contains(
+        "progressTasks.Add(id, state);\n      }\n\n\
+         #if DEBUG\n      Interlocked.Increment(ref outstandingHandles);",
+    ));
+}
+
+#[test]
+fn cancellable_async_functions() {
+    let mut lang = LangCSharp::new();
+    lang.set_cancel_convention(Default::default());
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn cancel_token_new() -> *mut CancelToken {}
+
+        #[no_mangle]
+        pub extern "C" fn cancel_token_cancel(token: *const CancelToken) {}
+
+        #[no_mangle]
+        pub extern "C" fn cancel_token_free(token: *mut CancelToken) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun(
+            id: u64,
+            token: *const CancelToken,
+            user_data: *mut c_void,
+            cb: extern "C" fn(*mut c_void, *const FfiResult, u32),
+        ) {
+        }
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<uint> FunAsync(\
+               ulong id, \
+               CancellationToken cancellationToken = default(CancellationToken)) {
+               var cancelToken = CancelTokenNewNative();
+               var (ret, userData) = Utils.PrepareTask<uint>(\
+                 cancellationToken, \
+                 () => CancelTokenCancelNative(cancelToken), \
+                 () => CancelTokenFreeNative(cancelToken));
+               FunNative(id, cancelToken, userData, OnFfiResultUIntCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(ulong id, \
+                                                   IntPtr token, \
+                                                   IntPtr userData, \
+                                                   FfiResultUIntCb cb);
+
+             [DllImport(DllName, EntryPoint = \"cancel_token_new\")]
+             internal static extern IntPtr CancelTokenNewNative();
+
+             [DllImport(DllName, EntryPoint = \"cancel_token_cancel\")]
+             internal static extern void CancelTokenCancelNative(IntPtr token);
+
+             [DllImport(DllName, EntryPoint = \"cancel_token_free\")]
+             internal static extern void CancelTokenFreeNative(IntPtr token);
+
+             internal delegate void FfiResultUIntCb(IntPtr arg0, IntPtr arg1, uint arg2);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultUIntCb))]
+             #endif
+             private static void OnFfiResultUIntCb(IntPtr arg0, IntPtr arg1, uint arg2) {
+               Utils.CompleteTask(arg0, \
+                                  Marshal.PtrToStructure<FfiResult>(arg1), \
+                                  () => arg2);
+             }
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "IBackend.cs");
+    assert!(actual.contains(
+        "Task<uint> FunAsync(ulong id, \
+         CancellationToken cancellationToken = default(CancellationToken));",
+    ));
+    assert!(!actual.contains("CancelTokenNew"));
+}
+
+#[test]
+fn mock() {
+    let mut lang = LangCSharp::new();
+    lang.set_mock_enabled(true);
+    lang.add_span_function("fun1");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun0(id: u64, name: *const c_char) -> i32 {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(data: *const u8, data_len: usize) -> i32 {}
+
+        #[no_mangle]
+        pub extern "C" fn fun2(
+            id: u64,
+            user_data: *mut c_void,
+            o_cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun3() {}
+    });
+
+    let actual = fetch(&outputs, "BackendMock.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class BackendMock : IBackend {
+             public Func<ulong, string, int> Fun0Func { get; set; }
+
+             public int Fun0(ulong id, string name) {
+               if (Fun0Func == null) throw new NotImplementedException(nameof(Fun0));
+               return Fun0Func(id, name);
+             }
+
+             public Func<List<byte>, int> Fun1Func { get; set; }
+
+             public int Fun1(List<byte> data) {
+               if (Fun1Func == null) throw new NotImplementedException(nameof(Fun1));
+               return Fun1Func(data);
+             }
+
+             public delegate int Fun1SpanDelegate(ReadOnlySpan<byte> data);
+
+             public Fun1SpanDelegate Fun1SpanFunc { get; set; }
+
+             public int Fun1(ReadOnlySpan<byte> data) {
+               if (Fun1SpanFunc == null) throw new NotImplementedException(nameof(Fun1));
+               return Fun1SpanFunc(data);
+             }
+
+             public Func<ulong, Task> Fun2AsyncFunc { get; set; }
+
+             public Task Fun2Async(ulong id) {
+               if (Fun2AsyncFunc == null) throw new NotImplementedException(nameof(Fun2Async));
+               return Fun2AsyncFunc(id);
+             }
+
+             public Action Fun3Func { get; set; }
+
+             public void Fun3() {
+               if (Fun3Func == null) throw new NotImplementedException(nameof(Fun3));
+               Fun3Func();
+             }
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn mock_disabled_by_default() {
+    let outputs = compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun() {}
+    });
+
+    assert!(outputs.get(Path::new("BackendMock.cs")).is_none());
+}
+
+#[test]
+fn functions_taking_nullable_pointers() {
+    let outputs = compile!(None, {
+        #[repr(C)]
+        pub struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun0(point: Option<&Point>) {}
+
+        #[no_mangle]
+        #[bindgen(nullable(tag))]
+        pub extern "C" fn fun1(name: *const c_char, tag: *const c_char) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(Point? point) {
+               var pointPtr = Utils.CopyFromObject(point);
+               Fun0Native(pointPtr);
+               Utils.FreeObject(ref pointPtr);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(IntPtr point);
+
+             public void Fun1(string name, string tag) {
+               if (name == null) throw new ArgumentNullException(nameof(name));
+               Fun1Native(name, tag);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               [MarshalAs(UnmanagedType.LPUTF8Str)] string name, \
+               [MarshalAs(UnmanagedType.LPUTF8Str)] string tag);
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn constants() {
+    let mut lang = LangCSharp::new();
+    lang.add_const("byte", "Custom", 45);
+
+    let outputs = compile!(lang, {
+        pub const NUMBER: i32 = 123;
+        pub const STRING: &'static str = "hello world";
+        pub const ARRAY: [u8; 4] = [0, 1, 2, 3];
+
+        pub const STRUCT_VALUE: Record = Record {
+            id: 0,
+            secret_code: "xyz",
+        };
+
+        pub const STRUCT_REF: &'static Record = &Record {
+            id: 1,
+            secret_code: "xyz",
+        };
+
+        pub const EMPTY_STR: *const c_char = 0 as *const c_char;
+    });
+
+    let actual = fetch(&outputs, "Constants.cs");
+    let expected = indoc!(
+        "using System;
+
+         namespace Backend {
+           public static class Constants {
+             public const int Number = 123;
+             public const string String = \"hello world\";
+             public static readonly byte[] Array = new byte[] { 0, 1, 2, 3 };
+             public static readonly Record StructValue = new Record { \
+                 id = 0, secretCode = \"xyz\" };
+             public static readonly Record StructRef = new Record { \
+                 id = 1, secretCode = \"xyz\" };
+             public const string EmptyStr = \"\";
+             public const byte Custom = 45;
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn byte_string_constants() {
+    let outputs = compile!(None, {
+        pub const TAG: &'static [u8] = b"safe";
+        pub const NONCE: &'static [u8; 3] = b"\x00\x01\xff";
+        pub const MAGIC: &'static [u8] = &[0xca, 0xfe];
+    });
+
+    let actual = fetch(&outputs, "Constants.cs");
+    let expected = indoc!(
+        "using System;
+
+         namespace Backend {
+           public static class Constants {
+             public static readonly byte[] Tag = new byte[] { 115, 97, 102, 101 };
+             public static readonly byte[] Nonce = new byte[] { 0, 1, 255 };
+             public static readonly byte[] Magic = new byte[] { 202, 254 };
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn slice_references_outside_constants() {
+    // References to slices and arrays can't cross the FFI boundary as C# arrays.
+    let result = try_compile!(None, {
+        #[no_mangle]
+        pub extern "C" fn fun(data: &'static [u8]) {}
+    });
+    assert!(result.is_err());
+
+    let result = try_compile!(None, {
+        #[repr(C)]
+        pub struct Record {
+            pub key: &'static [u8; 32],
+        }
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn arrays() {
+    let outputs = compile!(None, {
+        pub const ARRAY_SIZE: usize = 20;
+        pub type Key = [u8; 32];
+
+        #[no_mangle]
+        pub extern "C" fn fun0(a: [u8; 10], b: [u8; ARRAY_SIZE]) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun1(a: *const [u8; 10]) {}
+
+        #[no_mangle]
+        pub extern "C" fn fun2(a: *const Key) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(byte[] a, byte[] b) {
+               if (a == null) throw new ArgumentNullException(nameof(a));
+               if (b == null) throw new ArgumentNullException(nameof(b));
+               Fun0Native(a, b);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = 10)] \
+               byte[] a, \
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = (int) Constants.ArraySize)] \
+               byte[] b);
+
+             public void Fun1(byte[] a) {
+               if (a == null) throw new ArgumentNullException(nameof(a));
+               Fun1Native(a);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun1\")]
+             internal static extern void Fun1Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = 10)] \
+               byte[] a);
+
+             public void Fun2(byte[] a) {
+               if (a == null) throw new ArgumentNullException(nameof(a));
+               Fun2Native(a);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun2\")]
+             internal static extern void Fun2Native(\
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = 32)] \
+               byte[] a);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn constant_expressions() {
+    let outputs = compile!(None, {
+        pub const HASH_LEN: usize = 32;
+        pub const NONCE_LEN: usize = mem::size_of::<u64>() * 3;
+        pub const KEY_LEN: usize = HASH_LEN + NONCE_LEN;
+        pub const UNSUPPORTED: usize = compute_len();
+        pub const ALL: u32 = !0;
+        pub const ALL_BUT_FIRST: u32 = ALL ^ 1;
+        pub const LOW_BITS: u8 = 0x1ff as u8;
+        pub const MAX: u64 = !0;
+        pub const MIN: i16 = !0x7fff;
+        pub const POINTER_SIZED: usize = !0;
+
+        #[no_mangle]
+        pub extern "C" fn fun(a: [u8; 2 * HASH_LEN], b: [u8; KEY_LEN - 1]) {}
+    });
+
+    let actual = fetch(&outputs, "Constants.cs");
+    let expected = indoc!(
+        "using System;
+
+         namespace Backend {
+           public static class Constants {
+             public const ulong HashLen = 32;
+             public const ulong NonceLen = 24;
+             public const ulong KeyLen = HashLen + NonceLen;
+             public const uint All = 4294967295;
+             public const uint AllButFirst = All ^ 1;
+             public const byte LowBits = 255;
+             public const ulong Max = 18446744073709551615;
+             public const short Min = -32768;
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun(byte[] a, byte[] b) {
+               if (a == null) throw new ArgumentNullException(nameof(a));
+               if (b == null) throw new ArgumentNullException(nameof(b));
+               FunNative(a, b);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun\")]
+             internal static extern void FunNative(\
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = 64)] \
+               byte[] a, \
+               [MarshalAs(UnmanagedType.LPArray, SizeConst = 55)] \
+               byte[] b);
+
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn opaque_types() {
+    let mut lang = LangCSharp::new();
+    lang.add_opaque_type("Handle");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn fun0(handle: *const Handle) {}
+
+        #[repr(C)]
+        pub struct Context {
+            handle: *const Handle,
+        }
+    });
+
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           public struct Context {
+             public IntPtr Handle;
+           }
+
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public void Fun0(IntPtr handle) {
+               Fun0Native(handle);
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(IntPtr handle);
+
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn opaque_types_with_safe_handles() {
+    let mut lang = LangCSharp::new();
+    lang.add_opaque_type("App");
+    lang.add_owned_callback_handle("App");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn app_connect(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              app: *mut App),
+        ) {
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_is_connected(app: *const App) -> bool {}
+
+        #[no_mangle]
+        pub extern "C" fn app_free(app: *mut App) {}
+    });
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public sealed class AppHandle : SafeHandle {
+             internal AppHandle() : base(IntPtr.Zero, true) {}
+
+             internal AppHandle(IntPtr handle, bool ownsHandle) : base(IntPtr.Zero, ownsHandle) {
+               SetHandle(handle);
+             }
+
+             public override bool IsInvalid => handle == IntPtr.Zero;
+
+             protected override bool ReleaseHandle() {
+               AppFreeNative(handle);
+               return true;
+             }
+
+             [DllImport(Backend.DllName, EntryPoint = \"app_free\")]
+             private static extern void AppFreeNative(IntPtr app);
+           }
+
+           public partial class Backend : IBackend {
+             #if __IOS__
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
+             #endif
+
+             public Task<AppHandle> AppConnectAsync() {
+               var (ret, userData) = Utils.PrepareTask<AppHandle>();
+               AppConnectNative(userData, OnFfiResultAppCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"app_connect\")]
+             internal static extern void AppConnectNative(IntPtr userData, FfiResultAppCb cb);
 
-             internal delegate void FfiResultByteListCb(IntPtr userData, \
-                                                        IntPtr result, \
-                                                        IntPtr dataPtr, \
-                                                        ulong dataLen);
+             public bool AppIsConnected(AppHandle app) {
+               if (app == null) throw new ArgumentNullException(nameof(app));
+               var ret = AppIsConnectedNative(app);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"app_is_connected\")]
+             internal static extern bool AppIsConnectedNative(AppHandle app);
+
+             internal delegate void FfiResultAppCb(IntPtr userData, IntPtr result, IntPtr app);
 
              #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultByteListCb))]
+             [MonoPInvokeCallback(typeof(FfiResultAppCb))]
              #endif
-             private static void OnFfiResultByteListCb(IntPtr userData, \
-                                                       IntPtr result, \
-                                                       IntPtr dataPtr, \
-                                                       ulong dataLen) {
-               Utils.CompleteTask(userData, \
-                                  Marshal.PtrToStructure<FfiResult>(result), \
-                                  () => Utils.CopyToByteList(dataPtr, (int) dataLen));
+             private static void OnFfiResultAppCb(IntPtr userData, IntPtr result, IntPtr app) {
+               Utils.CompleteTask(\
+                 userData, \
+                 Marshal.PtrToStructure<FfiResult>(result), \
+                 () => new AppHandle(app, true)\
+               );
              }
 
-             internal delegate void FfiResultRecordListCb(IntPtr userData, \
-                                                          IntPtr result, \
-                                                          IntPtr recordsPtr, \
-                                                          ulong recordsLen);
+           }
+         }
+        "
+    );
+
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn borrowed_safe_handles_in_callbacks() {
+    let mut lang = LangCSharp::new();
+    lang.add_opaque_type("App");
+
+    let outputs = compile!(lang, {
+        #[no_mangle]
+        pub extern "C" fn app_current(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              app: *const App),
+        ) {
+        }
+
+        #[no_mangle]
+        pub extern "C" fn app_free(app: *mut App) {}
+    });
+
+    // The app is still owned by the native code, so the handle must not release it.
+    let actual = fetch(&outputs, "Backend.cs");
+    assert!(actual.contains("() => new AppHandle(app, false)"));
+    assert!(!actual.contains("new AppHandle(app, true)"));
+}
+
+#[test]
+fn disposable_types() {
+    let mut lang = LangCSharp::new();
+    lang.add_disposable_type_with_destructor("Entry", "entry_free");
+    lang.add_disposable_type("Key");
+
+    let outputs = compile!(lang, {
+        #[repr(C)]
+        pub struct Entry {
+            id: u32,
+            key: Key,
+            data_ptr: *const u8,
+            data_len: usize,
+        }
+
+        #[repr(C)]
+        pub struct Key {
+            value: u64,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn fun0(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void,
+                              result: *const FfiResult,
+                              entry: *const Entry),
+        ) {
+        }
+
+        #[no_mangle]
+        pub extern "C" fn entry_free(entry: *mut Entry) {}
+    });
+
+    let actual = fetch(&outputs, "Types.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using JetBrains.Annotations;
+
+         namespace Backend {
+           [PublicAPI]
+           public sealed class Entry : IDisposable {
+             public uint Id;
+             public Key Key;
+             public List<byte> Data;
+             private IntPtr _nativePtr;
+
+             public Entry() {}
+
+             internal Entry(EntryNative native) {
+               Id = native.Id;
+               Key = new Key(native.Key);
+               Data = Utils.CopyToByteList(native.DataPtr, (int) native.DataLen);
+             }
+
+             internal Entry(IntPtr nativePtr) : \
+               this(Marshal.PtrToStructure<EntryNative>(nativePtr)) {
+               _nativePtr = nativePtr;
+             }
+
+             ~Entry() {
+               FreeNative();
+             }
+
+             public void Dispose() {
+               FreeNative();
+               GC.SuppressFinalize(this);
+             }
+
+             internal EntryNative ToNative() {
+               return new EntryNative() {
+                 Id = Id,
+                 Key = Key.ToNative(),
+                 DataPtr = Utils.CopyFromByteList(Data),
+                 DataLen = (ulong) Data.Count
+               };
+             }
+
+             private void FreeNative() {
+               if (_nativePtr != IntPtr.Zero) {
+                 EntryFreeNative(_nativePtr);
+                 _nativePtr = IntPtr.Zero;
+               }
+             }
+
+             [DllImport(Backend.DllName, EntryPoint = \"entry_free\")]
+             private static extern void EntryFreeNative(IntPtr ptr);
+           }
+
+           internal struct EntryNative {
+             public uint Id;
+             public KeyNative Key;
+             public IntPtr DataPtr;
+             public ulong DataLen;
+
+             internal void Free() {
+               Key.Free();
+               Utils.FreeList(ref DataPtr, ref DataLen);
+             }
+           }
+
+           [PublicAPI]
+           public sealed class Key : IDisposable {
+             public ulong Value;
+
+             public Key() {}
+
+             internal Key(KeyNative native) {
+               Value = native.Value;
+             }
+
+             public void Dispose() {}
 
+             internal KeyNative ToNative() {
+               return new KeyNative() {
+                 Value = Value
+               };
+             }
+           }
+
+           internal struct KeyNative {
+             public ulong Value;
+
+             internal void Free() {
+             }
+           }
+
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
+
+    let actual = fetch(&outputs, "Backend.cs");
+    let expected = indoc!(
+        "using System;
+         using System.Collections.Generic;
+         using System.Linq;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+
+         namespace Backend {
+           public partial class Backend : IBackend {
              #if __IOS__
-             [MonoPInvokeCallback(typeof(FfiResultRecordListCb))]
+             internal const string DllName = \"__Internal\";
+             #else
+             internal const string DllName = \"backend\";
              #endif
-             private static void OnFfiResultRecordListCb(IntPtr userData, \
-                                                         IntPtr result, \
-                                                         IntPtr recordsPtr, \
-                                                         ulong recordsLen) {
+
+             public Task<Entry> Fun0Async() {
+               var (ret, userData) = Utils.PrepareTask<Entry>();
+               Fun0Native(userData, OnFfiResultEntryCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"fun0\")]
+             internal static extern void Fun0Native(IntPtr userData, FfiResultEntryCb cb);
+
+             internal delegate void FfiResultEntryCb(IntPtr userData, \
+                                                     IntPtr result, \
+                                                     IntPtr entry);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultEntryCb))]
+             #endif
+             private static void OnFfiResultEntryCb(IntPtr userData, \
+                                                    IntPtr result, \
+                                                    IntPtr entry) {
                Utils.CompleteTask(userData, \
                                   Marshal.PtrToStructure<FfiResult>(result), \
-                                  () => Utils.CopyToObjectList<Record>(\
-                                    recordsPtr, \
-                                    (int) recordsLen));
+                                  () => new Entry(entry));
              }
 
            }
          }
         "
     );
+    assert_multiline_eq!(actual, expected);
+}
+
+#[test]
+fn disposable_types_with_invalid_destructors() {
+    // Missing destructor.
+    let mut lang = LangCSharp::new();
+    lang.add_disposable_type_with_destructor("Entry", "entry_free");
+
+    let result = try_compile!(lang, {
+        #[repr(C)]
+        pub struct Entry {
+            id: u32,
+        }
+    });
+    assert!(result.is_err());
+
+    // Destructor of a different type.
+    let mut lang = LangCSharp::new();
+    lang.add_disposable_type_with_destructor("Entry", "entry_free");
 
-    assert_multiline_eq!(actual, expected);
+    let result = try_compile!(lang, {
+        #[repr(C)]
+        pub struct Entry {
+            id: u32,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn entry_free(id: u32) {}
+    });
+    assert!(result.is_err());
 }
 
 #[test]
-fn functions_with_return_values() {
+fn interface() {
     let outputs = compile!(None, {
         #[no_mangle]
-        pub extern "C" fn fun(arg: i32) -> bool {}
+        pub extern "C" fn fun(
+            enabled: bool,
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
+        }
     });
 
-    let actual = fetch(&outputs, "Backend.cs");
+    let actual = fetch(&outputs, "IBackend.cs");
     let expected = indoc!(
         "using System;
          using System.Collections.Generic;
-         using System.Linq;
          using System.Runtime.InteropServices;
          using System.Threading.Tasks;
 
          namespace Backend {
-           public partial class Backend : IBackend {
-             #if __IOS__
-             internal const string DllName = \"__Internal\";
-             #else
-             internal const string DllName = \"backend\";
-             #endif
-
-             public bool Fun(int arg) {
-               var ret = FunNative(arg);
-               return ret;
-             }
-
-             [DllImport(DllName, EntryPoint = \"fun\")]
-             internal static extern bool FunNative(int arg);
-
+           public partial interface IBackend {
+             Task FunAsync(bool enabled);
            }
          }
         "
@@ -941,10 +3400,23 @@ fn functions_with_return_values() {
 }
 
 #[test]
-fn functions_taking_out_param() {
-    let outputs = compile!(None, {
+fn platforms() {
+    let mut lang = LangCSharp::new();
+    lang.set_platform(Platform::unity());
+
+    let outputs = compile!(lang, {
+        #[repr(C)]
+        pub struct Record {
+            id: u64,
+        }
+
         #[no_mangle]
-        pub extern "C" fn fun(o_app: *mut *mut App) {}
+        pub extern "C" fn fun(
+            record: Record,
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
+        }
     });
 
     let actual = fetch(&outputs, "Backend.cs");
@@ -957,167 +3429,228 @@ fn functions_taking_out_param() {
 
          namespace Backend {
            public partial class Backend : IBackend {
-             #if __IOS__
+             #if UNITY_IOS && !UNITY_EDITOR
              internal const string DllName = \"__Internal\";
              #else
              internal const string DllName = \"backend\";
              #endif
 
-             public void Fun(out IntPtr oApp) {
-               FunNative(out oApp);
+             public Task FunAsync(Record record) {
+               var (ret, userData) = Utils.PrepareTask();
+               FunNative(record, userData, OnFfiResultCb);
+               return ret;
              }
 
-             [DllImport(DllName, EntryPoint = \"fun\")]
-             internal static extern void FunNative(out IntPtr oApp);
+             [DllImport(DllName, CallingConvention = CallingConvention.Cdecl, EntryPoint = \"fun\")]
+             internal static extern void FunNative(Record record, IntPtr userData, FfiResultCb cb);
+
+             [UnmanagedFunctionPointer(CallingConvention.Cdecl)]
+             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+
+             [AOT.MonoPInvokeCallback(typeof(FfiResultCb))]
+             private static void OnFfiResultCb(IntPtr userData, IntPtr result) {
+               Utils.CompleteTask(userData, Marshal.PtrToStructure<FfiResult>(result));
+             }
 
            }
          }
         "
     );
     assert_multiline_eq!(actual, expected);
-}
 
-#[test]
-fn constants() {
-    let mut lang = LangCSharp::new();
-    lang.add_const("byte", "Custom", 45);
-
-    let outputs = compile!(lang, {
-        pub const NUMBER: i32 = 123;
-        pub const STRING: &'static str = "hello world";
-        pub const ARRAY: [u8; 4] = [0, 1, 2, 3];
-
-        pub const STRUCT_VALUE: Record = Record {
-            id: 0,
-            secret_code: "xyz",
-        };
-
-        pub const STRUCT_REF: &'static Record = &Record {
-            id: 1,
-            secret_code: "xyz",
-        };
-
-        pub const EMPTY_STR: *const c_char = 0 as *const c_char;
-    });
-
-    let actual = fetch(&outputs, "Constants.cs");
+    let actual = fetch(&outputs, "Types.cs");
     let expected = indoc!(
         "using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
 
          namespace Backend {
-           public static class Constants {
-             public const int Number = 123;
-             public const string String = \"hello world\";
-             public static readonly byte[] Array = new byte[] { 0, 1, 2, 3 };
-             public static readonly Record StructValue = new Record { \
-                 id = 0, secretCode = \"xyz\" };
-             public static readonly Record StructRef = new Record { \
-                 id = 1, secretCode = \"xyz\" };
-             public const string EmptyStr = \"\";
-             public const byte Custom = 45;
+           public struct Record {
+             public ulong Id;
            }
+
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
-}
-
-#[test]
-fn arrays() {
-    let outputs = compile!(None, {
-        pub const ARRAY_SIZE: usize = 20;
-        pub type Key = [u8; 32];
 
-        #[no_mangle]
-        pub extern "C" fn fun0(a: [u8; 10], b: [u8; ARRAY_SIZE]) {}
+    let mut platform = Platform::netstandard();
+    platform.nullable = Some(false);
+    platform.usings.push("System.Buffers".to_string());
 
-        #[no_mangle]
-        pub extern "C" fn fun1(a: *const [u8; 10]) {}
+    let mut lang = LangCSharp::new();
+    lang.set_platform(platform);
 
+    let outputs = compile!(lang, {
         #[no_mangle]
-        pub extern "C" fn fun2(a: *const Key) {}
+        pub extern "C" fn fun(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
+        }
     });
 
     let actual = fetch(&outputs, "Backend.cs");
     let expected = indoc!(
-        "using System;
+        "#nullable disable
+
+         using System;
          using System.Collections.Generic;
          using System.Linq;
          using System.Runtime.InteropServices;
          using System.Threading.Tasks;
+         using System.Buffers;
 
          namespace Backend {
            public partial class Backend : IBackend {
-             #if __IOS__
-             internal const string DllName = \"__Internal\";
-             #else
              internal const string DllName = \"backend\";
-             #endif
 
-             public void Fun0(byte[] a, byte[] b) {
-               Fun0Native(a, b);
+             public Task FunAsync() {
+               var (ret, userData) = Utils.PrepareTask();
+               FunNative(userData, OnFfiResultCb);
+               return ret;
              }
 
-             [DllImport(DllName, EntryPoint = \"fun0\")]
-             internal static extern void Fun0Native(\
-               [MarshalAs(UnmanagedType.LPArray, SizeConst = 10)] \
-               byte[] a, \
-               [MarshalAs(UnmanagedType.LPArray, SizeConst = (int) Constants.ArraySize)] \
-               byte[] b);
+             [DllImport(DllName, CallingConvention = CallingConvention.Cdecl, EntryPoint = \"fun\")]
+             internal static extern void FunNative(IntPtr userData, FfiResultCb cb);
 
-             public void Fun1(byte[] a) {
-               Fun1Native(a);
+             [UnmanagedFunctionPointer(CallingConvention.Cdecl)]
+             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+
+             private static void OnFfiResultCb(IntPtr userData, IntPtr result) {
+               Utils.CompleteTask(userData, Marshal.PtrToStructure<FfiResult>(result));
              }
 
-             [DllImport(DllName, EntryPoint = \"fun1\")]
-             internal static extern void Fun1Native(\
-               [MarshalAs(UnmanagedType.LPArray, SizeConst = 10)] \
-               byte[] a);
+           }
+         }
+        "
+    );
+    assert_multiline_eq!(actual, expected);
 
-             public void Fun2(byte[] a) {
-               Fun2Native(a);
-             }
+    let actual = fetch(&outputs, "IBackend.cs");
+    let expected = indoc!(
+        "#nullable disable
 
-             [DllImport(DllName, EntryPoint = \"fun2\")]
-             internal static extern void Fun2Native(\
-               [MarshalAs(UnmanagedType.LPArray, SizeConst = 32)] \
-               byte[] a);
+         using System;
+         using System.Collections.Generic;
+         using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
+         using System.Buffers;
 
+         namespace Backend {
+           public partial interface IBackend {
+             Task FunAsync();
            }
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 #[test]
-fn opaque_types() {
+fn calling_convention_of_callbacks() {
+    let mut platform = Platform::netstandard();
+    platform.calling_convention = Some("StdCall".to_string());
+
     let mut lang = LangCSharp::new();
-    lang.add_opaque_type("Handle");
+    lang.set_platform(platform);
+    lang.set_blittable(true);
 
     let outputs = compile!(lang, {
         #[no_mangle]
-        pub extern "C" fn fun0(handle: *const Handle) {}
-
-        #[repr(C)]
-        pub struct Context {
-            handle: *const Handle,
+        pub extern "C" fn fun(
+            user_data: *mut c_void,
+            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
+        ) {
         }
     });
 
-    let actual = fetch(&outputs, "Types.cs");
+    let actual = fetch(&outputs, "Backend.cs");
+    assert!(actual.contains(
+        "internal static extern void FunNative(\
+         IntPtr userData, \
+         delegate* unmanaged[Stdcall]<IntPtr, IntPtr, void> cb);",
+    ));
+    assert!(actual.contains(
+        "[UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvStdcall) })]\n",
+    ));
+}
+
+#[test]
+fn module_sections() {
+    let mut lang = LangCSharp::new();
+    lang.add_module_section("ffi::mdata", "MData.cs", "Backend.MData");
+
+    let lib_src = indoc!(
+        "#[repr(C)]
+         pub struct Key {
+             id: u64,
+         }
+
+         #[no_mangle]
+         pub extern \"C\" fn fun0(key: Key) {}
+        "
+    );
+
+    let mdata_src = indoc!(
+        "#[repr(C)]
+         pub struct Entry {
+             key: Key,
+             version: u64,
+         }
+
+         #[no_mangle]
+         pub extern \"C\" fn mdata_get(
+             entry: Entry,
+             user_data: *mut c_void,
+             cb: extern \"C\" fn(user_data: *mut c_void, result: *const FfiResult),
+         ) {
+         }
+        "
+    );
+
+    let outputs = try_compile_modules(
+        lang,
+        vec![
+            (Vec::new(), lib_src.to_string()),
+            (
+                vec!["ffi".to_string(), "mdata".to_string()],
+                mdata_src.to_string()
+            ),
+        ],
+    ).unwrap();
+
+    let actual = fetch(&outputs, "MData.cs");
     let expected = indoc!(
         "using System;
          using System.Collections.Generic;
+         using System.Linq;
          using System.Runtime.InteropServices;
+         using System.Threading.Tasks;
          using JetBrains.Annotations;
+         using Backend.MData;
 
          namespace Backend {
+           public partial class Backend {
+             public Task MdataGetAsync(Entry entry) {
+               var (ret, userData) = Utils.PrepareTask();
+               MdataGetNative(entry, userData, OnFfiResultCb);
+               return ret;
+             }
+
+             [DllImport(DllName, EntryPoint = \"mdata_get\")]
+             internal static extern void MdataGetNative(Entry entry, \
+                                                        IntPtr userData, \
+                                                        FfiResultCb cb);
+
+           }
+         }
+
+         namespace Backend.MData {
            [PublicAPI]
-           public struct Context {
-             public IntPtr Handle;
+           public struct Entry {
+             public Key Key;
+             public ulong Version;
            }
 
          }
@@ -1132,6 +3665,7 @@ fn opaque_types() {
          using System.Linq;
          using System.Runtime.InteropServices;
          using System.Threading.Tasks;
+         using Backend.MData;
 
          namespace Backend {
            public partial class Backend : IBackend {
@@ -1141,65 +3675,76 @@ fn opaque_types() {
              internal const string DllName = \"backend\";
              #endif
 
-             public void Fun0(IntPtr handle) {
-               Fun0Native(handle);
+             public void Fun0(Key key) {
+               Fun0Native(key);
              }
 
              [DllImport(DllName, EntryPoint = \"fun0\")]
-             internal static extern void Fun0Native(IntPtr handle);
+             internal static extern void Fun0Native(Key key);
+
+             internal delegate void FfiResultCb(IntPtr userData, IntPtr result);
+
+             #if __IOS__
+             [MonoPInvokeCallback(typeof(FfiResultCb))]
+             #endif
+             private static void OnFfiResultCb(IntPtr userData, IntPtr result) {
+               Utils.CompleteTask(userData, Marshal.PtrToStructure<FfiResult>(result));
+             }
 
            }
          }
         "
     );
     assert_multiline_eq!(actual, expected);
-}
-
-#[test]
-fn interface() {
-    let outputs = compile!(None, {
-        #[no_mangle]
-        pub extern "C" fn fun(
-            enabled: bool,
-            user_data: *mut c_void,
-            cb: extern "C" fn(user_data: *mut c_void, result: *const FfiResult),
-        ) {
-        }
-    });
 
-    let actual = fetch(&outputs, "IBackend.cs");
+    let actual = fetch(&outputs, "Types.cs");
     let expected = indoc!(
         "using System;
          using System.Collections.Generic;
          using System.Runtime.InteropServices;
-         using System.Threading.Tasks;
+         using JetBrains.Annotations;
+         using Backend.MData;
 
          namespace Backend {
-           public partial interface IBackend {
-             Task FunAsync(bool enabled);
+           [PublicAPI]
+           public struct Key {
+             public ulong Id;
            }
+
          }
         "
     );
-
     assert_multiline_eq!(actual, expected);
 }
 
 fn try_compile<T: Into<Option<LangCSharp>>>(
     lang: T,
     rust_src: String,
+) -> Result<HashMap<PathBuf, String>, Vec<Error>> {
+    try_compile_modules(lang, vec![(Vec::new(), rust_src)])
+}
+
+// Compile the sources as if they were the given modules (e.g. `["ffi", "mdata"]`).
+fn try_compile_modules<T: Into<Option<LangCSharp>>>(
+    lang: T,
+    modules: Vec<(Vec<String>, String)>,
 ) -> Result<HashMap<PathBuf, String>, Vec<Error>> {
     use parse;
     use syntax;
 
     let session = syntax::parse::ParseSess::new();
-    let ast = syntax::parse::parse_crate_from_source_str("lib.rs".to_string(), rust_src, &session)
-        .unwrap();
-
     let mut outputs = Outputs::default();
     let mut lang = lang.into().unwrap_or_else(|| LangCSharp::new());
 
-    parse::parse_mod(&mut lang, &ast.module, &mut outputs)?;
+    for (path, rust_src) in modules {
+        let ast =
+            syntax::parse::parse_crate_from_source_str("lib.rs".to_string(), rust_src, &session)
+                .unwrap();
+
+        lang.set_current_module(&path);
+        parse::parse_mod(&mut lang, &ast.module, &mut outputs)?;
+    }
+
     lang.finalise_output(&mut outputs)?;
 
     Ok(outputs)
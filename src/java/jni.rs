@@ -1,8 +1,9 @@
 //! Functions to generate JNI bindings
 
-use common::{append_output, is_array_arg, is_user_data_arg};
+use common::{self, append_output, is_array_arg};
 use inflector::Inflector;
-use java::{Context, Outputs, callback_name};
+use java::{Context, Outputs, callback_name, callback_ty, fixed_array_ty, returned_out_param,
+           returns_error_code};
 use jni::signature::{self, JavaType, TypeSignature};
 use quote;
 use struct_field::StructField;
@@ -15,7 +16,24 @@ fn to_jni_arg(arg: &ast::Arg, ty_name: &str) -> quote::Tokens {
     quote! { #pat: #ty_name }
 }
 
-fn transform_jni_arg(arg: &ast::Arg) -> quote::Tokens {
+fn transform_jni_arg(arg: &ast::Arg, context: &Context) -> quote::Tokens {
+    // Callbacks and pointers which can be null
+    if callback_ty(&arg.ty).is_some() {
+        return to_jni_arg(arg, "JObject");
+    }
+    if let Some(pointee) = common::nullable_pointee(&arg.ty) {
+        return match pprust::ty_to_string(pointee).as_str() {
+            "c_char" => to_jni_arg(arg, "JString"),
+            _ => to_jni_arg(arg, "JObject"),
+        };
+    }
+    if common::generic_param(&arg.ty, "NonNull").is_some() {
+        return to_jni_arg(arg, "JObject");
+    }
+    if context.is_cancel_token(&arg.ty) {
+        return to_jni_arg(arg, "jlong");
+    }
+
     match arg.ty.node {
         // Callback
         ast::TyKind::BareFn(ref _bare_fn) => to_jni_arg(arg, "JObject"),
@@ -39,6 +57,9 @@ fn transform_jni_arg(arg: &ast::Arg) -> quote::Tokens {
             to_jni_arg(arg, jni_type)
         }
 
+        // Fixed-size arrays are passed as Java arrays.
+        ast::TyKind::Array(..) => to_jni_arg(arg, "JObject"),
+
         // Standard pointers.
         ast::TyKind::Ptr(ref ptr) => {
             // Detect strings, which are *const c_char or *mut c_char
@@ -69,6 +90,133 @@ fn fully_qualified(ty: &str, context: &Context) -> String {
     format!("{}/{}", context.namespace_model.replace(".", "/"), ty)
 }
 
+/// Generates helper functions shared by all JNI bindings: translation of
+/// errors and panics into Java exceptions and logging of failed callbacks.
+pub fn generate_prelude(context: &Context) -> String {
+    let exception_class = context.exception_class.replace(".", "/");
+    let log_macro = quote::Ident::new(context.log_macro.as_str());
+
+    let tokens =
+        quote! {
+            fn panic_message(payload: Box<::std::any::Any + Send>) -> String {
+                if let Some(message) = payload.downcast_ref::<&str>() {
+                    message.to_string()
+                } else if let Some(message) = payload.downcast_ref::<String>() {
+                    message.clone()
+                } else {
+                    "panic in native code".to_owned()
+                }
+            }
+
+            // Throws the exception class, constructed from the error code and the message.
+            fn throw_exception(env: &JNIEnv, code: i32, message: JObject) -> Result<(), JniError> {
+                let exception = env.new_object(
+                    #exception_class,
+                    "(ILjava/lang/String;)V",
+                    &[
+                        ::jni::objects::JValue::Int(code),
+                        ::jni::objects::JValue::Object(message),
+                    ],
+                )?;
+                env.throw(::jni::objects::JThrowable::from(exception))?;
+                Ok(())
+            }
+
+            fn throw_java_exception<T>(
+                env: &JNIEnv,
+                res: ::std::thread::Result<Result<T, JniError>>,
+            ) -> T {
+                let message = match res {
+                    Ok(Ok(value)) => return value,
+                    Ok(Err(err)) => format!("{}", err),
+                    Err(payload) => panic_message(payload),
+                };
+
+                // Keep the exception if it has been thrown by the Java code already.
+                // Errors of the bindings themselves are reported with code -1.
+                if !env.exception_check().unwrap_or(false) {
+                    if let Ok(message) = env.new_string(message) {
+                        let _ = throw_exception(env, -1, message.into());
+                    }
+                }
+
+                // Java ignores the returned value if an exception is pending.
+                unsafe { ::std::mem::zeroed() }
+            }
+
+            fn check_not_null(name: &str, null: bool) -> Result<(), JniError> {
+                if null {
+                    let message = format!("argument `{}` can not be null", name);
+                    return Err(message.as_str().into());
+                }
+
+                Ok(())
+            }
+
+            fn check_array_len(
+                name: &str,
+                actual: usize,
+                expected: usize,
+            ) -> Result<(), JniError> {
+                if actual != expected {
+                    let message = format!(
+                        "invalid length of array `{}`: expected {}, got {}",
+                        name,
+                        expected,
+                        actual
+                    );
+                    return Err(message.as_str().into());
+                }
+
+                Ok(())
+            }
+
+            // Moves the items of the vector into the array starting at `ptr`, which
+            // must have room for all of them.
+            unsafe fn move_into_array<T>(items: Vec<T>, ptr: *mut T) {
+                for (index, item) in items.into_iter().enumerate() {
+                    ::std::ptr::write(ptr.add(index), item);
+                }
+            }
+
+            fn log_callback_error(
+                cb_name: &str,
+                res: ::std::thread::Result<Result<(), JniError>>,
+            ) {
+                let message = match res {
+                    Ok(Ok(())) => return,
+                    Ok(Err(err)) => format!("{}", err),
+                    Err(payload) => panic_message(payload),
+                };
+
+                #log_macro!("Error in JNI callback {}: {}", cb_name, message);
+            }
+        };
+
+    let mut prelude = tokens.to_string();
+
+    if let Some(ref last_error) = context.last_error_function {
+        let last_error = quote::Ident::new(last_error.as_str());
+        let tokens =
+            quote! {
+                fn check_error_code(env: &JNIEnv, code: i32) -> Result<(), JniError> {
+                    if code == 0 {
+                        return Ok(());
+                    }
+
+                    let description: JObject = unsafe { #last_error() }.to_java(env)?.into();
+                    throw_exception(env, code, description)?;
+
+                    // The pending exception is kept by `throw_java_exception`.
+                    Err("native function returned an error code".into())
+                }
+            };
+        prelude.push_str(&tokens.to_string());
+    }
+
+    prelude
+}
+
 fn rust_ty_to_signature(ty: &ast::Ty, context: &Context) -> Option<JavaType> {
     match ty.node {
         // Callback
@@ -112,6 +260,11 @@ fn rust_ty_to_signature(ty: &ast::Ty, context: &Context) -> Option<JavaType> {
             }
         }
 
+        // Fixed-size arrays.
+        ast::TyKind::Array(ref elem, _) => {
+            rust_ty_to_signature(elem, context).map(|elem| JavaType::Array(Box::new(elem)))
+        }
+
         _ => None,
 
     }
@@ -124,10 +277,12 @@ struct JniArgResult {
 
 fn transform_string_arg(arg_name: &str) -> JniArgResult {
     // statements
+    let name = arg_name;
     let arg_name = quote::Ident::new(arg_name);
     let stmt =
         quote! {
-            let #arg_name = jni_unwrap!(CString::from_java(&env, #arg_name));
+            check_not_null(#name, #arg_name.is_null())?;
+            let #arg_name = CString::from_java(&env, #arg_name)?;
         };
 
     // call arg value(s)
@@ -138,11 +293,13 @@ fn transform_string_arg(arg_name: &str) -> JniArgResult {
 
 fn transform_struct_arg(arg_name: &str, arg_ty: &ast::Ty) -> JniArgResult {
     // statements
+    let name = arg_name;
     let arg_name = quote::Ident::new(arg_name);
     let struct_ty = quote::Ident::new(pprust::ty_to_string(arg_ty));
     let stmt =
         quote! {
-            let #arg_name = jni_unwrap!(#struct_ty::from_java(&env, #arg_name));
+            check_not_null(#name, #arg_name.is_null())?;
+            let #arg_name = #struct_ty::from_java(&env, #arg_name)?;
         };
 
     // call arg value(s)
@@ -151,12 +308,43 @@ fn transform_struct_arg(arg_name: &str, arg_ty: &ast::Ty) -> JniArgResult {
     JniArgResult { stmt, call_args }
 }
 
+/// Converts a string or struct which can be null into an `Option`, passed to
+/// the native function as `call_arg`.
+fn transform_nullable_arg(
+    arg_name: &str,
+    pointee: &ast::Ty,
+    call_arg: quote::Tokens,
+) -> JniArgResult {
+    // statements
+    let arg_name = quote::Ident::new(arg_name);
+    let value = match pprust::ty_to_string(pointee).as_str() {
+        "c_char" => quote! { CString::from_java(&env, #arg_name)? },
+        struct_ty => {
+            let struct_ty = quote::Ident::new(struct_ty);
+            quote! { #struct_ty::from_java(&env, #arg_name)? }
+        }
+    };
+    let stmt =
+        quote! {
+            let #arg_name = if #arg_name.is_null() {
+                None
+            } else {
+                Some(#value)
+            };
+        };
+
+    // call arg value(s)
+    let call_args = vec![call_arg];
+
+    JniArgResult { stmt, call_args }
+}
+
 fn transform_array_arg(arg_name: &str) -> JniArgResult {
     // statements
     let arg_name = quote::Ident::new(arg_name);
     let stmt =
         quote! {
-            let #arg_name = jni_unwrap!(Vec::from_java(&env, #arg_name));
+            let #arg_name = Vec::from_java(&env, #arg_name)?;
         };
 
     // call arg value(s)
@@ -165,14 +353,64 @@ fn transform_array_arg(arg_name: &str) -> JniArgResult {
     JniArgResult { stmt, call_args }
 }
 
+/// Converts a `Vec` obtained from a Java array into a fixed-size array,
+/// checking that the Java array has the expected length. The elements are
+/// moved, so they don't have to implement `Copy` or `Default`.
+fn vec_to_fixed_array(
+    ident: &quote::Ident,
+    name: &str,
+    elem: &ast::Ty,
+    len: &ast::Expr,
+    mutable: bool,
+) -> quote::Tokens {
+    let elem = quote::Ident::new(pprust::ty_to_string(elem));
+    let len = quote::Ident::new(pprust::expr_to_string(len));
+    let mutability = if mutable { quote! { mut } } else { quote!{} };
+
+    quote! {
+        let #ident = Vec::<#elem>::from_java(&env, #ident)?;
+        check_array_len(#name, #ident.len(), #len)?;
+        let #mutability #ident = {
+            let mut arr = ::std::mem::MaybeUninit::<[#elem; #len]>::uninit();
+            // The length has been checked, so all the elements are initialised.
+            unsafe {
+                move_into_array(#ident, arr.as_mut_ptr() as *mut #elem);
+                arr.assume_init()
+            }
+        };
+    }
+}
+
+fn transform_fixed_array_arg(arg_name: &str, arg_ty: &ast::Ty) -> JniArgResult {
+    let ident = quote::Ident::new(arg_name);
+    let (elem, len) = fixed_array_ty(arg_ty).expect("fixed-size array type expected");
+
+    // Arrays behind a pointer are passed by reference, others by value
+    let (mutable, call_arg) = match arg_ty.node {
+        ast::TyKind::Ptr(ast::MutTy { mutbl: ast::Mutability::Mutable, .. }) => {
+            (true, quote! { &mut #ident })
+        }
+        ast::TyKind::Ptr(..) => (false, quote! { &#ident }),
+        _ => (false, quote! { #ident }),
+    };
+
+    // statements
+    let stmt = vec_to_fixed_array(&ident, arg_name, elem, len, mutable);
+
+    // call arg value(s)
+    let call_args = vec![call_arg];
+
+    JniArgResult { stmt, call_args }
+}
+
 fn transform_callbacks_arg(
-    cb_idents: &[(ast::BareFnTy, quote::Ident)],
+    cb_idents: &[(ast::BareFnTy, quote::Ident, bool)],
     cb_base_name: &str,
 ) -> JniArgResult {
     // statements
     let cb_ids: Vec<quote::Ident> = cb_idents
         .iter()
-        .map(|&(_, ref ident)| ident.clone())
+        .map(|&(_, ref ident, _)| ident.clone())
         .collect();
 
     let stmt =
@@ -186,13 +424,19 @@ fn transform_callbacks_arg(
     let call_args = cb_idents
         .iter()
         .enumerate()
-        .map(|(idx, _)| {
+        .map(|(idx, &(_, ref ident, nullable))| {
             let cb_fn = if multi_callback {
                 quote::Ident::new(format!("{}_{}", cb_base_name, idx))
             } else {
                 quote::Ident::new(cb_base_name)
             };
-            quote! { #cb_fn }
+
+            // Optional callbacks are not called if Java passes null
+            if nullable {
+                quote! { if #ident.is_null() { None } else { Some(#cb_fn) } }
+            } else {
+                quote! { #cb_fn }
+            }
         })
         .collect();
 
@@ -216,9 +460,11 @@ fn transform_opaque_ptr(arg_name: &str, ty: &str) -> JniArgResult {
 
 /// Generates JNI function binding based on a native function
 pub fn generate_jni_function(
-    args: Vec<ast::Arg>,
+    fn_decl: &ast::FnDecl,
     native_name: &str,
     func_name: &str,
+    nullable: &[String],
+    out: &[String],
     context: &mut Context,
     outputs: &mut Outputs,
 ) -> String {
@@ -235,33 +481,100 @@ pub fn generate_jni_function(
     let mut stmts = Vec::new();
     let mut callbacks = Vec::new();
     let mut jni_fn_inputs = Vec::new();
+    let mut out_stmts = Vec::new();
+    let mut returned_out = None;
+    let mut callback_slots = Vec::new();
+
+    let returned_out_name = returned_out_param(fn_decl, out, context).map(
+        |arg| pprust::pat_to_string(&*arg.pat),
+    );
 
-    let mut args_iter = args.into_iter()
-        .filter(|arg| !is_user_data_arg(arg))
-        .peekable();
+    let mut args_iter = fn_decl.inputs.iter().peekable();
 
     while let Some(arg) = args_iter.next() {
         let arg_name = pprust::pat_to_string(&*arg.pat);
+        let ident = quote::Ident::new(arg_name.as_str());
+
+        // The user data holds references to the Java callbacks
+        if context.callbacks.is_user_data_arg(arg) {
+            call_args.push(quote! { ctx });
+            continue;
+        }
+
+        // Values of out-parameters are returned or stored in one-element Java arrays
+        if out.contains(&arg_name) {
+            if let ast::TyKind::Ptr(ref ptr) = arg.ty.node {
+                if returned_out_name.as_ref() == Some(&arg_name) {
+                    returned_out = Some(returned_value(&ident, &ptr.ty));
+                } else {
+                    let holder = quote::Ident::new(format!("{}_holder", arg_name));
+                    stmts.push(quote! { let #holder = #ident; });
+                    out_stmts.push(store_out_value(&ident, &holder, &ptr.ty));
+                    jni_fn_inputs.push(to_jni_arg(arg, "JObject"));
+                }
+
+                let out_ty = quote::Ident::new(pprust::ty_to_string(&ptr.ty));
+                stmts.push(quote! { let mut #ident: #out_ty = ::std::mem::zeroed(); });
+                call_args.push(quote! { &mut #ident });
+                continue;
+            }
+        }
 
-        let res = if is_array_arg(&arg, args_iter.peek()) {
+        let res = if is_array_arg(arg, args_iter.peek().cloned(), &context.callbacks) {
             args_iter.next();
             Some(transform_array_arg(&arg_name))
+        } else if fixed_array_ty(&arg.ty).is_some() {
+            Some(transform_fixed_array_arg(&arg_name, &arg.ty))
+        } else if let Some(bare_fn) = callback_ty(&arg.ty) {
+            // Callback, optional if wrapped in `Option`
+            let nullable = common::nullable_fn(&arg.ty).is_some();
+            callbacks.push((bare_fn.clone(), ident, nullable));
+            callback_slots.push(call_args.len());
+            call_args.push(quote!{});
+            None
+        } else if let Some(pointee) = common::nullable_pointee(&arg.ty) {
+            // `Option<&T>` or `Option<NonNull<T>>`
+            let non_null = common::generic_param(&arg.ty, "Option")
+                .and_then(|ty| common::generic_param(ty, "NonNull"))
+                .is_some();
+            let call_arg = if non_null {
+                quote! { #ident.as_ref().map(::std::ptr::NonNull::from) }
+            } else {
+                quote! { #ident.as_ref() }
+            };
+            Some(transform_nullable_arg(&arg_name, pointee, call_arg))
+        } else if let Some(pointee) = common::generic_param(&arg.ty, "NonNull") {
+            let mut res = transform_struct_arg(&arg_name, pointee);
+            res.call_args = vec![quote! { ::std::ptr::NonNull::from(&#ident) }];
+            Some(res)
         } else {
             match arg.ty.node {
-                // Callback
-                ast::TyKind::BareFn(ref bare_fn) => {
-                    callbacks.push((bare_fn.clone().unwrap(), quote::Ident::new(arg_name)));
-                    None
-                }
-
                 // Pointers
                 ast::TyKind::Ptr(ref ptr) => {
+                    let nullable = nullable.contains(&arg_name);
+
                     match pprust::ty_to_string(&ptr.ty).as_str() {
                         // Opaque pointer that should be passed as a long value
                         opaque @ "App" |
                         opaque @ "Authenticator" => Some(transform_opaque_ptr(&arg_name, opaque)),
+                        // Cancellation token, also passed as a long value
+                        token if context.is_cancel_token(&arg.ty) => {
+                            Some(transform_opaque_ptr(&arg_name, token))
+                        }
                         // Detect strings, which are *const c_char or *mut c_char
+                        "c_char" if nullable => {
+                            let call_arg = quote! {
+                                #ident.as_ref().map_or(::std::ptr::null(), |s| s.as_ptr())
+                            };
+                            Some(transform_nullable_arg(&arg_name, &ptr.ty, call_arg))
+                        }
                         "c_char" => Some(transform_string_arg(&arg_name)),
+                        _ if nullable => {
+                            let call_arg = quote! {
+                                #ident.as_ref().map_or(::std::ptr::null(), |s| s as *const _)
+                            };
+                            Some(transform_nullable_arg(&arg_name, &ptr.ty, call_arg))
+                        }
                         _ => Some(transform_struct_arg(&arg_name, &ptr.ty)),
                     }
                 }
@@ -284,20 +597,21 @@ pub fn generate_jni_function(
             stmts.push(jni_arg_res.stmt);
         }
 
-        jni_fn_inputs.push(transform_jni_arg(&arg));
+        jni_fn_inputs.push(transform_jni_arg(arg, context));
     }
 
     if callbacks.len() > 0 {
         let cb_base_name = if callbacks.len() > 1 {
             format!("call_{}", native_name_str)
         } else {
-            let &(ref cb, _) = &callbacks[0];
+            let &(ref cb, ..) = &callbacks[0];
             format!("call_{}", callback_name(&*cb.decl.inputs, context).unwrap())
         };
 
         let cb_arg_res = transform_callbacks_arg(&callbacks, &cb_base_name);
-        call_args.push(quote! { ctx });
-        call_args.extend(cb_arg_res.call_args);
+        for (slot, call_arg) in callback_slots.into_iter().zip(cb_arg_res.call_args) {
+            call_args[slot] = call_arg;
+        }
         stmts.push(cb_arg_res.stmt);
     }
 
@@ -305,7 +619,7 @@ pub fn generate_jni_function(
         // Generate extra callbacks for multi-callback functions
         let count = callbacks.len();
 
-        for (idx, &(ref cb, _)) in callbacks.iter().enumerate() {
+        for (idx, &(ref cb, ..)) in callbacks.iter().enumerate() {
             let full_cb_name = format!("call_{}_{}", native_name_str, idx);
             eprintln!("Generating JNI CB {}", full_cb_name);
 
@@ -319,6 +633,29 @@ pub fn generate_jni_function(
         }
     }
 
+    let call = quote! { #native_name(#(#call_args),*) };
+    let (call, ret_ty, ret_value) = match fn_decl.output {
+        ast::FunctionRetTy::Ty(..) if returns_error_code(fn_decl, context) => {
+            let (ret_ty, ret_value) = returned_out.unwrap_or_else(|| (quote!{ () }, quote!{ () }));
+            let call = quote! {
+                let code = #call;
+                check_error_code(&env, code)?;
+            };
+            (call, ret_ty, ret_value)
+        }
+        ast::FunctionRetTy::Ty(ref ty) => {
+            let ret = quote::Ident::new("ret");
+            let (ret_ty, ret_value) = returned_value(&ret, ty);
+            (quote! { let #ret = #call; }, ret_ty, ret_value)
+        }
+        ast::FunctionRetTy::Default(..) => {
+            let (ret_ty, ret_value) = returned_out.unwrap_or_else(|| (quote!{ () }, quote!{ () }));
+            (quote! { #call; }, ret_ty, ret_value)
+        }
+    };
+
+    // Conversion errors and panics are caught and rethrown as Java exceptions
+    // to avoid aborting the whole process.
     let tokens =
         quote! {
             #[no_mangle]
@@ -326,15 +663,100 @@ pub fn generate_jni_function(
                 env: JNIEnv,
                 _class: JClass,
                 #(#jni_fn_inputs),*
-            ) {
-                #(#stmts)*
-                #native_name(#(#call_args),*);
+            ) -> #ret_ty {
+                let res = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(
+                    || -> Result<#ret_ty, JniError> {
+                        #(#stmts)*
+                        #call
+                        #(#out_stmts)*
+                        Ok(#ret_value)
+                    }
+                ));
+                throw_java_exception(&env, res)
             }
         };
 
     tokens.to_string()
 }
 
+/// JNI type of a value returned by a native function, directly or through an
+/// out-parameter, and the `JNIEnv` method storing it in a Java array. Returns
+/// `None` if the value is converted into a Java object.
+fn jni_primitive(ty: &ast::Ty) -> Option<(&'static str, &'static str)> {
+    match ty.node {
+        // Pointers to native objects are passed as long values, except strings
+        ast::TyKind::Ptr(ref ptr) if pprust::ty_to_string(&ptr.ty) != "c_char" => {
+            Some(("jlong", "set_long_array_region"))
+        }
+        ast::TyKind::Path(..) => {
+            match pprust::ty_to_string(ty).as_str() {
+                "c_char" | "u8" | "i8" => Some(("jbyte", "set_byte_array_region")),
+                "c_short" | "u16" | "i16" => Some(("jshort", "set_short_array_region")),
+                "c_int" | "u32" | "i32" => Some(("jint", "set_int_array_region")),
+                "c_long" | "u64" | "i64" | "c_usize" | "usize" | "isize" => {
+                    Some(("jlong", "set_long_array_region"))
+                }
+                "c_bool" | "bool" => Some(("jboolean", "set_boolean_array_region")),
+                "f32" => Some(("jfloat", "set_float_array_region")),
+                "f64" => Some(("jdouble", "set_double_array_region")),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Converts a value returned by a native function into a JNI primitive or `JObject`.
+fn native_value_to_java(ident: &quote::Ident, ty: &ast::Ty) -> quote::Tokens {
+    if let Some((jni_ty, _)) = jni_primitive(ty) {
+        let jni_ty = quote::Ident::new(jni_ty);
+        return quote! { #ident as #jni_ty };
+    }
+
+    let value = match ty.node {
+        // Strings
+        ast::TyKind::Ptr(..) => quote! { (#ident as *const c_char).to_java(&env)? },
+        // Fixed-size arrays
+        ast::TyKind::Array(..) => quote! { (&#ident[..]).to_java(&env)? },
+        _ => quote! { #ident.to_java(&env)? },
+    };
+
+    quote! {{
+        let value: JObject = #value.into();
+        value
+    }}
+}
+
+/// Returns the JNI type and the converted value returned from a JNI function.
+fn returned_value(ident: &quote::Ident, ty: &ast::Ty) -> (quote::Tokens, quote::Tokens) {
+    let value = native_value_to_java(ident, ty);
+
+    match jni_primitive(ty) {
+        Some((jni_ty, _)) => {
+            let jni_ty = quote::Ident::new(jni_ty);
+            (quote! { #jni_ty }, value)
+        }
+        None => (quote! { jobject }, quote! { #value.into_inner() }),
+    }
+}
+
+/// Stores the value written to an out-parameter in a one-element Java array.
+fn store_out_value(
+    ident: &quote::Ident,
+    holder: &quote::Ident,
+    ty: &ast::Ty,
+) -> quote::Tokens {
+    let value = native_value_to_java(ident, ty);
+
+    match jni_primitive(ty) {
+        Some((_, setter)) => {
+            let setter = quote::Ident::new(setter);
+            quote! { env.#setter(#holder.into_inner(), 0, &[#value])?; }
+        }
+        None => quote! { env.set_object_array_element(#holder.into_inner(), 0, #value)?; },
+    }
+}
+
 /// Transform `ast::Arg` into an (identifier, type) tuple
 fn transform_arg(arg: &ast::Arg) -> (quote::Ident, quote::Ident) {
     (
@@ -360,19 +782,21 @@ fn generate_callback(cb: &ast::BareFnTy, context: &Context) -> JniCallback {
     let mut jni_cb_inputs = Vec::new();
     let mut arg_java_ty = Vec::new();
 
-    let mut args_iter = (&*cb.decl)
-        .inputs
-        .iter()
-        .filter(|arg| !is_user_data_arg(arg))
-        .peekable();
+    let mut args_iter = (&*cb.decl).inputs.iter().peekable();
 
     while let Some(arg) = args_iter.next() {
+        // The user data holds references to the Java callbacks
+        if context.callbacks.is_user_data_arg(arg) {
+            jni_cb_inputs.push(quote! { ctx: *mut c_void });
+            continue;
+        }
+
         let (arg_name, arg_ty) = transform_arg(&arg);
 
         jni_cb_inputs.push(quote! { #arg_name: #arg_ty });
         args.push(quote! { #arg_name.into() });
 
-        if is_array_arg(&arg, args_iter.peek().cloned()) {
+        if is_array_arg(&arg, args_iter.peek().cloned(), &context.callbacks) {
             // Handle array arguments
             let val_java_type = rust_ty_to_signature(&arg.ty, context).unwrap();
             arg_java_ty.push(JavaType::Array(Box::new(val_java_type)));
@@ -382,13 +806,24 @@ fn generate_callback(cb: &ast::BareFnTy, context: &Context) -> JniCallback {
                 jni_cb_inputs.push(quote! { #len_arg_name: #len_arg_ty });
 
                 stmts.push(quote! {
-                    let #arg_name = jni_unwrap!(slice::from_raw_parts(#arg_name, #len_arg_name).to_java(&env));
+                    let #arg_name = slice::from_raw_parts(#arg_name, #len_arg_name).to_java(&env)?;
                 });
             } else {
                 // error: no length arg?
             }
         } else {
             let stmt = match arg.ty.node {
+                // Fixed-size arrays
+                ast::TyKind::Array(..) => {
+                    quote! {
+                        let #arg_name: JObject = (&#arg_name[..]).to_java(&env)?.into();
+                    }
+                }
+                ast::TyKind::Ptr(..) if fixed_array_ty(&arg.ty).is_some() => {
+                    quote! {
+                        let #arg_name: JObject = (&(*#arg_name)[..]).to_java(&env)?.into();
+                    }
+                }
                 // Pointers
                 ast::TyKind::Ptr(ref ptr) => {
                     match pprust::ty_to_string(&ptr.ty).as_str() {
@@ -401,20 +836,20 @@ fn generate_callback(cb: &ast::BareFnTy, context: &Context) -> JniCallback {
                         // Strings
                         "c_char" => {
                             quote! {
-                                let #arg_name: JObject = jni_unwrap!(#arg_name.to_java(&env)).into();
+                                let #arg_name: JObject = #arg_name.to_java(&env)?.into();
                             }
                         }
                         // Other ptrs
                         _ => {
                             quote! {
-                            let #arg_name = jni_unwrap!((*#arg_name).to_java(&env));
-                        }
+                                let #arg_name = (*#arg_name).to_java(&env)?;
+                            }
                         }
                     }
                 }
                 _ => {
                     quote! {
-                        let #arg_name = jni_unwrap!(#arg_name.to_java(&env));
+                        let #arg_name = #arg_name.to_java(&env)?;
                     }
                 }
             };
@@ -447,6 +882,7 @@ fn generate_multi_jni_callback(
     callbacks_count: usize,
     context: &mut Context,
 ) -> String {
+    let cb_name_str = cb_name;
     let cb_name = quote::Ident::new(cb_name);
 
     let JniCallback {
@@ -458,29 +894,35 @@ fn generate_multi_jni_callback(
 
     let tokens =
         quote! {
-        extern "C" fn #cb_name(ctx: *mut c_void, #(#jni_cb_inputs),*) {
-            unsafe {
-                let env = JVM.as_ref()
-                    .map(|vm| vm.attach_current_thread_as_daemon().unwrap())
-                    .unwrap();
+        extern "C" fn #cb_name(#(#jni_cb_inputs),*) {
+            let res = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(
+                || -> Result<(), JniError> { unsafe {
+                    let env = JVM.as_ref()
+                        .ok_or("no JVM reference found")?
+                        .attach_current_thread_as_daemon()?;
+
+                    let mut cbs = Box::from_raw(ctx as *mut [Option<GlobalRef>; #callbacks_count]);
+                    let cb = cbs[#callback_index].take();
+
+                    if cbs.iter().any(|cb| cb.is_some()) {
+                        mem::forget(cbs);
+                    }
 
-                let mut cbs = Box::from_raw(ctx as *mut [Option<GlobalRef>; #callbacks_count]);
+                    if let Some(cb) = cb {
+                        #(#stmts);*
 
-                if let Some(cb) = cbs[#callback_index].take() {
-                    #(#stmts);*
-
-                    jni_unwrap!(env.call_method(
-                        cb.as_obj(),
-                        "call",
-                        #arg_ty_str,
-                        &[ #(#args),* ],
-                    ));
-                }
+                        env.call_method(
+                            cb.as_obj(),
+                            "call",
+                            #arg_ty_str,
+                            &[ #(#args),* ],
+                        )?;
+                    }
 
-                if cbs.iter().any(|cb| cb.is_some()) {
-                    mem::forget(cbs);
-                }
-            }
+                    Ok(())
+                }}
+            ));
+            log_callback_error(#cb_name_str, res);
         }
     };
 
@@ -489,6 +931,7 @@ fn generate_multi_jni_callback(
 
 /// Generates a JNI callback function based on a native callback type
 pub fn generate_jni_callback(cb: &ast::BareFnTy, cb_name: &str, context: &mut Context) -> String {
+    let cb_name_str = cb_name;
     let cb_name = quote::Ident::new(cb_name);
 
     let JniCallback {
@@ -498,24 +941,31 @@ pub fn generate_jni_callback(cb: &ast::BareFnTy, cb_name: &str, context: &mut Co
         arg_ty_str,
     } = generate_callback(cb, context);
 
+    // Callbacks can't propagate errors back to Java, so failures are logged
+    // and the result is dropped instead of crashing the process.
     let tokens =
         quote! {
-        extern "C" fn #cb_name(ctx: *mut c_void, #(#jni_cb_inputs),*) {
-            unsafe {
-                let env = JVM.as_ref()
-                    .map(|vm| vm.attach_current_thread_as_daemon().unwrap())
-                    .unwrap();
-                let cb = convert_cb_from_java(&env, ctx);
-
-                #(#stmts);*
-
-                jni_unwrap!(env.call_method(
-                    cb.as_obj(),
-                    "call",
-                    #arg_ty_str,
-                    &[ #(#args),* ],
-                ));
-            }
+        extern "C" fn #cb_name(#(#jni_cb_inputs),*) {
+            let res = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(
+                || -> Result<(), JniError> { unsafe {
+                    let env = JVM.as_ref()
+                        .ok_or("no JVM reference found")?
+                        .attach_current_thread_as_daemon()?;
+                    let cb = convert_cb_from_java(&env, ctx);
+
+                    #(#stmts);*
+
+                    env.call_method(
+                        cb.as_obj(),
+                        "call",
+                        #arg_ty_str,
+                        &[ #(#args),* ],
+                    )?;
+
+                    Ok(())
+                }}
+            ));
+            log_callback_error(#cb_name_str, res);
         }
     };
 
@@ -672,6 +1122,21 @@ fn generate_struct_to_java(
                             }
                         }
                     }
+                    ast::TyKind::Array(..) => {
+                        let signature = rust_ty_to_signature(&f.ty, context)
+                            .map(|ty| format!("{}", ty))
+                            .unwrap_or_default();
+
+                        quote! {
+                            let #field_name: JObject = (&self.#field_name[..]).to_java(&env)?.into();
+                            env.set_field(
+                                output,
+                                #java_field_name,
+                                #signature,
+                                #field_name.into()
+                            )?;
+                        }
+                    }
                     _ => quote!{},
                 }
             }
@@ -861,6 +1326,21 @@ fn generate_struct_from_java(
                             }
                         }
                     }
+                    ast::TyKind::Array(ref elem, ref len) => {
+                        let signature = rust_ty_to_signature(&f.ty, context)
+                            .map(|ty| format!("{}", ty))
+                            .unwrap_or_default();
+                        let conv = vec_to_fixed_array(&field_name, &java_field_name, elem, len, false);
+
+                        quote! {
+                            let #field_name = env.get_field(
+                                input,
+                                #java_field_name,
+                                #signature
+                            )?.l()?;
+                            #conv
+                        }
+                    }
                     _ => quote!{},
                 }
             }
@@ -3,8 +3,9 @@
 
 use Error;
 use Level;
-use common::{self, Outputs, append_output, check_no_mangle, is_array_arg, is_result_arg,
-             is_user_data_arg, parse_attr, retrieve_docstring};
+use common::{self, CallbackConvention, CancelConvention, Outputs, append_output, check_no_mangle,
+             is_array_arg, parse_attr, retrieve_docstring};
+use const_eval::ConstEvaluator;
 use inflector::Inflector;
 use rustfmt;
 use std::collections::{BTreeSet, HashMap};
@@ -14,35 +15,89 @@ use syntax::abi::Abi;
 use syntax::ast;
 use syntax::codemap;
 use syntax::print::pprust;
-mod jni;
+pub mod jni;
 
 pub struct LangJava {
     context: Context,
+    const_evaluator: ConstEvaluator,
 }
 
 pub struct Context {
     /// Native library name
-    lib_name: String,
+    pub lib_name: String,
     /// Namespace
-    namespace: String,
+    pub namespace: String,
     /// Model namespace (structures go into this one)
-    namespace_model: String,
+    pub namespace_model: String,
+    /// Fully qualified name of the exception class thrown on native errors
+    pub exception_class: String,
+    /// Fully qualified name of the annotation marking arguments which can be null
+    pub nullable_annotation: String,
+    /// Conventions of the callbacks reporting results of asynchronous functions
+    pub callbacks: CallbackConvention,
+    /// Native function returning description of the last error, if synchronous
+    /// functions returning `i32` report errors through it
+    pub last_error_function: Option<String>,
+    /// Conventions of asynchronous functions which can be cancelled, if enabled
+    pub cancellation: Option<CancelConvention>,
+    /// Macro logging errors of callbacks called from native threads, where they can't
+    /// be thrown (e.g. `error` of the `log` crate)
+    pub log_macro: String,
     /// Maps types from Rust to Java
-    type_map: HashMap<&'static str, &'static str>,
+    pub type_map: HashMap<&'static str, &'static str>,
     /// Keeps track of which JNI callback functions has been generated already
-    generated_jni_cbs: BTreeSet<String>,
+    pub generated_jni_cbs: BTreeSet<String>,
+}
+
+impl Context {
+    pub fn new(type_map: HashMap<&'static str, &'static str>) -> Self {
+        Context {
+            type_map,
+            lib_name: "backend".to_owned(),
+            namespace: "net.maidsafe.bindings".to_owned(),
+            namespace_model: "net.maidsafe.model".to_owned(),
+            exception_class: "net.maidsafe.FfiException".to_owned(),
+            nullable_annotation: "javax.annotation.Nullable".to_owned(),
+            callbacks: Default::default(),
+            last_error_function: None,
+            cancellation: None,
+            log_macro: "error".to_owned(),
+            generated_jni_cbs: BTreeSet::new(),
+        }
+    }
+
+    /// Path of a file containing a model class, following the package directory structure
+    /// (e.g. `net/maidsafe/model/Foo.java`).
+    pub fn model_file<S: AsRef<str>>(&self, class_name: S, extension: &str) -> PathBuf {
+        PathBuf::from(self.namespace_model.replace('.', "/"))
+            .join(format!("{}.{}", class_name.as_ref(), extension))
+    }
+
+    /// Import of the model package, empty if the model classes are in the same package
+    /// as the bindings.
+    pub fn model_import(&self) -> Option<String> {
+        if self.namespace_model == self.namespace {
+            None
+        } else {
+            Some(format!("{}.*", self.namespace_model))
+        }
+    }
+
+    /// Returns whether the type is a pointer to the cancellation token, which is passed
+    /// to Java as a long value.
+    pub fn is_cancel_token(&self, ty: &ast::Ty) -> bool {
+        self.cancellation.as_ref().map_or(
+            false,
+            |convention| convention.is_token_ty(ty),
+        )
+    }
 }
 
 impl LangJava {
     pub fn new(type_map: HashMap<&'static str, &'static str>) -> Self {
         LangJava {
-            context: Context {
-                type_map,
-                lib_name: "backend".to_owned(),
-                namespace: "net.maidsafe.bindings".to_owned(),
-                namespace_model: "net.maidsafe.model".to_owned(),
-                generated_jni_cbs: BTreeSet::new(),
-            },
+            context: Context::new(type_map),
+            const_evaluator: ConstEvaluator::new(),
         }
     }
 
@@ -61,40 +116,173 @@ impl LangJava {
         self.context.namespace_model = namespace.into();
     }
 
-    /// Applies rustfmt to JNI code to improve debuggability
-    fn format_jni_output(&self, input: &mut String) {
-        let mut output: Vec<u8> = Vec::with_capacity(input.len() * 2);
+    /// Set the exception class (e.g. `net.maidsafe.FfiException`) thrown when a native
+    /// function fails to convert its arguments or panics, returns an error code or
+    /// reports an error to its result callback. The class must have a public
+    /// `(int, String)` constructor taking the error code and the description, which is
+    /// the only one used. Failures of the bindings themselves are reported with code -1.
+    pub fn set_exception_class<T: Into<String>>(&mut self, class: T) {
+        self.context.exception_class = class.into();
+    }
 
-        let mut cfg = rustfmt::config::Config::default();
-        cfg.set().write_mode(rustfmt::config::WriteMode::Plain);
+    /// Set the annotation (e.g. `android.support.annotation.Nullable`) marking
+    /// arguments and fields which can be null.
+    pub fn set_nullable_annotation<T: Into<String>>(&mut self, annotation: T) {
+        self.context.nullable_annotation = annotation.into();
+    }
 
-        unwrap!(rustfmt::format_input(
-            rustfmt::Input::Text(input.clone()),
-            &cfg,
-            Some(&mut output),
-        ));
+    /// Set the conventions of the callbacks reporting results of asynchronous functions,
+    /// which determine the arguments hidden from Java and the functions exposed as
+    /// `CompletableFuture`s. The exception is constructed from the error code and the
+    /// description fields of the result.
+    pub fn set_callback_convention(&mut self, convention: CallbackConvention) {
+        self.context.callbacks = convention;
+    }
+
+    /// Treat `i32` returned by synchronous functions (those without callbacks) as
+    /// an error code. The native methods throw the exception class, constructed with
+    /// the code and the description returned by the given function, when the code is
+    /// non-zero, and return nothing or the value of the out-parameter otherwise.
+    /// The function must have the signature `extern "C" fn() -> *const c_char`.
+    pub fn set_last_error_function<T: Into<String>>(&mut self, name: T) {
+        self.context.last_error_function = Some(name.into());
+    }
 
-        *input = String::from_utf8(output).expect("Invalid Rustfmt output found");
+    /// Set the macro logging errors of callbacks called from native threads, e.g. failed
+    /// conversions or Java exceptions (`error` by default). It's called like `format!`
+    /// and must be imported by the crate including the JNI glue, e.g. with
+    /// `#[macro_use] extern crate log;`.
+    pub fn set_log_macro<T: Into<String>>(&mut self, name: T) {
+        self.context.log_macro = name.into();
     }
 
-    /// Adds package info to the NativeBindings Java module and indents lines
+    /// Enable cancellation of asynchronous functions taking a pointer to the token type
+    /// of the convention. Their `CompletableFuture` wrappers create the native token
+    /// for the call and cancel it from `Future.cancel`, which also completes the future
+    /// as cancelled. The token is released when the callback is called.
+    pub fn set_cancel_convention(&mut self, convention: CancelConvention) {
+        self.context.cancellation = Some(convention);
+    }
+
+    /// Name of the class containing `CompletableFuture` wrappers of native functions
+    fn async_class_name(&self) -> String {
+        self.context.lib_name.to_class_case()
+    }
+
+    /// Adds package info and imports to the async wrappers class and indents lines
+    fn format_async_functions(&self, funcs: &mut String) {
+        // Indent lines
+        let lines = funcs.lines().fold(String::new(), |mut s, line| {
+            if line.is_empty() {
+                s.push_str("\n");
+            } else {
+                s.push_str(&format!("\t{}\n", line));
+            }
+            s
+        });
+        let model_import = self.context.model_import().map_or_else(String::new, |import| {
+            format!("import {};\n", import)
+        });
+        *funcs = format!(
+            "package {namespace};\n\n\
+             import java.util.concurrent.CompletableFuture;\n\
+             import {exception_class};\n\
+             {model_import}\n\
+             public class {class} {{\n\
+             {lines}\
+             }}\n",
+            namespace = self.context.namespace,
+            exception_class = self.context.exception_class,
+            model_import = model_import,
+            class = self.async_class_name(),
+            lines = lines
+        );
+    }
+
+    /// Adds package info to the Constants class and indents lines
+    fn format_constants(&self, consts: &mut String) {
+        let lines = consts.lines().fold(String::new(), |mut s, line| {
+            if line.is_empty() {
+                s.push_str("\n");
+            } else {
+                s.push_str(&format!("\t{}\n", line));
+            }
+            s
+        });
+        *consts = format!(
+            "package {namespace};\n\n\
+             public class Constants {{\n\
+             {lines}\
+             }}\n",
+            namespace = self.context.namespace,
+            lines = lines
+        );
+    }
+
+    /// Adds package info and imports to the NativeBindings Java module and indents lines
     fn format_native_functions(&self, funcs: &mut String) {
         // Indent lines
         let lines = funcs.lines().fold(String::new(), |mut s, line| {
             s.push_str(&format!("\t{}\n", line));
             return s;
         });
+        let model_import = self.context.model_import().map_or_else(String::new, |import| {
+            format!("import {};\n\n", import)
+        });
         *funcs = format!(
-            "package {namespace};\n\n
-                         public class NativeBindings {{\n
-                         {lines}\n
-                         }}",
+            "package {namespace};\n\n\
+             {model_import}\
+             public class NativeBindings {{\n\
+             {lines}\
+             }}\n",
             namespace = self.context.namespace,
+            model_import = model_import,
             lines = lines
         );
     }
 }
 impl common::Lang for LangJava {
+    /// Convert a Rust constant into a Java `static final` field.
+    fn parse_const(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let docs = parse_attr(&item.attrs, |_| true, |attr| retrieve_docstring(attr, "")).1;
+
+        if let ast::ItemKind::Const(ref ty, ref expr) = item.node {
+            self.const_evaluator.define(&*item.ident.name.as_str(), ty, expr);
+            let evaluated = self.const_evaluator.eval_as(expr, ty);
+
+            let ty = rust_to_java(ty, &self.context)?.unwrap_or_default();
+            let value = match const_value_to_java(expr, &ty).or_else(|| {
+                evaluated.map(|value| int_value_to_java(value, &ty))
+            }) {
+                Some(value) => value,
+                None => {
+                    println!(
+                        "bindgen can not evaluate constant {}. Skipping.",
+                        pprust::item_to_string(item)
+                    );
+                    return Ok(());
+                }
+            };
+
+            let mut buffer = String::new();
+            if !docs.is_empty() {
+                buffer.push_str("/**\n");
+                buffer.push_str(&docs.replace("///", " *"));
+                buffer.push_str(" */\n");
+            }
+            buffer.push_str(&format!(
+                "public static final {} {} = {};\n\n",
+                ty,
+                item.ident.name.as_str(),
+                value
+            ));
+
+            append_output(buffer, "Constants.java", outputs);
+        }
+
+        Ok(())
+    }
+
     /// Convert a Rust function declaration into Java.
     fn parse_fn(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
         let (no_mangle, docs) = parse_attr(&item.attrs, check_no_mangle, |attr| {
@@ -121,10 +309,17 @@ impl common::Lang for LangJava {
                 });
             }
 
+            // The last error is fetched by the JNI glue of the failed functions.
+            if self.context.last_error_function.as_ref().map(String::as_str) == Some(&*name) {
+                return Ok(());
+            }
+
             transform_native_fn(
                 &*fn_decl,
                 &docs,
                 &format!("{}", name),
+                &common::nullable_params(&item.attrs),
+                &common::out_params(fn_decl, &item.attrs),
                 outputs,
                 &mut self.context,
             )?;
@@ -150,7 +345,9 @@ impl common::Lang for LangJava {
         }
 
         let mut buffer = String::new();
-        buffer.push_str(&format!("package {};\n\n", self.context.namespace));
+        buffer.push_str(&format!("package {};\n\n", self.context.namespace_model));
+        buffer.push_str("import java.util.Arrays;\n");
+        buffer.push_str("import java.util.Objects;\n\n");
         buffer.push_str(&docs);
 
         let orig_name = item.ident.name.as_str();
@@ -169,6 +366,7 @@ impl common::Lang for LangJava {
             if variants.is_struct() {
                 let mut constructor_fields = Vec::new();
                 let mut constructor_assignments = Vec::new();
+                let mut class_fields = Vec::new();
 
                 buffer.push_str(" {\n");
 
@@ -188,6 +386,11 @@ impl common::Lang for LangJava {
                         ty.push_str("[]");
                     }
 
+                    if struct_field.attrs.iter().any(common::check_nullable) ||
+                        common::nullable_pointee(&struct_field.ty).is_some()
+                    {
+                        buffer.push_str(&format!("\t@{}\n", self.context.nullable_annotation));
+                    }
                     buffer.push_str(&format!("\tprivate {} {};\n\n", ty, name));
 
                     buffer.push_str(&format!(
@@ -206,6 +409,7 @@ impl common::Lang for LangJava {
 
                     constructor_fields.push(format!("{} {}", ty, name));
                     constructor_assignments.push(format!("\t\tthis.{name} = {name};", name = name));
+                    class_fields.push((ty, name));
                 }
 
                 // Parametrised constructor
@@ -217,6 +421,10 @@ impl common::Lang for LangJava {
                         constructor_assignments.join("\n")
                 ));
 
+                buffer.push_str(&generate_equals(&name, &class_fields));
+                buffer.push_str(&generate_hash_code(&class_fields));
+                buffer.push_str(&generate_to_string(&name, &class_fields));
+
                 buffer.push_str("}");
 
                 let jni = jni::generate_struct(&fields, &orig_name, &name, &self.context);
@@ -242,15 +450,27 @@ impl common::Lang for LangJava {
 
         buffer.push_str("\n\n");
 
-        outputs.insert(From::from(format!("{}.java", name)), buffer);
+        outputs.insert(self.context.model_file(&name, "java"), buffer);
 
         Ok(())
     }
 
     fn finalise_output(&mut self, outputs: &mut Outputs) -> Result<(), Error> {
+        if let Some(input) = outputs.get_mut(&PathBuf::from("Constants.java")) {
+            self.format_constants(input);
+        }
+
+        // Async wrappers are optional: they are generated only for functions
+        // reporting the result through a single callback.
+        let async_file = PathBuf::from(format!("{}.java", self.async_class_name()));
+        if let Some(input) = outputs.get_mut(&async_file) {
+            self.format_async_functions(input);
+        }
+
         match outputs.get_mut(&PathBuf::from("jni.rs")) {
             Some(input) => {
-                self.format_jni_output(input);
+                input.insert_str(0, &jni::generate_prelude(&self.context));
+                format_jni_output(input);
             }
             None => {
                 return Err(Error {
@@ -275,6 +495,22 @@ impl common::Lang for LangJava {
     }
 }
 
+/// Applies rustfmt to JNI code to improve debuggability
+pub fn format_jni_output(input: &mut String) {
+    let mut output: Vec<u8> = Vec::with_capacity(input.len() * 2);
+
+    let mut cfg = rustfmt::config::Config::default();
+    cfg.set().write_mode(rustfmt::config::WriteMode::Plain);
+
+    unwrap!(rustfmt::format_input(
+        rustfmt::Input::Text(input.clone()),
+        &cfg,
+        Some(&mut output),
+    ));
+
+    *input = String::from_utf8(output).expect("Invalid Rustfmt output found");
+}
+
 /// Transform a struct name into a Java class name
 pub fn struct_to_java_classname<S: AsRef<str>>(s: S) -> String {
     // s.as_ref().to_class_case()
@@ -285,17 +521,121 @@ pub fn struct_to_java_classname<S: AsRef<str>>(s: S) -> String {
     }
 }
 
+/// Generate `equals` comparing all fields of a class, with deep equality for arrays
+fn generate_equals(class_name: &str, fields: &[(String, String)]) -> String {
+    let comparisons: Vec<_> = fields
+        .iter()
+        .map(|&(ref ty, ref name)| {
+            let other = format!("other.{}", name);
+
+            match ty.as_str() {
+                "float" | "double" => {
+                    format!("{}.compare({}, {}) == 0", java_boxed_type(ty), name, other)
+                }
+                "boolean" | "byte" | "short" | "int" | "long" => format!("{} == {}", name, other),
+                ty if is_primitive_array(ty) => format!("Arrays.equals({}, {})", name, other),
+                ty if ty.ends_with("[]") => format!("Arrays.deepEquals({}, {})", name, other),
+                _ => format!("Objects.equals({}, {})", name, other),
+            }
+        })
+        .collect();
+
+    let comparisons = if comparisons.is_empty() {
+        String::from("true")
+    } else {
+        comparisons.join(" &&\n\t\t\t")
+    };
+
+    format!(
+        "\t@Override\n\
+         \tpublic boolean equals(Object o) {{\n\
+         \t\tif (this == o) {{\n\
+         \t\t\treturn true;\n\
+         \t\t}}\n\
+         \t\tif (o == null || getClass() != o.getClass()) {{\n\
+         \t\t\treturn false;\n\
+         \t\t}}\n\
+         \t\tfinal {class} other = ({class}) o;\n\
+         \t\treturn {comparisons};\n\
+         \t}}\n\n",
+        class = class_name,
+        comparisons = comparisons
+    )
+}
+
+/// Generate `hashCode` consistent with the generated `equals`
+fn generate_hash_code(fields: &[(String, String)]) -> String {
+    let (arrays, values): (Vec<_>, Vec<_>) =
+        fields.iter().partition(|&&(ref ty, _)| ty.ends_with("[]"));
+
+    let values: Vec<_> = values.iter().map(|&&(_, ref name)| name.clone()).collect();
+    let mut buffer = format!(
+        "\t@Override\n\
+         \tpublic int hashCode() {{\n\
+         \t\tint result = Objects.hash({});\n",
+        values.join(", ")
+    );
+
+    for &&(ref ty, ref name) in &arrays {
+        let hash_fn = if is_primitive_array(ty) {
+            "hashCode"
+        } else {
+            "deepHashCode"
+        };
+        buffer.push_str(&format!(
+            "\t\tresult = 31 * result + Arrays.{}({});\n",
+            hash_fn,
+            name
+        ));
+    }
+
+    buffer.push_str("\t\treturn result;\n\t}\n\n");
+    buffer
+}
+
+/// Generate `toString` listing all fields of a class
+fn generate_to_string(class_name: &str, fields: &[(String, String)]) -> String {
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|&(ref ty, ref name)| if is_primitive_array(ty) {
+            format!("{name}=\" + Arrays.toString({name}) + \"", name = name)
+        } else if ty.ends_with("[]") {
+            format!("{name}=\" + Arrays.deepToString({name}) + \"", name = name)
+        } else {
+            format!("{name}=\" + {name} + \"", name = name)
+        })
+        .collect();
+
+    format!(
+        "\t@Override\n\
+         \tpublic String toString() {{\n\
+         \t\treturn \"{class}{{{fields}}}\";\n\
+         \t}}\n",
+        class = class_name,
+        fields = fields.join(", ")
+    )
+}
+
+/// Returns whether the Java type is an array of primitives (e.g. `byte[]`)
+fn is_primitive_array(ty: &str) -> bool {
+    match ty {
+        "boolean[]" | "byte[]" | "char[]" | "short[]" | "int[]" | "long[]" | "float[]" |
+        "double[]" => true,
+        _ => false,
+    }
+}
+
 /// Get the Java interface name for the callback based on its types
 pub fn callback_name(inputs: &[ast::Arg], context: &Context) -> Result<String, Error> {
     let mut components = Vec::new();
     let mut inputs = inputs.iter().peekable();
 
     while let Some(arg) = inputs.next() {
-        if is_user_data_arg(arg) {
+        if context.callbacks.is_user_data_arg(arg) {
             // Skip user_data args
             continue;
         }
-        if is_result_arg(arg) {
+        if context.callbacks.is_result_arg(arg) {
             // Make sure that a CB taking a single "result: *const FfiResult" param
             // won't end up being called "CallbackVoid" (but "CallbackResult" instead)
             components.push(From::from("Result"));
@@ -306,7 +646,7 @@ pub fn callback_name(inputs: &[ast::Arg], context: &Context) -> Result<String, E
             .map(struct_to_java_classname)
             .unwrap_or_default();
 
-        if is_array_arg(&arg, inputs.peek().cloned()) {
+        if is_array_arg(&arg, inputs.peek().cloned(), &context.callbacks) {
             inputs.next();
             arg_type.push_str("ArrayLen");
         }
@@ -326,24 +666,36 @@ pub fn transform_native_fn(
     fn_decl: &ast::FnDecl,
     docs: &str,
     name: &str,
+    nullable: &[String],
+    out: &[String],
     outputs: &mut Outputs,
     context: &mut Context,
 ) -> Result<(), Error> {
     let mut args_str = Vec::new();
+    let returned_out = returned_out_param(fn_decl, out, context);
 
     let mut fn_args = fn_decl
         .inputs
         .iter()
-        .filter(|arg| !is_user_data_arg(arg))
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
         .peekable();
 
     while let Some(arg) = fn_args.next() {
         let arg_name = pprust::pat_to_string(&*arg.pat);
 
+        // Out-parameters are returned or passed in one-element arrays
+        if out.contains(&arg_name) {
+            if returned_out.is_none() {
+                let java_type = out_java_type(arg, context)?;
+                args_str.push(format!("{}[] {}", java_type, arg_name.to_camel_case()));
+            }
+            continue;
+        }
+
         // Generate function arguments
         let mut java_type = rust_to_java(&arg.ty, context)?.unwrap_or_default();
 
-        if is_array_arg(&arg, fn_args.peek().cloned()) {
+        if is_array_arg(&arg, fn_args.peek().cloned(), &context.callbacks) {
             // This is an array, so add it to the type description
             java_type.push_str("[]");
 
@@ -352,12 +704,16 @@ pub fn transform_native_fn(
             fn_args.next();
         }
 
+        if nullable.contains(&arg_name) || is_nullable_arg(arg) {
+            java_type.insert_str(0, &format!("@{} ", context.nullable_annotation));
+        }
+
         args_str.push(format!("{} {}", java_type, arg_name.to_camel_case()));
 
         // Generate a callback class - if it wasn't generated already
-        if let ast::TyKind::BareFn(ref bare_fn) = arg.ty.node {
+        if let Some(bare_fn) = callback_ty(&arg.ty) {
             let cb_class = callback_name(&*bare_fn.decl.inputs, context)?;
-            let cb_file = PathBuf::from(format!("{}.java", cb_class));
+            let cb_file = context.model_file(&cb_class, "java");
 
             if let None = outputs.get(&cb_file) {
                 eprintln!("Generating CB {}", cb_class);
@@ -365,16 +721,6 @@ pub fn transform_native_fn(
                 let cb_output = transform_callback(&*arg.ty, &cb_class, context)?
                     .unwrap_or_default();
                 let _ = outputs.insert(cb_file, cb_output);
-
-                // Generate JNI callback fn
-                let jni_cb_name = format!("call_{}", cb_class);
-                if !context.generated_jni_cbs.contains(&jni_cb_name) {
-                    let mut jni = jni::generate_jni_callback(bare_fn, &jni_cb_name, context);
-                    jni.push_str("\n");
-
-                    append_output(jni, "jni.rs", outputs);
-                    context.generated_jni_cbs.insert(jni_cb_name);
-                }
             }
         }
     }
@@ -388,13 +734,20 @@ pub fn transform_native_fn(
                 message: "panics across a C boundary are naughty!".into(),
             });
         }
-        ast::FunctionRetTy::Default(..) => String::from("public static native void"),
-        ast::FunctionRetTy::Ty(ref ty) => rust_to_java(&*ty, context)?.unwrap_or_default(),
+        ast::FunctionRetTy::Ty(ref ty) if !returns_error_code(fn_decl, context) => {
+            returned_java_type(ty, context)?
+        }
+        _ => {
+            match returned_out {
+                Some(arg) => out_java_type(arg, context)?,
+                None => String::from("void"),
+            }
+        }
     };
 
     let java_name = name.to_camel_case();
     let func_decl = format!(
-        "{} {}({})",
+        "public static native {} {}({})",
         return_type,
         &java_name,
         args_str.as_slice().join(", ")
@@ -409,22 +762,493 @@ pub fn transform_native_fn(
 
     append_output(buffer, "NativeBindings.java", outputs);
 
-    let mut jni =
-        jni::generate_jni_function(fn_decl.inputs.clone(), name, &java_name, context, outputs);
+    // Futures can't be combined with out-parameters
+    if out.is_empty() {
+        if let Some(buffer) = transform_async_fn(fn_decl, docs, &java_name, outputs, context)? {
+            let async_file = format!("{}.java", context.lib_name.to_class_case());
+            append_output(buffer, &async_file, outputs);
+        }
+    }
+
+    generate_jni_bindings(fn_decl, name, &java_name, nullable, out, outputs, context)
+}
+
+/// Returns the out-parameter whose value is returned from the native method instead
+/// of the native function result. That's possible if the function has a single
+/// out-parameter and doesn't return anything but an error code, otherwise the values
+/// of out-parameters are passed back in one-element arrays.
+pub fn returned_out_param<'a>(
+    fn_decl: &'a ast::FnDecl,
+    out: &[String],
+    context: &Context,
+) -> Option<&'a ast::Arg> {
+    let returns_value = match fn_decl.output {
+        ast::FunctionRetTy::Default(..) => false,
+        ast::FunctionRetTy::Ty(..) => !returns_error_code(fn_decl, context),
+    };
+
+    if returns_value || out.len() != 1 {
+        return None;
+    }
+
+    fn_decl.inputs.iter().find(
+        |arg| pprust::pat_to_string(&*arg.pat) == out[0],
+    )
+}
+
+/// Returns whether the `i32` returned by the function is an error code, which is
+/// turned into an exception by the JNI glue (see `LangJava::set_last_error_function`).
+pub fn returns_error_code(fn_decl: &ast::FnDecl, context: &Context) -> bool {
+    if context.last_error_function.is_none() ||
+        fn_decl.inputs.iter().any(|arg| callback_ty(&arg.ty).is_some())
+    {
+        return false;
+    }
+
+    match fn_decl.output {
+        ast::FunctionRetTy::Ty(ref ty) => pprust::ty_to_string(ty) == "i32",
+        ast::FunctionRetTy::Default(..) => false,
+    }
+}
+
+/// Turn the type of the value written to an out-parameter into Java.
+pub fn out_java_type(arg: &ast::Arg, context: &Context) -> Result<String, Error> {
+    let pointee = match arg.ty.node {
+        ast::TyKind::Ptr(ref ptr) => &ptr.ty,
+        _ => {
+            return Err(Error {
+                level: Level::Error,
+                span: Some(arg.ty.span),
+                message: "out-parameters must be pointers".into(),
+            })
+        }
+    };
+
+    returned_java_type(pointee, context)
+}
+
+/// Turn the type of a value returned by a native function, directly or through
+/// an out-parameter, into Java.
+pub fn returned_java_type(ty: &ast::Ty, context: &Context) -> Result<String, Error> {
+    match ty.node {
+        // Pointers to native objects are passed as long values, except strings
+        ast::TyKind::Ptr(ref ptr) if pprust::ty_to_string(&ptr.ty) != "c_char" => {
+            Ok("long".to_owned())
+        }
+        _ => Ok(rust_to_java(ty, context)?.unwrap_or_default()),
+    }
+}
+
+/// Returns the function type of a callback argument, which can be null if it's
+/// wrapped in `Option`.
+pub fn callback_ty(ty: &ast::Ty) -> Option<&ast::BareFnTy> {
+    match ty.node {
+        ast::TyKind::BareFn(ref bare_fn) => Some(&**bare_fn),
+        _ => common::nullable_fn(ty),
+    }
+}
+
+/// Returns whether the type of the argument allows null (`Option<&T>`,
+/// `Option<NonNull<T>>` or `Option<extern "C" fn(..)>`).
+pub fn is_nullable_arg(arg: &ast::Arg) -> bool {
+    common::nullable_pointee(&arg.ty).is_some() || common::nullable_fn(&arg.ty).is_some()
+}
+
+/// Generate JNI glue for a Rust FFI function and its callbacks
+pub fn generate_jni_bindings(
+    fn_decl: &ast::FnDecl,
+    name: &str,
+    java_name: &str,
+    nullable: &[String],
+    out: &[String],
+    outputs: &mut Outputs,
+    context: &mut Context,
+) -> Result<(), Error> {
+    for arg in &fn_decl.inputs {
+        if let Some(bare_fn) = callback_ty(&arg.ty) {
+            // Generate JNI callback fn - if it wasn't generated already
+            let cb_class = callback_name(&*bare_fn.decl.inputs, context)?;
+            let jni_cb_name = format!("call_{}", cb_class);
+
+            if !context.generated_jni_cbs.contains(&jni_cb_name) {
+                let mut jni = jni::generate_jni_callback(bare_fn, &jni_cb_name, context);
+                jni.push_str("\n");
+
+                append_output(jni, "jni.rs", outputs);
+                context.generated_jni_cbs.insert(jni_cb_name);
+            }
+        }
+    }
+
+    let mut jni = jni::generate_jni_function(
+        fn_decl,
+        name,
+        java_name,
+        nullable,
+        out,
+        context,
+        outputs,
+    );
     jni.push_str("\n");
     append_output(jni, "jni.rs", outputs);
 
     Ok(())
 }
 
+/// Returns the callback of a function reporting its result through a single
+/// `FfiResult` callback, i.e. one which can be represented as a future.
+pub fn extract_result_callback<'a>(
+    fn_decl: &'a ast::FnDecl,
+    context: &Context,
+) -> Option<&'a ast::BareFnTy> {
+    let mut callbacks = fn_decl.inputs.iter().filter_map(|arg| callback_ty(&arg.ty));
+
+    let callback = match (callbacks.next(), callbacks.next()) {
+        (Some(callback), None) => callback,
+        // Functions with multiple callbacks can't be represented by a single future
+        _ => return None,
+    };
+
+    let inputs = &callback.decl.inputs;
+    let convention = &context.callbacks;
+    let user_data = inputs.get(convention.user_data_index);
+    let result = inputs.get(convention.result_index);
+
+    match (user_data, result) {
+        (Some(user_data), Some(result)) if convention.is_user_data_arg(user_data) &&
+                                            convention.is_result_arg(result) => Some(callback),
+        _ => None,
+    }
+}
+
+/// Names of the parameters of a lambda implementing the result callback, in the order
+/// of the callback arguments: `result` for the result and the given names for values.
+pub fn result_lambda_params(
+    callback: &ast::BareFnTy,
+    value_names: &[&str],
+    context: &Context,
+) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut values = value_names.iter();
+
+    let mut cb_args = callback
+        .decl
+        .inputs
+        .iter()
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
+        .peekable();
+
+    while let Some(arg) = cb_args.next() {
+        if context.callbacks.is_result_arg(arg) {
+            params.push("result".to_owned());
+            continue;
+        }
+
+        if is_array_arg(&arg, cb_args.peek().cloned(), &context.callbacks) {
+            cb_args.next();
+        }
+
+        params.push(values.next().map_or_else(String::new, |name| name.to_string()));
+    }
+
+    params
+}
+
+/// Transform a Rust FFI function reporting its result through a single
+/// `FfiResult` callback into a Java method returning `CompletableFuture`.
+/// Multiple values passed to the callback are completed as an instance of
+/// a generated `<Name>Result` model class.
+///
+/// Returns `None` if the function doesn't follow this convention.
+fn transform_async_fn(
+    fn_decl: &ast::FnDecl,
+    docs: &str,
+    java_name: &str,
+    outputs: &mut Outputs,
+    context: &Context,
+) -> Result<Option<String>, Error> {
+    let callback = match extract_result_callback(fn_decl, context) {
+        Some(callback) => callback,
+        None => return Ok(None),
+    };
+
+    let mut params = Vec::new();
+    let mut call_args = Vec::new();
+    let mut cancellable = false;
+
+    let mut fn_args = fn_decl
+        .inputs
+        .iter()
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
+        .peekable();
+
+    while let Some(arg) = fn_args.next() {
+        if callback_ty(&arg.ty).is_some() {
+            call_args.push(None);
+            continue;
+        }
+
+        // The cancellation token is created by the wrapper
+        if context.is_cancel_token(&arg.ty) {
+            call_args.push(Some("cancelToken".to_owned()));
+            cancellable = true;
+            continue;
+        }
+
+        let arg_name = pprust::pat_to_string(&*arg.pat).to_camel_case();
+        let mut java_type = try_some!(rust_to_java(&arg.ty, context));
+
+        if is_array_arg(&arg, fn_args.peek().cloned(), &context.callbacks) {
+            java_type.push_str("[]");
+            fn_args.next();
+        }
+
+        params.push(format!("{} {}", java_type, arg_name));
+        call_args.push(Some(arg_name));
+    }
+
+    // Skip `user_data` and `result` params
+    let mut cb_args = callback
+        .decl
+        .inputs
+        .iter()
+        .filter(|arg| {
+            !context.callbacks.is_user_data_arg(arg) && !context.callbacks.is_result_arg(arg)
+        })
+        .peekable();
+
+    let mut values = Vec::new();
+
+    while let Some(arg) = cb_args.next() {
+        let mut java_type = try_some!(rust_to_java(&*arg.ty, context));
+        let mut name = pprust::pat_to_string(&*arg.pat);
+
+        if is_array_arg(&arg, cb_args.peek().cloned(), &context.callbacks) {
+            java_type.push_str("[]");
+            cb_args.next();
+
+            if name.ends_with("_ptr") {
+                let len = name.len() - "_ptr".len();
+                name.truncate(len);
+            }
+        }
+
+        values.push((java_type, name.to_camel_case()));
+    }
+
+    let value_names: Vec<_> = if values.len() > 1 {
+        (0..values.len()).map(|index| format!("value{}", index)).collect()
+    } else {
+        vec!["value".to_owned()]
+    };
+
+    let (future_type, value) = match values.len() {
+        0 => (String::from("Void"), String::from("null")),
+        1 => (java_boxed_type(&values[0].0), String::from("value")),
+        _ => {
+            // Java has no tuples, so the values are held by a generated class
+            let class_name = format!("{}Result", java_name.to_class_case());
+            let class = generate_result_class(&class_name, java_name, &values, context);
+            outputs.insert(context.model_file(&class_name, "java"), class);
+
+            let value = format!("new {}({})", class_name, value_names.join(", "));
+            (class_name, value)
+        }
+    };
+    let value_names: Vec<_> = value_names.iter().map(String::as_str).collect();
+    let lambda_params = format!(
+        "({})",
+        result_lambda_params(callback, &value_names, context).join(", ")
+    );
+    let error_code = format!("result.get{}()", context.callbacks.error_code_field.to_pascal_case());
+    let description = format!(
+        "result.get{}()",
+        context.callbacks.description_field.to_pascal_case()
+    );
+
+    let exception_class = context
+        .exception_class
+        .rsplit('.')
+        .next()
+        .unwrap_or_default();
+
+    let completion = format!(
+        "if ({code} != 0) {{\n\
+         \tfuture.completeExceptionally(new {exception}({code}, {description}));\n\
+         }} else {{\n\
+         \tfuture.complete({value});\n\
+         }}\n",
+        code = error_code,
+        description = description,
+        exception = exception_class,
+        value = value,
+    );
+
+    let cancellation = match context.cancellation {
+        Some(ref convention) if cancellable => Some(convention),
+        _ => None,
+    };
+
+    // The token is released once the future is completed, under the lock taken by
+    // `cancel`, so it's never cancelled after being released.
+    let completion = match cancellation {
+        Some(convention) => format!(
+            "synchronized (future) {{\n\
+             {completion}\
+             \tNativeBindings.{free}(cancelToken);\n\
+             }}\n",
+            completion = indent_lines(&completion, 1),
+            free = convention.free_function.to_camel_case(),
+        ),
+        None => completion,
+    };
+
+    let lambda = format!(
+        "{params} -> {{\n{body}\t}}",
+        params = lambda_params,
+        body = indent_lines(&completion, 2),
+    );
+
+    let call_args: Vec<_> = call_args
+        .into_iter()
+        .map(|arg| arg.unwrap_or_else(|| lambda.clone()))
+        .collect();
+
+    let mut buffer = String::new();
+    buffer.push_str("/**\n");
+    buffer.push_str(&docs.replace("///", " *"));
+    buffer.push_str(" */\n");
+    buffer.push_str(&format!(
+        "public static CompletableFuture<{ty}> {name}({params}) {{\n",
+        ty = future_type,
+        name = java_name,
+        params = params.join(", "),
+    ));
+
+    match cancellation {
+        Some(convention) => buffer.push_str(&format!(
+            "\tfinal long cancelToken = NativeBindings.{new}();\n\
+             \tfinal CompletableFuture<{ty}> future = new CompletableFuture<{ty}>() {{\n\
+             \t\t@Override\n\
+             \t\tpublic boolean cancel(boolean mayInterruptIfRunning) {{\n\
+             \t\t\tsynchronized (this) {{\n\
+             \t\t\t\tif (!isDone()) {{\n\
+             \t\t\t\t\tNativeBindings.{cancel}(cancelToken);\n\
+             \t\t\t\t}}\n\
+             \t\t\t}}\n\
+             \t\t\treturn super.cancel(mayInterruptIfRunning);\n\
+             \t\t}}\n\
+             \t}};\n",
+            ty = future_type,
+            new = convention.new_function.to_camel_case(),
+            cancel = convention.cancel_function.to_camel_case(),
+        )),
+        None => buffer.push_str(&format!(
+            "\tfinal CompletableFuture<{ty}> future = new CompletableFuture<>();\n",
+            ty = future_type,
+        )),
+    }
+
+    buffer.push_str(&format!(
+        "\tNativeBindings.{name}({args});\n\
+         \treturn future;\n\
+         }}\n\n",
+        name = java_name,
+        args = call_args.join(", "),
+    ));
+
+    Ok(Some(buffer))
+}
+
+/// Generate a class holding the values passed to the result callback of
+/// an asynchronous function.
+fn generate_result_class(
+    class_name: &str,
+    java_name: &str,
+    values: &[(String, String)],
+    context: &Context,
+) -> String {
+    let mut buffer = String::new();
+    buffer.push_str(&format!("package {};\n\n", context.namespace_model));
+    buffer.push_str("import java.util.Arrays;\n");
+    buffer.push_str("import java.util.Objects;\n\n");
+    buffer.push_str(&format!(
+        "/**\n * Result of {{@code {}}}.\n */\n",
+        java_name
+    ));
+    buffer.push_str(&format!("public class {} {{\n", class_name));
+
+    for &(ref ty, ref name) in values {
+        buffer.push_str(&format!("\tprivate final {} {};\n\n", ty, name));
+    }
+
+    let params: Vec<_> = values
+        .iter()
+        .map(|&(ref ty, ref name)| format!("{} {}", ty, name))
+        .collect();
+    let assignments: Vec<_> = values
+        .iter()
+        .map(|&(_, ref name)| format!("\t\tthis.{name} = {name};\n", name = name))
+        .collect();
+    buffer.push_str(&format!(
+        "\tpublic {name}({params}) {{\n{assignments}\t}}\n\n",
+        name = class_name,
+        params = params.join(", "),
+        assignments = assignments.concat(),
+    ));
+
+    for &(ref ty, ref name) in values {
+        buffer.push_str(&format!(
+            "\tpublic {ty} get{capitalized}() {{\n\t\treturn {name};\n\t}}\n\n",
+            ty = ty,
+            name = name,
+            capitalized = name.to_class_case(),
+        ));
+    }
+
+    buffer.push_str(&generate_equals(class_name, values));
+    buffer.push_str(&generate_hash_code(values));
+    buffer.push_str(&generate_to_string(class_name, values));
+    buffer.push_str("}\n");
+    buffer
+}
+
+/// Indent non-empty lines of the code by the given number of tabs.
+fn indent_lines(code: &str, level: usize) -> String {
+    let indent = "\t".repeat(level);
+    code.lines().fold(String::new(), |mut s, line| {
+        if !line.is_empty() {
+            s.push_str(&indent);
+        }
+        s.push_str(line);
+        s.push_str("\n");
+        s
+    })
+}
+
+/// Convert a primitive Java type into its boxed counterpart usable in generics.
+fn java_boxed_type(ty: &str) -> String {
+    match ty {
+        "boolean" => "Boolean",
+        "byte" => "Byte",
+        "short" => "Short",
+        "int" => "Integer",
+        "long" => "Long",
+        "float" => "Float",
+        "double" => "Double",
+        "void" => "Void",
+        ty => ty,
+    }.to_owned()
+}
+
 /// Turn a Rust callback function type into a Java interface.
 pub fn transform_callback<S: AsRef<str>>(
     ty: &ast::Ty,
     class_name: S,
     context: &Context,
 ) -> Result<Option<String>, Error> {
-    match ty.node {
-        ast::TyKind::BareFn(ref bare_fn) => Ok(Some(format!(
+    match callback_ty(ty) {
+        Some(bare_fn) => Ok(Some(format!(
             "package {namespace};\n\n\
              public interface {name} {{\n\
              \tpublic void call({types});\n}}\n",
@@ -433,7 +1257,7 @@ pub fn transform_callback<S: AsRef<str>>(
             types = try_some!(callback_to_java(bare_fn, ty.span, context)),
         ))),
         // All other types just have a name associated with them.
-        _ => Err(Error {
+        None => Err(Error {
             level: Level::Error,
             span: Some(ty.span),
             message: "Invalid callback type".into(),
@@ -467,14 +1291,14 @@ fn callback_to_java(
     let mut args_iter = fn_decl
         .inputs
         .iter()
-        .filter(|arg| !is_user_data_arg(arg))
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
         .peekable();
 
     while let Some(arg) = args_iter.next() {
         let arg_name = pprust::pat_to_string(&*arg.pat);
         let mut java_type = try_some!(rust_to_java(&*arg.ty, context));
 
-        if is_array_arg(&arg, args_iter.peek().cloned()) {
+        if is_array_arg(&arg, args_iter.peek().cloned(), &context.callbacks) {
             // Detect array ptrs: skip the length args and add array to the type sig
             java_type.push_str("[]");
             args_iter.next();
@@ -511,12 +1335,27 @@ fn callback_arg_to_java(
 
 /// Turn a Rust type with an associated name or type into a C type.
 pub fn rust_to_java(ty: &ast::Ty, context: &Context) -> Result<Option<String>, Error> {
-    match ty.node {
+    match callback_ty(ty) {
         // This is a callback ref taken as a function argument
-        ast::TyKind::BareFn(ref bare_fn) => callback_arg_to_java(bare_fn, ty.span, context),
+        Some(bare_fn) => callback_arg_to_java(bare_fn, ty.span, context),
 
         // All other types just have a name associated with them.
-        _ => anon_rust_to_java(ty, context, true),
+        None => anon_rust_to_java(ty, context, true),
+    }
+}
+
+/// Returns the element type and the length of a fixed-size array type (e.g. `[u8; 32]`),
+/// looking through a pointer to the array if necessary.
+pub fn fixed_array_ty(ty: &ast::Ty) -> Option<(&ast::Ty, &ast::Expr)> {
+    match ty.node {
+        ast::TyKind::Array(ref elem, ref len) => Some((elem, len)),
+        ast::TyKind::Ptr(ref ptr) => {
+            match ptr.ty.node {
+                ast::TyKind::Array(ref elem, ref len) => Some((elem, len)),
+                _ => None,
+            }
+        }
+        _ => None,
     }
 }
 
@@ -524,6 +1363,12 @@ pub fn rust_to_java(ty: &ast::Ty, context: &Context) -> Result<Option<String>, E
 /// Handles the size types in a special way because Rust has to distinguish
 /// between usize and u64, that's required for JNI bindings to work properly.
 fn rust_ty_to_java_class_name(ty: &ast::Ty, context: &Context) -> Result<Option<String>, Error> {
+    if let Some((elem, _)) = fixed_array_ty(ty) {
+        return Ok(rust_ty_to_java_class_name(elem, context)?.map(|elem| {
+            format!("{}Array", struct_to_java_classname(elem))
+        }));
+    }
+
     match ty.node {
         ast::TyKind::Path(None, ref path) => {
             let primitive_type: &str = &path.segments[0].identifier.name.as_str();
@@ -553,18 +1398,42 @@ fn anon_rust_to_java(
                 .into(),
         }),
 
+        // Cancellation tokens are passed as long values.
+        ast::TyKind::Ptr(_) if context.is_cancel_token(ty) => Ok(Some("long".into())),
+
         // Standard pointers.
-        ast::TyKind::Ptr(ref ptr) => {
-            // Detect strings, which are *const c_char or *mut c_char
-            if pprust::ty_to_string(&ptr.ty) == "c_char" {
-                return Ok(Some("String".into()));
-            }
-            anon_rust_to_java(&ptr.ty, context, use_type_map)
+        ast::TyKind::Ptr(ref ptr) => pointee_to_java(&ptr.ty, context, use_type_map),
+
+        // Pointers which can be null (`Option<&T>`, `Option<NonNull<T>>`) and `NonNull<T>`.
+        ast::TyKind::Path(None, _) if common::nullable_pointee(ty).is_some() ||
+                                     common::generic_param(ty, "NonNull").is_some() => {
+            let pointee = common::nullable_pointee(ty)
+                .or_else(|| common::generic_param(ty, "NonNull"))
+                .expect("already checked the type is a pointer");
+            pointee_to_java(pointee, context, use_type_map)
         }
 
         // Plain old types.
         ast::TyKind::Path(None, ref path) => path_to_java(path, context, use_type_map),
 
+        // Fixed-size arrays, e.g. `[u8; 32]`.
+        ast::TyKind::Array(ref elem, _) => {
+            Ok(anon_rust_to_java(elem, context, use_type_map)?.map(|elem| format!("{}[]", elem)))
+        }
+
+        // References, e.g. `&'static str` or `&'static [u8]` in constants.
+        ast::TyKind::Rptr(_, ref ptr) => {
+            match ptr.ty.node {
+                ast::TyKind::Slice(ref elem) => {
+                    Ok(anon_rust_to_java(elem, context, use_type_map)?.map(
+                        |elem| format!("{}[]", elem),
+                    ))
+                }
+                _ if pprust::ty_to_string(&ptr.ty) == "str" => Ok(Some("String".into())),
+                _ => anon_rust_to_java(&ptr.ty, context, use_type_map),
+            }
+        }
+
         // Possibly void, likely not.
         _ => {
             let new_type = pprust::ty_to_string(ty);
@@ -581,6 +1450,102 @@ fn anon_rust_to_java(
     }
 }
 
+/// Turn the type a pointer points to into a Java type signature.
+fn pointee_to_java(
+    ty: &ast::Ty,
+    context: &Context,
+    use_type_map: bool,
+) -> Result<Option<String>, Error> {
+    // Detect strings, which are *const c_char or *mut c_char
+    if pprust::ty_to_string(ty) == "c_char" {
+        return Ok(Some("String".into()));
+    }
+    anon_rust_to_java(ty, context, use_type_map)
+}
+
+/// Convert a constant value into Java. Supports literals, byte strings and arrays.
+fn const_value_to_java(expr: &ast::Expr, ty: &str) -> Option<String> {
+    let lit = match expr.node {
+        ast::ExprKind::Lit(ref lit) => lit,
+        ast::ExprKind::AddrOf(_, ref expr) => return const_value_to_java(expr, ty),
+        ast::ExprKind::Array(ref elements) if ty.ends_with("[]") => {
+            let elem_ty = &ty[..ty.len() - 2];
+            let elements: Option<Vec<_>> = elements
+                .iter()
+                .map(|elem| const_value_to_java(elem, elem_ty))
+                .collect();
+
+            return elements.map(|elements| {
+                format!("new {}[] {{ {} }}", elem_ty, elements.join(", "))
+            });
+        }
+        _ => return None,
+    };
+
+    let value = match lit.node {
+        ast::LitKind::Bool(value) => format!("{}", value),
+        ast::LitKind::Byte(value) => int_value_to_java(value as i64, ty),
+        ast::LitKind::Int(value, _) => int_value_to_java(value as i64, ty),
+        ast::LitKind::Float(ref value, _) |
+        ast::LitKind::FloatUnsuffixed(ref value) if ty == "float" => format!("{}f", value),
+        ast::LitKind::Float(ref value, _) |
+        ast::LitKind::FloatUnsuffixed(ref value) => format!("{}", value),
+        ast::LitKind::Str(ref value, ..) => string_to_java(&*value.as_str()),
+        ast::LitKind::ByteStr(ref value) => {
+            let bytes: Vec<_> = value
+                .iter()
+                .map(|byte| int_value_to_java(*byte as i64, "byte"))
+                .collect();
+            format!("new byte[] {{ {} }}", bytes.join(", "))
+        }
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Quote and escape a string as a Java string literal. Unicode escapes are translated
+/// before the source is parsed, so control characters use octal escapes instead.
+fn string_to_java(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\x7f' => {
+                result.push_str(&format!("\\{:03o}", c as u32))
+            }
+            c if (c as u32) < 0x80 => result.push(c),
+            c => {
+                let mut buffer = [0; 2];
+                for unit in c.encode_utf16(&mut buffer).iter() {
+                    result.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+/// Format an integer constant value of the given Java type. Java integers are signed,
+/// so unsigned values are converted to their two's complement representation.
+fn int_value_to_java(value: i64, ty: &str) -> String {
+    match ty {
+        "long" => format!("{}L", value),
+        "byte" => format!("{}", value as u8 as i8),
+        "short" => format!("{}", value as u16 as i16),
+        "int" => format!("{}", value as u32 as i32),
+        _ => format!("{}", value),
+    }
+}
+
 /// Convert a Rust path type (my_mod::MyType) to a C type.
 ///
 /// Types hidden behind modules are almost certainly custom types (which wouldn't work) except
@@ -706,9 +1671,25 @@ fn rust_ty_to_java<'a>(ty: &'a str, context: &Context, use_type_map: bool) -> &'
 #[cfg(test)]
 mod tests {
     use super::*;
+    use common::Lang;
     use syntax::ast::{Arg, ItemKind};
     use syntax::parse::{self, ParseSess};
 
+    fn parse_fn_decl(source: &str) -> ast::FnDecl {
+        let parse_sess = ParseSess::new();
+
+        let item = unwrap!(unwrap!(parse::parse_item_from_source_str(
+            "dummy.rs".to_owned(),
+            source.to_owned(),
+            &parse_sess,
+        )));
+
+        match item.node {
+            ItemKind::Fn(ref fn_decl, _, _, _, _, _) => (**fn_decl).clone(),
+            _ => panic!("wrong item type"),
+        }
+    }
+
     #[test]
     fn cb_names() {
         fn get_inputs(source: &str) -> Vec<Arg> {
@@ -726,13 +1707,7 @@ mod tests {
             }
         }
 
-        let context = Context {
-            type_map: HashMap::new(),
-            lib_name: "backend".to_owned(),
-            namespace: "net.maidsafe.bindings".to_owned(),
-            namespace_model: "net.maidsafe.model".to_owned(),
-            generated_jni_cbs: BTreeSet::new(),
-        };
+        let context = Context::new(HashMap::new());
 
         let inputs = get_inputs("fn dummy() {}");
         assert_eq!("CallbackVoid", unwrap!(callback_name(&inputs, &context)));
@@ -766,4 +1741,581 @@ mod tests {
             unwrap!(callback_name(&inputs, &context))
         );
     }
+
+    #[test]
+    fn fixed_size_arrays() {
+        let context = Context::new(HashMap::new());
+
+        let fn_decl = parse_fn_decl(
+            "fn dummy(key: *const [u8; 32], keys: [MDataKey; 4], \
+             o_cb: extern \"C\" fn(user_data: *mut c_void, \
+             result: *const FfiResult, nonce: *const [u8; 24])) {}",
+        );
+
+        assert_eq!(
+            unwrap!(rust_to_java(&fn_decl.inputs[0].ty, &context)),
+            Some("byte[]".to_owned())
+        );
+        assert_eq!(
+            unwrap!(rust_to_java(&fn_decl.inputs[1].ty, &context)),
+            Some("MDataKey[]".to_owned())
+        );
+
+        match fn_decl.inputs[2].ty.node {
+            ast::TyKind::BareFn(ref bare_fn) => {
+                assert_eq!(
+                    "CallbackResultByteArray",
+                    unwrap!(callback_name(&bare_fn.decl.inputs, &context))
+                );
+            }
+            _ => panic!("callback expected"),
+        }
+
+        let mut context = context;
+        let mut outputs = Outputs::new();
+        let fn_decl = parse_fn_decl("fn dummy(key: *const [u8; 32]) {}");
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "dummy",
+            &[],
+            &[],
+            &mut outputs,
+            &mut context,
+        ));
+
+        let jni = unwrap!(outputs.get(&PathBuf::from("jni.rs")));
+        assert!(jni.contains("check_array_len ( \"key\" , key . len ( ) , 32 ) ?"));
+        assert!(jni.contains(":: std :: mem :: MaybeUninit :: < [ u8 ; 32 ] > :: uninit ( )"));
+        assert!(jni.contains("move_into_array ( key , arr . as_mut_ptr ( ) as * mut u8 )"));
+
+        // Structs are moved into the array, without requiring `Copy` or `Default`.
+        let mut outputs = Outputs::new();
+        let fn_decl = parse_fn_decl("fn dummy(keys: [MDataKey; 4]) {}");
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "dummy",
+            &[],
+            &[],
+            &mut outputs,
+            &mut context,
+        ));
+
+        let jni = unwrap!(outputs.get(&PathBuf::from("jni.rs")));
+        assert!(jni.contains("Vec :: < MDataKey > :: from_java ( & env , keys ) ?"));
+        assert!(jni.contains("check_array_len ( \"keys\" , keys . len ( ) , 4 ) ?"));
+        assert!(jni.contains(
+            ":: std :: mem :: MaybeUninit :: < [ MDataKey ; 4 ] > :: uninit ( )",
+        ));
+        assert!(jni.contains(
+            "move_into_array ( keys , arr . as_mut_ptr ( ) as * mut MDataKey )",
+        ));
+        assert!(!jni.contains("Default :: default ( )"));
+    }
+
+    #[test]
+    fn struct_value_methods() {
+        let fields = vec![
+            ("long".to_owned(), "id".to_owned()),
+            ("String".to_owned(), "name".to_owned()),
+            ("byte[]".to_owned(), "key".to_owned()),
+            ("MDataKey[]".to_owned(), "entries".to_owned()),
+        ];
+
+        assert_eq!(
+            generate_equals("Foo", &fields),
+            "\t@Override\n\
+             \tpublic boolean equals(Object o) {\n\
+             \t\tif (this == o) {\n\
+             \t\t\treturn true;\n\
+             \t\t}\n\
+             \t\tif (o == null || getClass() != o.getClass()) {\n\
+             \t\t\treturn false;\n\
+             \t\t}\n\
+             \t\tfinal Foo other = (Foo) o;\n\
+             \t\treturn id == other.id &&\n\
+             \t\t\tObjects.equals(name, other.name) &&\n\
+             \t\t\tArrays.equals(key, other.key) &&\n\
+             \t\t\tArrays.deepEquals(entries, other.entries);\n\
+             \t}\n\n"
+        );
+
+        assert_eq!(
+            generate_hash_code(&fields),
+            "\t@Override\n\
+             \tpublic int hashCode() {\n\
+             \t\tint result = Objects.hash(id, name);\n\
+             \t\tresult = 31 * result + Arrays.hashCode(key);\n\
+             \t\tresult = 31 * result + Arrays.deepHashCode(entries);\n\
+             \t\treturn result;\n\
+             \t}\n\n"
+        );
+
+        assert_eq!(
+            generate_to_string("Foo", &fields),
+            "\t@Override\n\
+             \tpublic String toString() {\n\
+             \t\treturn \"Foo{id=\" + id + \", name=\" + name + \", \
+             key=\" + Arrays.toString(key) + \", \
+             entries=\" + Arrays.deepToString(entries) + \"}\";\n\
+             \t}\n"
+        );
+
+        let context = Context::new(HashMap::new());
+        assert_eq!(
+            context.model_file("Foo", "java"),
+            PathBuf::from("net/maidsafe/model/Foo.java")
+        );
+    }
+
+    #[test]
+    fn nullable_args() {
+        let mut context = Context::new(HashMap::new());
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl(
+            "fn fetch(key: Option<&MDataKey>, name: *const c_char, tag: *const c_char, \
+             user_data: *mut c_void, o_cb: Option<extern \"C\" fn(user_data: *mut c_void)>) {}",
+        );
+        let nullable = vec!["tag".to_owned()];
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "fetch",
+            &nullable,
+            &[],
+            &mut outputs,
+            &mut context,
+        ));
+
+        let bindings = unwrap!(outputs.get(&PathBuf::from("NativeBindings.java")));
+        assert_eq!(
+            *bindings,
+            "/**\n */\n\
+             public static native void fetch(\
+             @javax.annotation.Nullable MDataKey key, \
+             String name, \
+             @javax.annotation.Nullable String tag, \
+             @javax.annotation.Nullable CallbackVoid oCb);\n\n"
+        );
+    }
+
+    #[test]
+    fn out_params() {
+        let mut context = Context::new(HashMap::new());
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl("fn app_open(name: *const c_char, o_app: *mut *mut App) {}");
+        let out = common::out_params(&fn_decl, &[]);
+        assert_eq!(out, vec!["o_app".to_owned()]);
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "app_open",
+            &[],
+            &out,
+            &mut outputs,
+            &mut context,
+        ));
+
+        let fn_decl = parse_fn_decl(
+            "fn point_at(id: u64, o_len: *mut usize, o_point: *mut Point, \
+             buf: *mut u8) -> i32 {}",
+        );
+        let out = common::out_params(&fn_decl, &[]);
+        assert_eq!(out, vec!["o_len".to_owned(), "o_point".to_owned()]);
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "point_at",
+            &[],
+            &out,
+            &mut outputs,
+            &mut context,
+        ));
+
+        let bindings = unwrap!(outputs.get(&PathBuf::from("NativeBindings.java")));
+        assert_eq!(
+            *bindings,
+            "/**\n */\n\
+             public static native long appOpen(String name);\n\n\
+             /**\n */\n\
+             public static native int pointAt(long id, long[] oLen, Point[] oPoint, byte buf);\n\n"
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        let mut context = Context::new(HashMap::new());
+        context.last_error_function = Some("last_error".to_owned());
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl("fn app_close(handle: u64) -> i32 {}");
+        assert!(returns_error_code(&fn_decl, &context));
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "app_close",
+            &[],
+            &[],
+            &mut outputs,
+            &mut context,
+        ));
+
+        let fn_decl = parse_fn_decl("fn app_len(handle: u64, o_len: *mut usize) -> i32 {}");
+        let out = common::out_params(&fn_decl, &[]);
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "app_len",
+            &[],
+            &out,
+            &mut outputs,
+            &mut context,
+        ));
+
+        let fn_decl = parse_fn_decl("fn app_count(handle: u64) -> u32 {}");
+        assert!(!returns_error_code(&fn_decl, &context));
+
+        let bindings = unwrap!(outputs.get(&PathBuf::from("NativeBindings.java")));
+        assert_eq!(
+            *bindings,
+            "/**\n */\n\
+             public static native void appClose(long handle);\n\n\
+             /**\n */\n\
+             public static native long appLen(long handle);\n\n"
+        );
+
+        let jni = unwrap!(outputs.get(&PathBuf::from("jni.rs")));
+        assert!(jni.contains("check_error_code ( & env , code ) ?"));
+
+        let prelude = jni::generate_prelude(&context);
+        assert!(prelude.contains("fn check_error_code"));
+        assert!(prelude.contains("last_error ( )"));
+    }
+
+    #[test]
+    fn exceptions_are_constructed_from_code_and_description() {
+        let mut context = Context::new(HashMap::new());
+        context.last_error_function = Some("last_error".to_owned());
+
+        // The same `(int, String)` constructor is used for all errors
+        let prelude = jni::generate_prelude(&context);
+        assert!(!prelude.contains("throw_new"));
+        assert_eq!(prelude.matches("(ILjava/lang/String;)V").count(), 1);
+        assert!(prelude.contains("throw_exception ( env , code , description ) ?"));
+    }
+
+    #[test]
+    fn callback_errors_are_logged() {
+        let mut context = Context::new(HashMap::new());
+        let prelude = jni::generate_prelude(&context);
+        assert!(prelude.contains("error ! ( \"Error in JNI callback {}: {}\""));
+        assert!(!prelude.contains("eprintln"));
+
+        context.log_macro = "warn".to_owned();
+        let prelude = jni::generate_prelude(&context);
+        assert!(prelude.contains("warn ! ( \"Error in JNI callback {}: {}\""));
+    }
+
+    #[test]
+    fn async_fns() {
+        let context = Context::new(HashMap::new());
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl(
+            "fn app_version(id: u64, user_data: *mut c_void, \
+             o_cb: extern \"C\" fn(user_data: *mut c_void, \
+             result: *const FfiResult, version: u32)) {}",
+        );
+        let actual = unwrap!(unwrap!(
+            transform_async_fn(&fn_decl, "", "appVersion", &mut outputs, &context)
+        ));
+        assert_eq!(
+            actual,
+            "/**\n */\n\
+             public static CompletableFuture<Integer> appVersion(long id) {\n\
+             \tfinal CompletableFuture<Integer> future = new CompletableFuture<>();\n\
+             \tNativeBindings.appVersion(id, (result, value) -> {\n\
+             \t\tif (result.getErrorCode() != 0) {\n\
+             \t\t\tfuture.completeExceptionally(\
+             new FfiException(result.getErrorCode(), result.getDescription()));\n\
+             \t\t} else {\n\
+             \t\t\tfuture.complete(value);\n\
+             \t\t}\n\
+             \t});\n\
+             \treturn future;\n\
+             }\n\n"
+        );
+
+        // No callback
+        let fn_decl = parse_fn_decl("fn app_free(app: *mut App) {}");
+        let actual = unwrap!(transform_async_fn(&fn_decl, "", "appFree", &mut outputs, &context));
+        assert!(actual.is_none());
+
+        // Multiple values are held by a generated class
+        let fn_decl = parse_fn_decl(
+            "fn mdata_get(key: u64, user_data: *mut c_void, \
+             o_cb: extern \"C\" fn(user_data: *mut c_void, result: *const FfiResult, \
+             content_ptr: *const u8, content_len: usize, version: u64)) {}",
+        );
+        let actual = unwrap!(unwrap!(
+            transform_async_fn(&fn_decl, "", "mdataGet", &mut outputs, &context)
+        ));
+        assert_eq!(
+            actual,
+            "/**\n */\n\
+             public static CompletableFuture<MdataGetResult> mdataGet(long key) {\n\
+             \tfinal CompletableFuture<MdataGetResult> future = new CompletableFuture<>();\n\
+             \tNativeBindings.mdataGet(key, (result, value0, value1) -> {\n\
+             \t\tif (result.getErrorCode() != 0) {\n\
+             \t\t\tfuture.completeExceptionally(\
+             new FfiException(result.getErrorCode(), result.getDescription()));\n\
+             \t\t} else {\n\
+             \t\t\tfuture.complete(new MdataGetResult(value0, value1));\n\
+             \t\t}\n\
+             \t});\n\
+             \treturn future;\n\
+             }\n\n"
+        );
+
+        let class = unwrap!(outputs.get(&PathBuf::from("net/maidsafe/model/MdataGetResult.java")));
+        assert!(class.starts_with(
+            "package net.maidsafe.model;\n\n\
+             import java.util.Arrays;\n\
+             import java.util.Objects;\n\n\
+             /**\n * Result of {@code mdataGet}.\n */\n\
+             public class MdataGetResult {\n\
+             \tprivate final byte[] content;\n\n\
+             \tprivate final long version;\n\n\
+             \tpublic MdataGetResult(byte[] content, long version) {\n\
+             \t\tthis.content = content;\n\
+             \t\tthis.version = version;\n\
+             \t}\n\n\
+             \tpublic byte[] getContent() {\n\
+             \t\treturn content;\n\
+             \t}\n\n",
+        ));
+        assert!(class.contains("Arrays.equals(content, other.content)"));
+
+        // Callback not taking a result
+        let fn_decl = parse_fn_decl(
+            "fn app_notify(user_data: *mut c_void, \
+             o_cb: extern \"C\" fn(user_data: *mut c_void, event: i32)) {}",
+        );
+        let actual = unwrap!(transform_async_fn(&fn_decl, "", "appNotify", &mut outputs, &context));
+        assert!(actual.is_none());
+    }
+
+    #[test]
+    fn async_fns_with_custom_callback_convention() {
+        let mut context = Context::new(HashMap::new());
+        context.callbacks = CallbackConvention {
+            user_data_name: "ctx".to_owned(),
+            user_data_index: 0,
+            result_name: "err".to_owned(),
+            result_index: 2,
+            result_type: "Error".to_owned(),
+            error_code_field: "code".to_owned(),
+            description_field: "message".to_owned(),
+            description_first: false,
+        };
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl(
+            "fn app_version(id: u64, ctx: *mut c_void, \
+             o_cb: extern \"C\" fn(ctx: *mut c_void, version: u32, err: *const Error)) {}",
+        );
+        let actual = unwrap!(unwrap!(
+            transform_async_fn(&fn_decl, "", "appVersion", &mut outputs, &context)
+        ));
+        assert_eq!(
+            actual,
+            "/**\n */\n\
+             public static CompletableFuture<Integer> appVersion(long id) {\n\
+             \tfinal CompletableFuture<Integer> future = new CompletableFuture<>();\n\
+             \tNativeBindings.appVersion(id, (value, result) -> {\n\
+             \t\tif (result.getCode() != 0) {\n\
+             \t\t\tfuture.completeExceptionally(\
+             new FfiException(result.getCode(), result.getMessage()));\n\
+             \t\t} else {\n\
+             \t\t\tfuture.complete(value);\n\
+             \t\t}\n\
+             \t});\n\
+             \treturn future;\n\
+             }\n\n"
+        );
+
+        // The result is not at the configured position
+        let fn_decl = parse_fn_decl(
+            "fn app_version(id: u64, ctx: *mut c_void, \
+             o_cb: extern \"C\" fn(ctx: *mut c_void, err: *const Error, version: u32)) {}",
+        );
+        let actual =
+            unwrap!(transform_async_fn(&fn_decl, "", "appVersion", &mut outputs, &context));
+        assert!(actual.is_none());
+
+        // The user data is passed to the JNI callback at its native position
+        let fn_decl = parse_fn_decl(
+            "fn app_version(id: u64, ctx: *mut c_void, \
+             o_cb: extern \"C\" fn(version: u32, ctx: *mut c_void, err: *const Error)) {}",
+        );
+        context.callbacks.user_data_index = 1;
+        let callback = unwrap!(extract_result_callback(&fn_decl, &context)).clone();
+        let jni = jni::generate_jni_callback(&callback, "call_CallbackIntResult", &mut context);
+        assert!(jni.contains(
+            "fn call_CallbackIntResult ( version : u32 , ctx : * mut c_void , err :",
+        ));
+    }
+
+    #[test]
+    fn cancellable_async_fns() {
+        let mut context = Context::new(HashMap::new());
+        context.cancellation = Some(Default::default());
+        let mut outputs = Outputs::new();
+
+        let fn_decl = parse_fn_decl(
+            "fn app_version(id: u64, token: *const CancelToken, user_data: *mut c_void, \
+             o_cb: extern \"C\" fn(user_data: *mut c_void, \
+             result: *const FfiResult, version: u32)) {}",
+        );
+        let actual = unwrap!(unwrap!(
+            transform_async_fn(&fn_decl, "", "appVersion", &mut outputs, &context)
+        ));
+        assert_eq!(
+            actual,
+            "/**\n */\n\
+             public static CompletableFuture<Integer> appVersion(long id) {\n\
+             \tfinal long cancelToken = NativeBindings.cancelTokenNew();\n\
+             \tfinal CompletableFuture<Integer> future = new CompletableFuture<Integer>() {\n\
+             \t\t@Override\n\
+             \t\tpublic boolean cancel(boolean mayInterruptIfRunning) {\n\
+             \t\t\tsynchronized (this) {\n\
+             \t\t\t\tif (!isDone()) {\n\
+             \t\t\t\t\tNativeBindings.cancelTokenCancel(cancelToken);\n\
+             \t\t\t\t}\n\
+             \t\t\t}\n\
+             \t\t\treturn super.cancel(mayInterruptIfRunning);\n\
+             \t\t}\n\
+             \t};\n\
+             \tNativeBindings.appVersion(id, cancelToken, (result, value) -> {\n\
+             \t\tsynchronized (future) {\n\
+             \t\t\tif (result.getErrorCode() != 0) {\n\
+             \t\t\t\tfuture.completeExceptionally(\
+             new FfiException(result.getErrorCode(), result.getDescription()));\n\
+             \t\t\t} else {\n\
+             \t\t\t\tfuture.complete(value);\n\
+             \t\t\t}\n\
+             \t\t\tNativeBindings.cancelTokenFree(cancelToken);\n\
+             \t\t}\n\
+             \t});\n\
+             \treturn future;\n\
+             }\n\n"
+        );
+
+        // The tokens are passed to the native methods as long values
+        let fn_decl = parse_fn_decl("fn cancel_token_cancel(token: *const CancelToken) {}");
+        unwrap!(transform_native_fn(
+            &fn_decl,
+            "",
+            "cancel_token_cancel",
+            &[],
+            &[],
+            &mut outputs,
+            &mut context,
+        ));
+
+        let bindings = unwrap!(outputs.get(&PathBuf::from("NativeBindings.java")));
+        assert_eq!(
+            *bindings,
+            "/**\n */\n\
+             public static native void cancelTokenCancel(long token);\n\n"
+        );
+
+        let jni = unwrap!(outputs.get(&PathBuf::from("jni.rs")));
+        assert!(jni.contains("token : jlong"));
+        assert!(jni.contains("let token = token as * mut CancelToken ;"));
+    }
+
+    #[test]
+    fn native_bindings_import_model_classes() {
+        let mut lang = LangJava::new(HashMap::new());
+
+        let mut funcs = "public static native void entryFree(Entry entry);\n".to_owned();
+        lang.format_native_functions(&mut funcs);
+        assert_eq!(
+            funcs,
+            "package net.maidsafe.bindings;\n\n\
+             import net.maidsafe.model.*;\n\n\
+             public class NativeBindings {\n\
+             \tpublic static native void entryFree(Entry entry);\n\
+             }\n"
+        );
+
+        let mut funcs = String::new();
+        lang.format_async_functions(&mut funcs);
+        assert!(funcs.contains("import net.maidsafe.model.*;\n"));
+
+        // No import is needed if the model classes are in the same package.
+        lang.set_model_namespace("net.maidsafe.bindings");
+
+        let mut funcs = "public static native void entryFree(Entry entry);\n".to_owned();
+        lang.format_native_functions(&mut funcs);
+        assert_eq!(
+            funcs,
+            "package net.maidsafe.bindings;\n\n\
+             public class NativeBindings {\n\
+             \tpublic static native void entryFree(Entry entry);\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn string_constants_are_escaped() {
+        assert_eq!(string_to_java("safe"), r#""safe""#);
+        assert_eq!(string_to_java("say \"hi\"\\\n"), r#""say \"hi\"\\\n""#);
+        assert_eq!(string_to_java("\u{0}1\u{7f}"), r#""\0001\177""#);
+        assert_eq!(
+            string_to_java("caf\u{e9} \u{1f600}"),
+            r#""caf\u00e9 \ud83d\ude00""#
+        );
+    }
+
+    #[test]
+    fn byte_string_constants() {
+        let mut lang = LangJava::new(HashMap::new());
+        let mut outputs = Outputs::new();
+
+        for source in &[
+            "pub const TAG: &'static [u8] = b\"safe\";",
+            "pub const MAGIC: &'static [u8] = &[0xca, 0xfe];",
+            "pub const NAME: &'static str = \"safe\";",
+            "pub const LEN: u64 = 2 * 16;",
+        ]
+        {
+            let parse_sess = ParseSess::new();
+            let item = unwrap!(unwrap!(parse::parse_item_from_source_str(
+                "dummy.rs".to_owned(),
+                source.to_string(),
+                &parse_sess,
+            )));
+
+            unwrap!(lang.parse_const(&item, &mut outputs));
+        }
+
+        let consts = unwrap!(outputs.get_mut(&PathBuf::from("Constants.java")));
+        lang.format_constants(consts);
+
+        assert_eq!(
+            *consts,
+            "package net.maidsafe.bindings;\n\n\
+             public class Constants {\n\
+             \tpublic static final byte[] TAG = new byte[] { 115, 97, 102, 101 };\n\n\
+             \tpublic static final byte[] MAGIC = new byte[] { -54, -2 };\n\n\
+             \tpublic static final String NAME = \"safe\";\n\n\
+             \tpublic static final long LEN = 32L;\n\n\
+             }\n"
+        );
+    }
 }
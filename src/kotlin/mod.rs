@@ -0,0 +1,1245 @@
+//! Functions for converting Rust types to Kotlin types.
+//!
+//! Kotlin classes are binary compatible with the Java ones, so this backend reuses
+//! the Java type mapping and the JNI glue generated by `java::jni`.
+
+use Error;
+use Level;
+use common::{self, CallbackConvention, Lang, Outputs, append_output, check_no_mangle,
+             is_array_arg, parse_attr, retrieve_docstring};
+use const_eval::ConstEvaluator;
+use inflector::Inflector;
+use java::{self, Context, callback_name, extract_result_callback, rust_to_java,
+           struct_to_java_classname};
+use java::jni;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use struct_field::{StructField, transform_struct_fields};
+use syntax::ast;
+use syntax::print::pprust;
+
+pub struct LangKotlin {
+    context: Context,
+    const_evaluator: ConstEvaluator,
+    /// First variant of each enum class, used as the default value of enum properties.
+    enums: HashMap<String, String>,
+    /// Data classes are generated at the end, once the types of all properties are known.
+    data_classes: Vec<DataClass>,
+}
+
+struct DataClass {
+    name: String,
+    docs: String,
+    /// Names, Kotlin types and lengths of the fixed-size array properties.
+    properties: Vec<(String, String, Option<i64>)>,
+}
+
+impl LangKotlin {
+    pub fn new(type_map: HashMap<&'static str, &'static str>) -> Self {
+        LangKotlin {
+            context: Context::new(type_map),
+            const_evaluator: ConstEvaluator::new(),
+            enums: HashMap::new(),
+            data_classes: Vec::new(),
+        }
+    }
+
+    /// Set the name of the native library. This also sets the name of the object
+    /// containing `suspend` wrappers.
+    pub fn set_lib_name<T: Into<String>>(&mut self, name: T) {
+        self.context.lib_name = name.into();
+    }
+
+    /// Set the package to put the NativeBindings object in.
+    pub fn set_namespace<T: Into<String>>(&mut self, namespace: T) {
+        self.context.namespace = namespace.into();
+    }
+
+    /// Set the package to put all data classes and callback interfaces in.
+    pub fn set_model_namespace<T: Into<String>>(&mut self, namespace: T) {
+        self.context.namespace_model = namespace.into();
+    }
+
+    /// Set the exception class (e.g. `net.maidsafe.FfiException`) thrown when a native
+    /// function fails. It must have an `(Int, String)` constructor, see
+    /// `LangJava::set_exception_class` for details.
+    pub fn set_exception_class<T: Into<String>>(&mut self, class: T) {
+        self.context.exception_class = class.into();
+    }
+
+    /// Treat `i32` returned by synchronous functions as an error code, thrown as
+    /// the exception class with the description returned by the given function.
+    /// See `LangJava::set_last_error_function` for details.
+    pub fn set_last_error_function<T: Into<String>>(&mut self, name: T) {
+        self.context.last_error_function = Some(name.into());
+    }
+
+    /// Set the macro logging errors of callbacks called from native threads.
+    /// See `LangJava::set_log_macro` for details.
+    pub fn set_log_macro<T: Into<String>>(&mut self, name: T) {
+        self.context.log_macro = name.into();
+    }
+
+    /// Set the conventions of the callbacks reporting results of asynchronous functions.
+    /// See `LangJava::set_callback_convention` for details.
+    pub fn set_callback_convention(&mut self, convention: CallbackConvention) {
+        self.context.callbacks = convention;
+    }
+
+    /// Name of the object containing `suspend` wrappers of native functions
+    fn async_object_name(&self) -> String {
+        self.context.lib_name.to_class_case()
+    }
+
+    /// Wraps the declarations in a package and an object and indents lines
+    fn format_object(&self, decls: &mut String, name: &str, imports: &[String]) {
+        let lines = decls.lines().fold(String::new(), |mut s, line| {
+            if line.is_empty() {
+                s.push_str("\n");
+            } else {
+                s.push_str(&format!("    {}\n", line));
+            }
+            s
+        });
+
+        let mut imports = imports.iter().fold(String::new(), |mut s, import| {
+            s.push_str(&format!("import {}\n", import));
+            s
+        });
+        if !imports.is_empty() {
+            imports.push_str("\n");
+        }
+
+        *decls = format!(
+            "package {namespace}\n\n\
+             {imports}\
+             object {name} {{\n\
+             {lines}\
+             }}\n",
+            namespace = self.context.namespace,
+            imports = imports,
+            name = name,
+            lines = lines
+        );
+    }
+
+    /// Default value of a Kotlin type, used for the data class properties.
+    /// Fixed-size arrays (with a `len`) default to arrays of that length.
+    /// Returns `None` if the type isn't known to have a default value.
+    fn default_value(&self, ty: &str, len: Option<i64>) -> Option<String> {
+        if let Some(len) = len {
+            return if ty.starts_with("Array<") && ty.ends_with('>') {
+                let elem = &ty["Array<".len()..ty.len() - 1];
+                self.default_value(elem, None).map(|value| {
+                    format!("Array({}) {{ {} }}", len, value)
+                })
+            } else if ty.ends_with("Array") {
+                Some(format!("{}({})", ty, len))
+            } else {
+                None
+            };
+        }
+
+        Some(match ty {
+            "Boolean" => "false".to_owned(),
+            "Byte" | "Short" | "Int" => "0".to_owned(),
+            "Long" => "0L".to_owned(),
+            "UByte" | "UShort" | "UInt" => "0u".to_owned(),
+            "ULong" => "0uL".to_owned(),
+            "Float" => "0f".to_owned(),
+            "Double" => "0.0".to_owned(),
+            "Char" => "'\\u0000'".to_owned(),
+            "String" => "\"\"".to_owned(),
+            ty if ty.starts_with("Array<") => "emptyArray()".to_owned(),
+            ty if ty.ends_with("Array") => format!("{}(0)", ty),
+            ty => {
+                if let Some(variant) = self.enums.get(ty) {
+                    format!("{}.{}", ty, variant)
+                } else if self.data_classes.iter().any(|class| class.name == ty) {
+                    format!("{}()", ty)
+                } else {
+                    return None;
+                }
+            }
+        })
+    }
+
+    /// Generate the source of a data class.
+    fn format_data_class(&self, class: &DataClass) -> Result<String, Error> {
+        let mut properties = Vec::new();
+
+        for &(ref name, ref ty, len) in &class.properties {
+            let value = self.default_value(ty, len).ok_or_else(|| {
+                Error {
+                    level: Level::Error,
+                    span: None,
+                    message: format!(
+                        "bindgen can not determine the default value of `{}.{}: {}`",
+                        class.name,
+                        name,
+                        ty
+                    ),
+                }
+            })?;
+
+            properties.push(format!("    var {}: {} = {}", name, ty, value));
+        }
+
+        // Default values for all properties make Kotlin generate the no-arg
+        // constructor required by the JNI glue.
+        let mut buffer = String::new();
+        buffer.push_str(&format!("package {}\n\n", self.context.namespace_model));
+        buffer.push_str(&format_docs(&class.docs));
+        buffer.push_str(&format!(
+            "data class {}(\n{}\n)",
+            class.name,
+            properties.join(",\n")
+        ));
+
+        // Generated `equals` and `hashCode` compare arrays by reference.
+        if class.properties.iter().any(|&(_, ref ty, _)| is_array(ty)) {
+            buffer.push_str(" {\n");
+            buffer.push_str(&indent(&format_equals(class)));
+            buffer.push_str("}\n");
+        } else {
+            buffer.push_str("\n");
+        }
+
+        Ok(buffer)
+    }
+}
+
+impl Lang for LangKotlin {
+    /// Convert a Rust constant into a Kotlin `const val`.
+    fn parse_const(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let docs = parse_attr(&item.attrs, |_| true, |attr| retrieve_docstring(attr, "")).1;
+
+        if let ast::ItemKind::Const(ref ty, ref expr) = item.node {
+            self.const_evaluator.define(&*item.ident.name.as_str(), ty, expr);
+            let evaluated = self.const_evaluator.eval_as(expr, ty);
+
+            let ty = rust_to_kotlin(ty, &self.context)?.unwrap_or_default();
+            let value = match const_value_to_kotlin(expr, &ty).or_else(|| {
+                evaluated.map(|value| int_value_to_kotlin(value, &ty))
+            }) {
+                Some(value) => value,
+                None => {
+                    println!(
+                        "bindgen can not evaluate constant {}. Skipping.",
+                        pprust::item_to_string(item)
+                    );
+                    return Ok(());
+                }
+            };
+
+            // Arrays can't be compile-time constants.
+            let modifier = if ty.ends_with("Array") { "val" } else { "const val" };
+
+            let mut buffer = String::new();
+            buffer.push_str(&format_docs(&docs));
+            buffer.push_str(&format!(
+                "{} {}: {} = {}\n\n",
+                modifier,
+                item.ident.name.as_str(),
+                ty,
+                value
+            ));
+
+            append_output(buffer, "Constants.kt", outputs);
+        }
+
+        Ok(())
+    }
+
+    /// Convert a Rust enum into a Kotlin enum class.
+    fn parse_enum(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let (repr_c, docs) = parse_attr(&item.attrs, common::check_repr_c, |attr| {
+            retrieve_docstring(attr, "")
+        });
+        // If it's not #[repr(C)] then it can't be called from C.
+        if !repr_c {
+            return Ok(());
+        }
+
+        if let ast::ItemKind::Enum(ast::EnumDef { ref variants }, ref generics) = item.node {
+            if generics.is_parameterized() {
+                return Err(Error {
+                    level: Level::Error,
+                    span: Some(item.span),
+                    message: "bindgen can not handle parameterized `#[repr(C)]` enums".into(),
+                });
+            }
+
+            let name = struct_to_java_classname(&*item.ident.name.as_str());
+
+            let mut buffer = String::new();
+            buffer.push_str(&format!("package {}\n\n", self.context.namespace_model));
+            buffer.push_str(&format_docs(&docs));
+            buffer.push_str(&format!("enum class {}(val value: Int) {{\n", name));
+
+            let mut next_value = 0;
+
+            for (index, variant) in variants.iter().enumerate() {
+                if !variant.node.data.is_unit() {
+                    return Err(Error {
+                        level: Level::Error,
+                        span: Some(variant.span),
+                        message: "bindgen can not handle enum variants with fields".into(),
+                    });
+                }
+
+                if let Some(ref expr) = variant.node.disr_expr {
+                    next_value = self.const_evaluator.eval(expr).ok_or_else(|| {
+                        unsupported_discriminant_error(expr)
+                    })?;
+                }
+
+                let variant_docs = parse_attr(&variant.node.attrs, |_| true, |attr| {
+                    retrieve_docstring(attr, "")
+                }).1;
+
+                buffer.push_str(&indent(&format_docs(&variant_docs)));
+                buffer.push_str(&format!(
+                    "    {}({}){}\n",
+                    variant.node.name.name.as_str(),
+                    next_value,
+                    if index + 1 < variants.len() { "," } else { ";" }
+                ));
+
+                next_value += 1;
+            }
+
+            buffer.push_str("}\n");
+
+            if let Some(variant) = variants.first() {
+                let _ = self.enums.insert(
+                    name.clone(),
+                    variant.node.name.name.as_str().to_string(),
+                );
+            }
+
+            outputs.insert(self.context.model_file(&name, "kt"), buffer);
+        }
+
+        Ok(())
+    }
+
+    /// Convert a Rust struct into a Kotlin data class.
+    fn parse_struct(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let (repr_c, docs) = parse_attr(&item.attrs, common::check_repr_c, |attr| {
+            retrieve_docstring(attr, "")
+        });
+        // If it's not #[repr(C)] then it can't be called from C.
+        if !repr_c {
+            return Ok(());
+        }
+
+        let orig_name = item.ident.name.as_str();
+        let name = struct_to_java_classname(&*orig_name);
+
+        if let ast::ItemKind::Struct(ref variants, ref generics) = item.node {
+            if generics.is_parameterized() {
+                return Err(Error {
+                    level: Level::Error,
+                    span: Some(item.span),
+                    message: "bindgen can not handle parameterized `#[repr(C)]` structs".into(),
+                });
+            }
+
+            if !variants.is_struct() {
+                return Err(Error {
+                    level: Level::Error,
+                    span: Some(item.span),
+                    message: format!("bindgen can not handle unit or tuple structs ({})", name),
+                });
+            }
+
+            let fields = transform_struct_fields(variants.fields());
+            let mut properties = Vec::new();
+
+            for field in &fields {
+                let mut ty = rust_to_java(&*field.struct_field().ty, &self.context)?
+                    .unwrap_or_default();
+
+                if let StructField::Array { .. } = *field {
+                    // Detect array ptrs: skip the length args and add array to the type sig
+                    ty.push_str("[]");
+                }
+
+                // Fixed-size arrays must default to their length to pass the
+                // length check of the JNI glue.
+                let len = match field.struct_field().ty.node {
+                    ast::TyKind::Array(_, ref len) => {
+                        Some(self.const_evaluator.eval(len).ok_or_else(|| {
+                            Error {
+                                level: Level::Error,
+                                span: Some(field.struct_field().span),
+                                message: format!(
+                                    "bindgen can not evaluate the length of `{}.{}`",
+                                    name,
+                                    field.name()
+                                ),
+                            }
+                        })?)
+                    }
+                    _ => None,
+                };
+
+                properties.push((field.name().to_camel_case(), java_to_kotlin(&ty), len));
+            }
+
+            let jni = jni::generate_struct(&fields, &orig_name, &name, &self.context);
+            append_output(jni, "jni.rs", outputs);
+
+            self.data_classes.push(DataClass {
+                name: name,
+                docs: docs,
+                properties: properties,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Convert a Rust function declaration into a Kotlin external function.
+    fn parse_fn(&mut self, item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let (no_mangle, docs) = parse_attr(&item.attrs, check_no_mangle, |attr| {
+            retrieve_docstring(attr, "")
+        });
+        // If it's not #[no_mangle] then it can't be called from C.
+        if !no_mangle {
+            return Ok(());
+        }
+
+        let name = item.ident.name.as_str();
+
+        if let ast::ItemKind::Fn(ref fn_decl, _, _, abi, ref generics, _) = item.node {
+            if !common::is_extern(abi) {
+                // If it doesn't have a C ABI it can't be called from C.
+                return Ok(());
+            }
+
+            if generics.is_parameterized() {
+                return Err(Error {
+                    level: Level::Error,
+                    span: Some(item.span),
+                    message: "bindgen can not handle parameterized extern functions".into(),
+                });
+            }
+
+            // The last error is fetched by the JNI glue of the failed functions.
+            if self.context.last_error_function.as_ref().map(String::as_str) == Some(&*name) {
+                return Ok(());
+            }
+
+            let nullable = common::nullable_params(&item.attrs);
+            let out = common::out_params(fn_decl, &item.attrs);
+            transform_native_fn(
+                fn_decl,
+                &docs,
+                &name,
+                &nullable,
+                &out,
+                outputs,
+                &mut self.context,
+            )
+        } else {
+            Err(Error {
+                level: Level::Bug,
+                span: Some(item.span),
+                message: "`parse_fn` called on wrong `Item_`".into(),
+            })
+        }
+    }
+
+    fn finalise_output(&mut self, outputs: &mut Outputs) -> Result<(), Error> {
+        for class in &self.data_classes {
+            let buffer = self.format_data_class(class)?;
+            let _ = outputs.insert(self.context.model_file(&class.name, "kt"), buffer);
+        }
+
+        match outputs.get_mut(&PathBuf::from("jni.rs")) {
+            Some(input) => {
+                input.insert_str(0, &jni::generate_prelude(&self.context));
+                java::format_jni_output(input);
+            }
+            None => {
+                return Err(Error {
+                    level: Level::Error,
+                    span: None,
+                    message: "no jni bindings generated?".to_owned(),
+                })
+            }
+        }
+
+        if let Some(input) = outputs.get_mut(&PathBuf::from("Constants.kt")) {
+            *input = format!("package {}\n\n{}", self.context.namespace, input);
+        }
+
+        let async_file = PathBuf::from(format!("{}.kt", self.async_object_name()));
+        if let Some(input) = outputs.get_mut(&async_file) {
+            let mut imports = vec![
+                "kotlin.coroutines.resume".to_owned(),
+                "kotlin.coroutines.resumeWithException".to_owned(),
+                "kotlin.coroutines.suspendCoroutine".to_owned(),
+                self.context.exception_class.clone(),
+            ];
+            imports.extend(self.context.model_import());
+            self.format_object(input, &self.async_object_name(), &imports);
+        }
+
+        match outputs.get_mut(&PathBuf::from("NativeBindings.kt")) {
+            Some(input) => {
+                let imports: Vec<_> = self.context.model_import().into_iter().collect();
+                self.format_object(input, "NativeBindings", &imports);
+                Ok(())
+            }
+            None => Err(Error {
+                level: Level::Error,
+                span: None,
+                message: "no native bindings generated?".to_owned(),
+            }),
+        }
+    }
+}
+
+/// Transform a Rust FFI function into a Kotlin external function, its callback
+/// interfaces, the JNI glue and, if possible, a `suspend` wrapper.
+fn transform_native_fn(
+    fn_decl: &ast::FnDecl,
+    docs: &str,
+    name: &str,
+    nullable: &[String],
+    out: &[String],
+    outputs: &mut Outputs,
+    context: &mut Context,
+) -> Result<(), Error> {
+    let mut params = Vec::new();
+    let returned_out = java::returned_out_param(fn_decl, out, context);
+
+    let mut fn_args = fn_decl
+        .inputs
+        .iter()
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
+        .peekable();
+
+    while let Some(arg) = fn_args.next() {
+        let arg_name = pprust::pat_to_string(&*arg.pat);
+
+        // Out-parameters are returned or passed in one-element arrays
+        if out.contains(&arg_name) {
+            if returned_out.is_none() {
+                let java_type = format!("{}[]", java::out_java_type(arg, context)?);
+                let kotlin_type = java_to_kotlin(&java_type);
+                params.push(format!("{}: {}", arg_name.to_camel_case(), kotlin_type));
+            }
+            continue;
+        }
+
+        let mut java_type = rust_to_java(&arg.ty, context)?.unwrap_or_default();
+
+        if is_array_arg(&arg, fn_args.peek().cloned(), &context.callbacks) {
+            java_type.push_str("[]");
+            fn_args.next();
+        }
+
+        let mut kotlin_type = java_to_kotlin(&java_type);
+        if nullable.contains(&arg_name) || java::is_nullable_arg(arg) {
+            kotlin_type.push('?');
+        }
+
+        params.push(format!("{}: {}", arg_name.to_camel_case(), kotlin_type));
+
+        // Generate a callback interface - if it wasn't generated already
+        if let Some(bare_fn) = java::callback_ty(&arg.ty) {
+            let cb_class = callback_name(&*bare_fn.decl.inputs, context)?;
+            let cb_file = context.model_file(&cb_class, "kt");
+
+            if let None = outputs.get(&cb_file) {
+                let cb_output = transform_callback(bare_fn, &cb_class, context)?;
+                let _ = outputs.insert(cb_file, cb_output);
+            }
+        }
+    }
+
+    let return_type = match fn_decl.output {
+        ast::FunctionRetTy::Ty(ref ty) if ty.node == ast::TyKind::Never => {
+            return Err(Error {
+                level: Level::Error,
+                span: Some(ty.span),
+                message: "panics across a C boundary are naughty!".into(),
+            });
+        }
+        ast::FunctionRetTy::Ty(ref ty) if !java::returns_error_code(fn_decl, context) => {
+            format!(": {}", java_to_kotlin(&java::returned_java_type(ty, context)?))
+        }
+        _ => {
+            match returned_out {
+                Some(arg) => format!(": {}", java_to_kotlin(&java::out_java_type(arg, context)?)),
+                None => String::new(),
+            }
+        }
+    };
+
+    let kotlin_name = name.to_camel_case();
+
+    let mut buffer = format_docs(docs);
+    buffer.push_str(&format!(
+        "@JvmStatic\nexternal fun {}({}){}\n\n",
+        kotlin_name,
+        params.join(", "),
+        return_type
+    ));
+    append_output(buffer, "NativeBindings.kt", outputs);
+
+    // Coroutines can't be combined with out-parameters
+    if out.is_empty() {
+        if let Some(buffer) = transform_suspend_fn(fn_decl, docs, &kotlin_name, context)? {
+            let async_file = format!("{}.kt", context.lib_name.to_class_case());
+            append_output(buffer, &async_file, outputs);
+        }
+    }
+
+    java::generate_jni_bindings(fn_decl, name, &kotlin_name, nullable, out, outputs, context)
+}
+
+/// Turn a Rust callback function type into a Kotlin functional interface.
+fn transform_callback(
+    bare_fn: &ast::BareFnTy,
+    class_name: &str,
+    context: &Context,
+) -> Result<String, Error> {
+    let mut params = Vec::new();
+
+    let mut args = bare_fn
+        .decl
+        .inputs
+        .iter()
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
+        .peekable();
+
+    while let Some(arg) = args.next() {
+        let arg_name = pprust::pat_to_string(&*arg.pat).to_camel_case();
+        let mut java_type = rust_to_java(&*arg.ty, context)?.unwrap_or_default();
+
+        if is_array_arg(&arg, args.peek().cloned(), &context.callbacks) {
+            // Detect array ptrs: skip the length args and add array to the type sig
+            java_type.push_str("[]");
+            args.next();
+        }
+
+        params.push(format!("{}: {}", arg_name, java_to_kotlin(&java_type)));
+    }
+
+    Ok(format!(
+        "package {namespace}\n\n\
+         fun interface {name} {{\n\
+         \x20   fun call({params})\n\
+         }}\n",
+        namespace = context.namespace_model,
+        name = class_name,
+        params = params.join(", ")
+    ))
+}
+
+/// Transform a Rust FFI function reporting its result through a single
+/// `FfiResult` callback into a Kotlin `suspend` function.
+///
+/// Returns `None` if the function doesn't follow this convention.
+fn transform_suspend_fn(
+    fn_decl: &ast::FnDecl,
+    docs: &str,
+    kotlin_name: &str,
+    context: &Context,
+) -> Result<Option<String>, Error> {
+    let callback = match extract_result_callback(fn_decl, context) {
+        Some(callback) => callback,
+        None => return Ok(None),
+    };
+
+    let mut params = Vec::new();
+    let mut call_args = Vec::new();
+
+    let mut fn_args = fn_decl
+        .inputs
+        .iter()
+        .filter(|arg| !context.callbacks.is_user_data_arg(arg))
+        .peekable();
+
+    while let Some(arg) = fn_args.next() {
+        if java::callback_ty(&arg.ty).is_some() {
+            call_args.push(None);
+            continue;
+        }
+
+        let arg_name = pprust::pat_to_string(&*arg.pat).to_camel_case();
+        let mut java_type = try_some!(rust_to_java(&arg.ty, context));
+
+        if is_array_arg(&arg, fn_args.peek().cloned(), &context.callbacks) {
+            java_type.push_str("[]");
+            fn_args.next();
+        }
+
+        params.push(format!("{}: {}", arg_name, java_to_kotlin(&java_type)));
+        call_args.push(Some(arg_name));
+    }
+
+    // Skip `user_data` and `result` params
+    let mut cb_args = callback
+        .decl
+        .inputs
+        .iter()
+        .filter(|arg| {
+            !context.callbacks.is_user_data_arg(arg) && !context.callbacks.is_result_arg(arg)
+        })
+        .peekable();
+
+    let mut values = Vec::new();
+
+    while let Some(arg) = cb_args.next() {
+        let mut java_type = try_some!(rust_to_java(&*arg.ty, context));
+
+        if is_array_arg(&arg, cb_args.peek().cloned(), &context.callbacks) {
+            java_type.push_str("[]");
+            cb_args.next();
+        }
+
+        values.push(java_to_kotlin(&java_type));
+    }
+
+    let (return_type, names, value) = match values.len() {
+        0 => (String::from("Unit"), Vec::new(), String::from("Unit")),
+        1 => (values[0].clone(), vec!["value"], String::from("value")),
+        _ => {
+            // Multiple values are returned as a `Pair` or a `Triple`
+            let (holder, names) = match values.len() {
+                2 => ("Pair", vec!["first", "second"]),
+                3 => ("Triple", vec!["first", "second", "third"]),
+                _ => return Ok(None),
+            };
+
+            (
+                format!("{}<{}>", holder, values.join(", ")),
+                names.clone(),
+                format!("{}({})", holder, names.join(", ")),
+            )
+        }
+    };
+    let lambda_params = java::result_lambda_params(callback, &names, context).join(", ");
+    let error_code = format!("result.{}", context.callbacks.error_code_field.to_camel_case());
+    let description = format!("result.{}", context.callbacks.description_field.to_camel_case());
+
+    let cb_class = callback_name(&*callback.decl.inputs, context)?;
+    let exception_class = context
+        .exception_class
+        .rsplit('.')
+        .next()
+        .unwrap_or_default();
+
+    let lambda = format!(
+        "{class} {{ {params} ->\n\
+         \x20       if ({code} != 0) {{\n\
+         \x20           continuation.resumeWithException({exception}({code}, {description}))\n\
+         \x20       }} else {{\n\
+         \x20           continuation.resume({value})\n\
+         \x20       }}\n\
+         \x20   }}",
+        class = cb_class,
+        params = lambda_params,
+        code = error_code,
+        description = description,
+        exception = exception_class,
+        value = value,
+    );
+
+    let call_args: Vec<_> = call_args
+        .into_iter()
+        .map(|arg| arg.unwrap_or_else(|| lambda.clone()))
+        .collect();
+
+    let mut buffer = format_docs(docs);
+    buffer.push_str(&format!(
+        "suspend fun {name}({params}): {ty} = suspendCoroutine {{ continuation ->\n\
+         \x20   NativeBindings.{name}({args})\n\
+         }}\n\n",
+        name = kotlin_name,
+        params = params.join(", "),
+        ty = return_type,
+        args = call_args.join(", "),
+    ));
+
+    Ok(Some(buffer))
+}
+
+/// Turn a Rust type into a Kotlin type.
+fn rust_to_kotlin(ty: &ast::Ty, context: &Context) -> Result<Option<String>, Error> {
+    Ok(rust_to_java(ty, context)?.map(|ty| java_to_kotlin(&ty)))
+}
+
+/// Convert a Java type into the corresponding Kotlin type.
+fn java_to_kotlin(ty: &str) -> String {
+    if ty.ends_with("[]") {
+        let elem = &ty[0..ty.len() - 2];
+
+        return match elem {
+            "boolean" => "BooleanArray".to_owned(),
+            "byte" => "ByteArray".to_owned(),
+            "short" => "ShortArray".to_owned(),
+            "int" => "IntArray".to_owned(),
+            "long" => "LongArray".to_owned(),
+            "float" => "FloatArray".to_owned(),
+            "double" => "DoubleArray".to_owned(),
+            elem => format!("Array<{}>", java_to_kotlin(elem)),
+        };
+    }
+
+    match ty {
+        "void" => "Unit",
+        "boolean" => "Boolean",
+        "byte" => "Byte",
+        "short" => "Short",
+        "int" => "Int",
+        "long" => "Long",
+        "float" => "Float",
+        "double" => "Double",
+        ty => ty,
+    }.to_owned()
+}
+
+/// Returns whether the Kotlin type is an array (e.g. `ByteArray` or `Array<Foo>`).
+fn is_array(ty: &str) -> bool {
+    ty.starts_with("Array<") || ty.ends_with("Array")
+}
+
+/// Generate `equals` and `hashCode` of a data class, comparing arrays by their contents.
+fn format_equals(class: &DataClass) -> String {
+    let (comparisons, hashes): (Vec<_>, Vec<_>) = class
+        .properties
+        .iter()
+        .map(|&(ref name, ref ty, _)| if is_array(ty) {
+            (
+                format!("{0}.contentEquals(other.{0})", name),
+                format!("{}.contentHashCode()", name),
+            )
+        } else {
+            (format!("{0} == other.{0}", name), format!("{}.hashCode()", name))
+        })
+        .unzip();
+
+    let mut buffer = String::new();
+    buffer.push_str("override fun equals(other: Any?): Boolean {\n");
+    buffer.push_str("    if (this === other) return true\n");
+    buffer.push_str(&format!("    if (other !is {}) return false\n", class.name));
+    buffer.push_str(&format!("    return {}\n", comparisons.join(" &&\n        ")));
+    buffer.push_str("}\n\n");
+
+    buffer.push_str("override fun hashCode(): Int {\n");
+    buffer.push_str(&format!("    var result = {}\n", hashes[0]));
+    for hash in &hashes[1..] {
+        buffer.push_str(&format!("    result = 31 * result + {}\n", hash));
+    }
+    buffer.push_str("    return result\n");
+    buffer.push_str("}\n");
+
+    buffer
+}
+
+/// Convert a literal constant value into Kotlin.
+fn const_value_to_kotlin(expr: &ast::Expr, ty: &str) -> Option<String> {
+    let lit = match expr.node {
+        ast::ExprKind::Lit(ref lit) => lit,
+        ast::ExprKind::AddrOf(_, ref expr) => return const_value_to_kotlin(expr, ty),
+        ast::ExprKind::Array(ref elements) if ty == "ByteArray" => {
+            let elements: Option<Vec<_>> = elements
+                .iter()
+                .map(|elem| const_value_to_kotlin(elem, "Byte"))
+                .collect();
+
+            return elements.map(|elements| format!("byteArrayOf({})", elements.join(", ")));
+        }
+        _ => return None,
+    };
+
+    let value = match lit.node {
+        ast::LitKind::Bool(value) => format!("{}", value),
+        ast::LitKind::Byte(value) => int_value_to_kotlin(value as i64, ty),
+        ast::LitKind::Int(value, _) => int_value_to_kotlin(value as i64, ty),
+        ast::LitKind::Float(ref value, _) |
+        ast::LitKind::FloatUnsuffixed(ref value) if ty == "Float" => format!("{}f", value),
+        ast::LitKind::Float(ref value, _) |
+        ast::LitKind::FloatUnsuffixed(ref value) => format!("{}", value),
+        ast::LitKind::Str(ref value, ..) => string_to_kotlin(&*value.as_str()),
+        ast::LitKind::ByteStr(ref value) => {
+            let bytes: Vec<_> = value
+                .iter()
+                .map(|byte| int_value_to_kotlin(*byte as i64, "Byte"))
+                .collect();
+            format!("byteArrayOf({})", bytes.join(", "))
+        }
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Quote and escape a string as a Kotlin string literal, including `$` which
+/// would start a string template.
+fn string_to_kotlin(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '$' => result.push_str("\\$"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) >= 0x20 && (c as u32) < 0x7f => result.push(c),
+            c => {
+                let mut buffer = [0; 2];
+                for unit in c.encode_utf16(&mut buffer).iter() {
+                    result.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+
+    result.push('"');
+    result
+}
+
+/// Format an integer constant value of the given Kotlin type. Kotlin integers are signed,
+/// so unsigned values are converted to their two's complement representation.
+fn int_value_to_kotlin(value: i64, ty: &str) -> String {
+    match ty {
+        "Long" => format!("{}L", value),
+        "Byte" => format!("{}", value as u8 as i8),
+        "Short" => format!("{}", value as u16 as i16),
+        "Int" => format!("{}", value as u32 as i32),
+        _ => format!("{}", value),
+    }
+}
+
+/// Turn docstrings into a KDoc comment.
+fn format_docs(docs: &str) -> String {
+    if docs.is_empty() {
+        return String::new();
+    }
+
+    format!("/**\n{} */\n", docs.replace("///", " *"))
+}
+
+/// Indent all non-empty lines by one level.
+fn indent(text: &str) -> String {
+    text.lines().fold(String::new(), |mut s, line| {
+        if !line.is_empty() {
+            s.push_str("    ");
+        }
+        s.push_str(line);
+        s.push_str("\n");
+        s
+    })
+}
+
+fn unsupported_discriminant_error(expr: &ast::Expr) -> Error {
+    Error {
+        level: Level::Error,
+        span: Some(expr.span),
+        message: format!(
+            "bindgen can not handle enum discriminant {}",
+            pprust::expr_to_string(expr)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse;
+    use std::path::Path;
+    use syntax;
+    use syntax::parse::ParseSess;
+
+    fn try_compile(lang: &mut LangKotlin, source: &str) -> Result<Outputs, Vec<Error>> {
+        let session = ParseSess::new();
+        let ast = unwrap!(syntax::parse::parse_crate_from_source_str(
+            "lib.rs".to_owned(),
+            source.to_owned(),
+            &session,
+        ));
+
+        let mut outputs = Outputs::new();
+        parse::parse_mod(lang, &ast.module, &mut outputs)?;
+        lang.finalise_output(&mut outputs).map_err(|error| vec![error])?;
+
+        Ok(outputs)
+    }
+
+    fn compile(source: &str) -> Outputs {
+        unwrap!(try_compile(&mut LangKotlin::new(HashMap::new()), source))
+    }
+
+    fn fetch<'a>(outputs: &'a Outputs, name: &str) -> &'a str {
+        outputs.get(Path::new(name)).map(String::as_str).unwrap_or(
+            "",
+        )
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(java_to_kotlin("long"), "Long");
+        assert_eq!(java_to_kotlin("String"), "String");
+        assert_eq!(java_to_kotlin("byte[]"), "ByteArray");
+        assert_eq!(java_to_kotlin("MDataKey[]"), "Array<MDataKey>");
+
+        let lang = LangKotlin::new(HashMap::new());
+        assert_eq!(unwrap!(lang.default_value("Long", None)), "0L");
+        assert_eq!(unwrap!(lang.default_value("Char", None)), "'\\u0000'");
+        assert_eq!(unwrap!(lang.default_value("ByteArray", None)), "ByteArray(0)");
+        assert_eq!(unwrap!(lang.default_value("ByteArray", Some(32))), "ByteArray(32)");
+        assert_eq!(unwrap!(lang.default_value("Array<MDataKey>", None)), "emptyArray()");
+        assert_eq!(unwrap!(lang.default_value("Array<Long>", Some(4))), "Array(4) { 0L }");
+        assert!(lang.default_value("MDataKey", None).is_none());
+        assert!(lang.default_value("Array<MDataKey>", Some(4)).is_none());
+    }
+
+    #[test]
+    fn structs() {
+        let outputs = compile(
+            "#[repr(C)]
+             pub struct MDataKey {
+                 pub key: [u8; 32],
+                 pub version: u64,
+             }
+
+             #[no_mangle]
+             pub extern \"C\" fn mdata_key_len(key: *const MDataKey) -> usize {}",
+        );
+
+        assert_eq!(
+            fetch(&outputs, "net/maidsafe/model/MDataKey.kt"),
+            "package net.maidsafe.model\n\n\
+             data class MDataKey(\n\
+             \x20   var key: ByteArray = ByteArray(32),\n\
+             \x20   var version: Long = 0L\n\
+             ) {\n\
+             \x20   override fun equals(other: Any?): Boolean {\n\
+             \x20       if (this === other) return true\n\
+             \x20       if (other !is MDataKey) return false\n\
+             \x20       return key.contentEquals(other.key) &&\n\
+             \x20           version == other.version\n\
+             \x20   }\n\n\
+             \x20   override fun hashCode(): Int {\n\
+             \x20       var result = key.contentHashCode()\n\
+             \x20       result = 31 * result + version.hashCode()\n\
+             \x20       return result\n\
+             \x20   }\n\
+             }\n"
+        );
+
+        assert_eq!(
+            fetch(&outputs, "NativeBindings.kt"),
+            "package net.maidsafe.bindings\n\n\
+             import net.maidsafe.model.*\n\n\
+             object NativeBindings {\n\
+             \x20   @JvmStatic\n\
+             \x20   external fun mdataKeyLen(key: MDataKey): Long\n\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn structs_with_fixed_size_arrays() {
+        let outputs = compile(
+            "pub const KEY_COUNT: usize = 2;
+
+             #[repr(C)]
+             pub struct MDataKey {
+                 pub version: u64,
+             }
+
+             #[repr(C)]
+             pub struct MDataKeys {
+                 pub keys: [MDataKey; KEY_COUNT],
+             }
+
+             #[no_mangle]
+             pub extern \"C\" fn mdata_keys_free(keys: *mut MDataKeys) {}",
+        );
+
+        let class = fetch(&outputs, "net/maidsafe/model/MDataKeys.kt");
+        assert!(class.contains("var keys: Array<MDataKey> = Array(2) { MDataKey() }"));
+    }
+
+    #[test]
+    fn enums() {
+        // The enum is declared after the struct using it.
+        let outputs = compile(
+            "#[repr(C)]
+             pub struct Permission {
+                 pub action: Action,
+                 pub allowed: bool,
+             }
+
+             /// Action on mutable data.
+             #[repr(C)]
+             pub enum Action {
+                 Insert,
+                 Update = 4,
+                 Delete,
+             }
+
+             #[no_mangle]
+             pub extern \"C\" fn permission_check(permission: *const Permission) -> bool {}",
+        );
+
+        assert_eq!(
+            fetch(&outputs, "net/maidsafe/model/Action.kt"),
+            "package net.maidsafe.model\n\n\
+             /**\n\
+             \x20* Action on mutable data.\n\
+             \x20*/\n\
+             enum class Action(val value: Int) {\n\
+             \x20   Insert(0),\n\
+             \x20   Update(4),\n\
+             \x20   Delete(5);\n\
+             }\n"
+        );
+
+        assert_eq!(
+            fetch(&outputs, "net/maidsafe/model/Permission.kt"),
+            "package net.maidsafe.model\n\n\
+             data class Permission(\n\
+             \x20   var action: Action = Action.Insert,\n\
+             \x20   var allowed: Boolean = false\n\
+             )\n"
+        );
+    }
+
+    #[test]
+    fn constants() {
+        let outputs = compile(
+            r#"pub const TAG: &'static [u8] = b"safe";
+               pub const GREETING: &'static str = "cost: $5\n";
+               pub const LEN: u64 = 2 * 16;
+               pub const FLAGS: i32 = 1 << 4 | 1;
+               pub const ALL: u32 = !0;
+               pub const LOW_BITS: u16 = !0 as u8 as u16;
+               pub const PI: f32 = 3.14;
+
+               #[no_mangle]
+               pub extern "C" fn entry_free(entry: *mut Entry) {}"#,
+        );
+
+        assert_eq!(
+            fetch(&outputs, "Constants.kt"),
+            "package net.maidsafe.bindings\n\n\
+             val TAG: ByteArray = byteArrayOf(115, 97, 102, 101)\n\n\
+             const val GREETING: String = \"cost: \\$5\\n\"\n\n\
+             const val LEN: Long = 32L\n\n\
+             const val FLAGS: Int = 17\n\n\
+             const val ALL: Int = -1\n\n\
+             const val LOW_BITS: Short = 255\n\n\
+             const val PI: Float = 3.14f\n\n"
+        );
+
+        assert_eq!(string_to_kotlin("say \"hi\"\\"), r#""say \"hi\"\\""#);
+        assert_eq!(
+            string_to_kotlin("caf\u{e9}\u{1}\u{1f600}"),
+            r#""caf\u00e9\u0001\ud83d\ude00""#
+        );
+    }
+
+    #[test]
+    fn model_in_bindings_namespace() {
+        let mut lang = LangKotlin::new(HashMap::new());
+        lang.set_model_namespace("net.maidsafe.bindings");
+
+        let outputs = unwrap!(try_compile(
+            &mut lang,
+            "#[no_mangle]
+             pub extern \"C\" fn entry_free(entry: *mut Entry) {}",
+        ));
+
+        assert_eq!(
+            fetch(&outputs, "NativeBindings.kt"),
+            "package net.maidsafe.bindings\n\n\
+             object NativeBindings {\n\
+             \x20   @JvmStatic\n\
+             \x20   external fun entryFree(entry: Entry)\n\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn properties_without_default_values() {
+        let mut lang = LangKotlin::new(HashMap::new());
+        let result = try_compile(
+            &mut lang,
+            "#[repr(C)]
+             pub struct Entry {
+                 pub value: Unknown,
+             }
+
+             #[no_mangle]
+             pub extern \"C\" fn entry_free(entry: *mut Entry) {}",
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn callbacks_and_suspend_functions() {
+        let outputs = compile(
+            "#[no_mangle]
+             pub extern \"C\" fn mdata_version(id: u64,
+                                                user_data: *mut c_void,
+                                                o_cb: extern \"C\" fn(user_data: *mut c_void,
+                                                                      result: *const FfiResult,
+                                                                      version: u64)) {}",
+        );
+
+        assert_eq!(
+            fetch(&outputs, "net/maidsafe/model/CallbackResultLong.kt"),
+            "package net.maidsafe.model\n\n\
+             fun interface CallbackResultLong {\n\
+             \x20   fun call(result: FfiResult, version: Long)\n\
+             }\n"
+        );
+
+        assert_eq!(
+            fetch(&outputs, "NativeBindings.kt"),
+            "package net.maidsafe.bindings\n\n\
+             import net.maidsafe.model.*\n\n\
+             object NativeBindings {\n\
+             \x20   @JvmStatic\n\
+             \x20   external fun mdataVersion(id: Long, oCb: CallbackResultLong)\n\n\
+             }\n"
+        );
+
+        assert_eq!(
+            fetch(&outputs, "Backend.kt"),
+            "package net.maidsafe.bindings\n\n\
+             import kotlin.coroutines.resume\n\
+             import kotlin.coroutines.resumeWithException\n\
+             import kotlin.coroutines.suspendCoroutine\n\
+             import net.maidsafe.FfiException\n\
+             import net.maidsafe.model.*\n\n\
+             object Backend {\n\
+             \x20   suspend fun mdataVersion(id: Long): Long = \
+             suspendCoroutine { continuation ->\n\
+             \x20       NativeBindings.mdataVersion(id, CallbackResultLong { result, value ->\n\
+             \x20           if (result.errorCode != 0) {\n\
+             \x20               continuation.resumeWithException(\
+             FfiException(result.errorCode, result.description))\n\
+             \x20           } else {\n\
+             \x20               continuation.resume(value)\n\
+             \x20           }\n\
+             \x20       })\n\
+             \x20   }\n\n\
+             }\n"
+        );
+    }
+}
@@ -25,6 +25,38 @@ pub struct LangC;
 
 
 impl Lang for LangC {
+    /// Convert a Rust byte-string or byte-array constant into a C array.
+    ///
+    /// `pub const TAG: &'static [u8] = b"safe";` becomes
+    /// `static const uint8_t TAG[] = { 0x73, 0x61, 0x66, 0x65 };`. Other constants are skipped.
+    fn parse_const(item: &ast::Item, outputs: &mut Outputs) -> Result<(), Error> {
+        let (_, docs) = parse_attr(&item.attrs, |_| true, |attr| retrieve_docstring(attr, ""));
+
+        let name = item.ident.name.as_str();
+        let array = match item.node {
+            ast::ItemKind::Const(_, ref expr) => {
+                match byte_array_to_c(&name, expr) {
+                    Some(array) => array,
+                    None => return Ok(None),
+                }
+            }
+            _ => {
+                return Err(Error {
+                    level: Level::Bug,
+                    span: Some(item.span),
+                    message: "`parse_const` called on wrong `Item_`".into(),
+                });
+            }
+        };
+
+        let mut buffer = String::new();
+        buffer.push_str(&docs);
+        buffer.push_str(&array);
+        buffer.push_str("\n\n");
+
+        Ok(Some(buffer))
+    }
+
     /// Convert `pub type A = B;` into `typedef B A;`.
     ///
     /// Aborts if A is generic.
@@ -295,6 +327,40 @@ pub fn transform_native_fn(
     Ok(Some(output))
 }
 
+/// Turn a byte-string (`b"safe"`) or byte-array (`&[0xca, 0xfe]`) constant into
+/// a `static const uint8_t` array definition.
+fn byte_array_to_c(name: &str, expr: &ast::Expr) -> Option<String> {
+    let bytes: Vec<u8> = match expr.node {
+        ast::ExprKind::AddrOf(_, ref expr) => return byte_array_to_c(name, expr),
+        ast::ExprKind::Lit(ref lit) => {
+            match lit.node {
+                ast::LitKind::ByteStr(ref bytes) => (**bytes).clone(),
+                _ => return None,
+            }
+        }
+        ast::ExprKind::Array(ref elements) => {
+            let mut bytes = Vec::with_capacity(elements.len());
+            for element in elements {
+                match element.node {
+                    ast::ExprKind::Lit(ref lit) => {
+                        match lit.node {
+                            ast::LitKind::Byte(value) => bytes.push(value),
+                            ast::LitKind::Int(value, _) if value <= 0xff => bytes.push(value as u8),
+                            _ => return None,
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            bytes
+        }
+        _ => return None,
+    };
+
+    let bytes: Vec<_> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+    Some(format!("static const uint8_t {}[] = {{ {} }};", name, bytes.join(", ")))
+}
+
 /// Turn a Rust type with an associated name or type into a C type.
 pub fn rust_to_c(ty: &ast::Ty, assoc: &str) -> Result<Option<String>, Error> {
     match ty.node {
@@ -576,6 +642,40 @@ mod test {
         }
     }
 
+    fn expr(source: &str) -> ::syntax::ast::Expr {
+        let sess = ::syntax::parse::ParseSess::new();
+        let result = {
+            let mut parser =
+                ::syntax::parse::new_parser_from_source_str(&sess, "".into(), source.into());
+            parser.parse_expr()
+        };
+
+        match result {
+            Ok(p) => (*p).clone(),
+            _ => {
+                panic!(
+                    "internal testing error: could not parse expression from {:?}",
+                    source
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn byte_array_constants() {
+        assert_eq!(
+            super::byte_array_to_c("TAG", &expr("b\"safe\"")),
+            Some("static const uint8_t TAG[] = { 0x73, 0x61, 0x66, 0x65 };".into())
+        );
+        assert_eq!(
+            super::byte_array_to_c("MAGIC", &expr("&[0xca, b'x', 254]")),
+            Some("static const uint8_t MAGIC[] = { 0xca, 0x78, 0xfe };".into())
+        );
+        assert_eq!(super::byte_array_to_c("LEN", &expr("32")), None);
+        assert_eq!(super::byte_array_to_c("NAME", &expr("\"safe\"")), None);
+        assert_eq!(super::byte_array_to_c("BIG", &expr("&[256]")), None);
+    }
+
     // TODO: do a check for genericness at the top of rust_to_c
     // rust_to_c_abort_generic
     // rust_to_c_fail_generic
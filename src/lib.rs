@@ -32,10 +32,11 @@ extern crate indoc;
 extern crate unwrap;
 
 use common::{Lang, Outputs};
-pub use common::FilterMode;
-pub use csharp::LangCSharp;
+pub use common::{CallbackConvention, CancelConvention, FilterMode};
+pub use csharp::{LangCSharp, Platform, StringEncoding};
 pub use errors::Level;
 pub use java::LangJava;
+pub use kotlin::LangKotlin;
 use std::collections::HashMap;
 use std::convert;
 use std::fmt::Display;
@@ -53,9 +54,11 @@ macro_rules! try_some {
 }
 
 mod common;
+mod const_eval;
 // mod lang_c;
 mod csharp;
 mod java;
+mod kotlin;
 mod output;
 mod parse;
 mod struct_field;
@@ -256,6 +259,7 @@ impl Bindgen {
 
         // Parse the top level mod.
         let krate = syntax::parse::parse_crate_from_file(&self.input, &self.session).unwrap();
+        lang.set_current_module(&[]);
         parse::parse_mod(lang, &krate.module, outputs)?;
 
         // Parse other mods.
@@ -276,6 +280,7 @@ impl Bindgen {
             eprintln!("Parsing {:?}", mod_path);
 
             let krate = syntax::parse::parse_crate_from_file(&mod_path, &self.session).unwrap();
+            lang.set_current_module(&module);
             parse::parse_mod(lang, &krate.module, outputs)?;
 
             // TODO: insert custom_code to each module?